@@ -2,7 +2,6 @@ use std::vec;
 
 use llm_primitives::Model;
 use llm_primitives::OpenAIModel;
-use tokio;
 
 #[tokio::main]
 async fn main() {
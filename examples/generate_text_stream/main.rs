@@ -0,0 +1,35 @@
+use std::io::Write;
+
+use futures::StreamExt;
+use llm_primitives::Model;
+use llm_primitives::OpenAIModel;
+
+#[tokio::main]
+async fn main() {
+    let model = OpenAIModel::new(String::from("gpt-4o"));
+    let response = model
+        .generate_text_stream(
+            String::from("Respond to the user"),
+            String::from("User: Hello, how are you?"),
+        )
+        .await;
+    let mut stream = match response {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!("{:?}", e);
+            return;
+        }
+    };
+    while let Some(delta) = stream.next().await {
+        match delta {
+            Ok(delta) => {
+                print!("{}", delta.text);
+                std::io::stdout().flush().unwrap();
+                if let Some(finish_reason) = delta.finish_reason {
+                    println!("\n\nfinish reason: {}", finish_reason);
+                }
+            }
+            Err(e) => println!("{:?}", e),
+        }
+    }
+}
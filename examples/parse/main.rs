@@ -2,7 +2,6 @@ use llm_primitives::Model;
 use llm_primitives::OpenAIModel;
 use schemars::JsonSchema;
 use serde::Deserialize;
-use tokio;
 
 #[derive(JsonSchema, Deserialize, Debug)]
 struct Address {
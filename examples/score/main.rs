@@ -1,6 +1,5 @@
 use llm_primitives::Model;
 use llm_primitives::OpenAIModel;
-use tokio;
 
 #[tokio::main]
 async fn main() {
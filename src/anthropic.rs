@@ -0,0 +1,519 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+use crate::config::{
+    common_builder_methods, credential_builder_methods, CommonConfig, ModelConfig,
+};
+use crate::http::with_timeout;
+use crate::stream::{text_stream, Framing, StreamState};
+use crate::{
+    decode_json, extract_json_object, primitives, CallOptions, ChatModel, CostTracker,
+    CredentialProvider, Error, GenerateMessageOptions, Generation, Message, MessageRole, Model,
+    Response, TextStream, Usage,
+};
+
+pub const ANTHROPIC_API_KEY_NAME: &str = "ANTHROPIC_API_KEY";
+pub const ANTHROPIC_API_BASE: &str = "api.anthropic.com/v1";
+pub const ANTHROPIC_API_MESSAGES_ENDPOINT: &str = "/messages";
+pub const ANTHROPIC_API_VERSION: &str = "2023-06-01";
+
+const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Prefilled into the assistant turn when JSON is required. The Messages API
+/// has no `response_format`, so starting the reply with an open brace is the
+/// most reliable way to keep the model from answering in prose.
+const JSON_PREFILL: &str = "{";
+
+/// The `stop_reason` of a reply the model declined to give for safety reasons.
+const REFUSAL_STOP_REASON: &str = "refusal";
+
+pub struct AnthropicModel {
+    model: String,
+    credentials: CredentialProvider,
+    base_url: String,
+    max_tokens: u32,
+    config: ModelConfig,
+}
+
+pub struct AnthropicModelBuilder {
+    model: String,
+    base_url: String,
+    max_tokens: u32,
+    common: CommonConfig,
+}
+
+impl AnthropicModelBuilder {
+    pub fn new(model: String) -> Self {
+        AnthropicModelBuilder {
+            model,
+            base_url: format!("https://{}", ANTHROPIC_API_BASE),
+            max_tokens: DEFAULT_MAX_TOKENS,
+            common: CommonConfig::new(),
+        }
+    }
+
+    credential_builder_methods!("ANTHROPIC_API_KEY");
+
+    /// Full base URL including the scheme, e.g. `http://localhost:8080/v1`.
+    pub fn base_url(&mut self, base_url: String) -> &mut Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn max_tokens(&mut self, max_tokens: u32) -> &mut Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    common_builder_methods!();
+
+    /// Builds the model.
+    ///
+    /// # Panics
+    ///
+    /// If the credentials are known to be unavailable or the HTTP client cannot
+    /// be created; see `try_build`.
+    pub fn build(&self) -> AnthropicModel {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds the model, or fails with `Error::Credentials` if the API key
+    /// comes from an environment variable that is not set or a file that
+    /// cannot be read, or with `Error::Transport` if the HTTP client cannot be
+    /// created.
+    pub fn try_build(&self) -> Result<AnthropicModel, Error> {
+        let credentials = self.common.credentials(ANTHROPIC_API_KEY_NAME)?;
+        Ok(AnthropicModel {
+            model: self.model.clone(),
+            credentials,
+            base_url: self.base_url.clone(),
+            max_tokens: self.max_tokens,
+            config: self.common.build()?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct AnthropicMessage {
+    role: MessageRole,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct AnthropicRequestBody {
+    model: String,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage>,
+    temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<AnthropicMetadata>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct AnthropicMetadata {
+    user_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicResponse {
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    model: Option<String>,
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// The subset of streaming events that carry text, usage or errors.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: StreamMessage,
+    },
+    ContentBlockDelta {
+        delta: StreamDelta,
+    },
+    MessageDelta {
+        delta: StreamMessageDelta,
+        usage: Option<AnthropicUsage>,
+    },
+    MessageStop,
+    Error {
+        error: StreamError,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamMessage {
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamDelta {
+    TextDelta {
+        text: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamMessageDelta {
+    stop_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AnthropicUsage {
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+    cache_creation_input_tokens: Option<u64>,
+    cache_read_input_tokens: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamError {
+    r#type: String,
+}
+
+impl AnthropicModel {
+    /// Creates a model that reads its API key from `ANTHROPIC_API_KEY`.
+    ///
+    /// # Panics
+    ///
+    /// If `ANTHROPIC_API_KEY` is not set; `try_new` returns an error instead.
+    pub fn new(model: String) -> Self {
+        AnthropicModelBuilder::new(model).build()
+    }
+
+    /// Like `new`, but fails with `Error::Credentials` if `ANTHROPIC_API_KEY` is
+    /// not set.
+    pub fn try_new(model: String) -> Result<Self, Error> {
+        AnthropicModelBuilder::new(model).try_build()
+    }
+
+    pub fn with_api_key(model: String, api_key: String) -> Self {
+        AnthropicModelBuilder::new(model).api_key(api_key).build()
+    }
+
+    /// Creates a model that sends its requests with an existing client.
+    pub fn with_client(model: String, client: reqwest::Client) -> Self {
+        AnthropicModelBuilder::new(model).client(client).build()
+    }
+}
+
+impl AnthropicModel {
+    async fn messages_request(
+        &self,
+        messages: Vec<Message>,
+        options: &GenerateMessageOptions,
+        stream: bool,
+    ) -> Result<reqwest::RequestBuilder, Error> {
+        let api_key = self.credentials.fetch().await?;
+        let url = format!("{}{}", self.base_url, ANTHROPIC_API_MESSAGES_ENDPOINT);
+        let mut system_prompts = vec![];
+        let mut anthropic_messages = vec![];
+        for message in messages {
+            match message.role {
+                MessageRole::System => system_prompts.push(message.content),
+                MessageRole::User | MessageRole::Assistant => {
+                    anthropic_messages.push(AnthropicMessage {
+                        role: message.role,
+                        content: message.content,
+                    })
+                }
+            }
+        }
+        if options.force_json {
+            anthropic_messages.push(AnthropicMessage {
+                role: MessageRole::Assistant,
+                content: String::from(JSON_PREFILL),
+            });
+        }
+        let body = AnthropicRequestBody {
+            model: self.model.clone(),
+            max_tokens: options.max_tokens.unwrap_or(self.max_tokens),
+            system: if system_prompts.is_empty() {
+                None
+            } else {
+                Some(system_prompts.join("\n\n"))
+            },
+            messages: anthropic_messages,
+            temperature: options.temperature,
+            top_p: options.top_p,
+            stop_sequences: options.stop.clone(),
+            metadata: options
+                .user
+                .clone()
+                .map(|user_id| AnthropicMetadata { user_id }),
+            stream,
+        };
+        let request = self
+            .config
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("x-api-key", api_key)
+            .header("anthropic-version", ANTHROPIC_API_VERSION)
+            .json(&body);
+        Ok(with_timeout(request, options.timeout))
+    }
+}
+
+fn decode_stream_event(payload: &str, state: &mut StreamState) -> Result<Option<String>, Error> {
+    match decode_json::<StreamEvent>(payload.to_string())? {
+        StreamEvent::MessageStart { message } => {
+            if let Some(usage) = message.usage {
+                record_usage(state.usage.get_or_insert_with(Usage::default), &usage);
+            }
+            Ok(None)
+        }
+        StreamEvent::ContentBlockDelta {
+            delta: StreamDelta::TextDelta { text },
+        } => Ok(Some(text)),
+        StreamEvent::MessageDelta { delta, usage } => {
+            if delta.stop_reason.as_deref() == Some(REFUSAL_STOP_REASON) {
+                return Err(Error::ContentFiltered {
+                    reason: REFUSAL_STOP_REASON.to_string(),
+                });
+            }
+            if delta.stop_reason.is_some() {
+                state.finish_reason = delta.stop_reason;
+            }
+            if let Some(usage) = usage {
+                record_usage(state.usage.get_or_insert_with(Usage::default), &usage);
+            }
+            Ok(None)
+        }
+        StreamEvent::MessageStop => {
+            state.done = true;
+            Ok(None)
+        }
+        // Errors after the stream has started arrive as events rather than
+        // statuses, so map them onto the status the same error would have had.
+        StreamEvent::Error { error } => Err(match error.r#type.as_str() {
+            "rate_limit_error" => Error::RateLimited {
+                retry_after: None,
+                body: payload.to_string(),
+            },
+            "overloaded_error" => Error::Http {
+                status: 529,
+                body: payload.to_string(),
+            },
+            _ => Error::Http {
+                status: 500,
+                body: payload.to_string(),
+            },
+        }),
+        StreamEvent::ContentBlockDelta { .. } | StreamEvent::Other => Ok(None),
+    }
+}
+
+/// Folds reported counts into `total`. `input_tokens` excludes cache reads and
+/// writes, which are added back so that `Usage::input_tokens` is the total.
+fn record_usage(total: &mut Usage, usage: &AnthropicUsage) {
+    if let Some(input_tokens) = usage.input_tokens {
+        let cache_read_input_tokens = usage.cache_read_input_tokens.unwrap_or(0);
+        total.input_tokens =
+            input_tokens + usage.cache_creation_input_tokens.unwrap_or(0) + cache_read_input_tokens;
+        total.cached_input_tokens = cache_read_input_tokens;
+    }
+    if let Some(output_tokens) = usage.output_tokens {
+        total.output_tokens = output_tokens;
+    }
+}
+
+impl fmt::Debug for AnthropicModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AnthropicModel")
+            .field("model", &self.model)
+            .field("base_url", &self.base_url)
+            .field("credentials", &self.credentials)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ChatModel for AnthropicModel {
+    async fn generate_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<Generation, Error> {
+        let response = self
+            .config
+            .retry_policy
+            .send(self.messages_request(messages, &options, false).await?)
+            .await?;
+        let headers = response.headers().clone();
+        let anthropic_response = decode_json::<AnthropicResponse>(response.text().await?)?;
+        if anthropic_response.stop_reason.as_deref() == Some(REFUSAL_STOP_REASON) {
+            return Err(Error::ContentFiltered {
+                reason: REFUSAL_STOP_REASON.to_string(),
+            });
+        }
+        let mut content: String = anthropic_response
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text),
+                ContentBlock::Other => None,
+            })
+            .collect();
+        let obj = if options.force_json {
+            content.insert_str(0, JSON_PREFILL);
+            Some(extract_json_object(&content)?)
+        } else {
+            None
+        };
+        Ok(Generation {
+            message: Message {
+                role: MessageRole::Assistant,
+                content,
+                obj,
+            },
+            usage: anthropic_response.usage.map(|reported| {
+                let mut usage = Usage::default();
+                record_usage(&mut usage, &reported);
+                usage
+            }),
+            model: anthropic_response.model,
+            headers,
+            alternatives: vec![],
+        })
+    }
+
+    fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    fn model_name(&self) -> &str {
+        &self.model
+    }
+
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn stream_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<TextStream, Error> {
+        let response = self
+            .config
+            .retry_policy
+            .send(self.messages_request(messages, &options, true).await?)
+            .await?;
+        Ok(text_stream(
+            response,
+            Framing::ServerSentEvents,
+            decode_stream_event,
+        ))
+    }
+}
+
+impl Model for AnthropicModel {
+    fn cost_tracker(&self) -> &CostTracker {
+        &self.config.cost_tracker
+    }
+
+    async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        primitives::classify(self, instruction, text, choices, options).await
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        primitives::binary_classify(self, instruction, text, options).await
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        primitives::generate_text(self, instruction, text, options).await
+    }
+
+    async fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        primitives::generate_text_stream(self, instruction, text, options).await
+    }
+
+    async fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        primitives::score_float(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        primitives::score_int(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        primitives::parse(self, text, options).await
+    }
+
+    async fn parse_value_with_options(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        primitives::parse_value(self, text, schema, options).await
+    }
+}
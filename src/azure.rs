@@ -0,0 +1,359 @@
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+
+use crate::config::{common_builder_methods, CommonConfig, ModelConfig};
+use crate::http::with_timeout;
+use crate::openai::{send_chat_request, send_chat_stream, ChatRequestBody};
+use crate::{
+    primitives, vote, CallOptions, ChatModel, ClassifyVote, CostTracker, CredentialProvider, Error,
+    GenerateMessageOptions, Generation, Message, Model, Response, ScoreVote, TextStream,
+    VoteOptions,
+};
+
+pub const AZURE_OPENAI_API_KEY_NAME: &str = "AZURE_OPENAI_API_KEY";
+pub const AZURE_OPENAI_API_VERSION: &str = "2024-06-01";
+
+/// The first API version that accepts `stream_options`.
+const STREAM_USAGE_API_VERSION: &str = "2024-09-01";
+
+#[derive(Debug, Clone)]
+enum AzureAuth {
+    /// Sent in the `api-key` header.
+    ApiKey(CredentialProvider),
+    /// A Microsoft Entra ID access token, sent as a bearer token.
+    Token(CredentialProvider),
+}
+
+pub struct AzureOpenAIModel {
+    deployment: String,
+    endpoint: String,
+    api_version: String,
+    auth: AzureAuth,
+    stream_usage: bool,
+    config: ModelConfig,
+}
+
+pub struct AzureOpenAIModelBuilder {
+    deployment: String,
+    endpoint: String,
+    api_version: String,
+    auth: Option<AzureAuth>,
+    stream_usage: Option<bool>,
+    common: CommonConfig,
+}
+
+impl AzureOpenAIModelBuilder {
+    /// Targets `https://{resource}.openai.azure.com` and the given deployment.
+    pub fn new(resource: String, deployment: String) -> Self {
+        AzureOpenAIModelBuilder {
+            deployment,
+            endpoint: format!("https://{}.openai.azure.com", resource),
+            api_version: String::from(AZURE_OPENAI_API_VERSION),
+            auth: None,
+            stream_usage: None,
+            common: CommonConfig::new(),
+        }
+    }
+
+    /// Overrides the resource endpoint, e.g. for a custom domain or a local mock.
+    pub fn endpoint(&mut self, endpoint: String) -> &mut Self {
+        self.endpoint = endpoint.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn api_version(&mut self, api_version: String) -> &mut Self {
+        self.api_version = api_version;
+        self
+    }
+
+    /// Whether streams ask for a final chunk with token counts, so that they
+    /// report usage and cost. By default they do if the API version is
+    /// 2024-09-01 or later; earlier versions reject the option.
+    pub fn stream_usage(&mut self, stream_usage: bool) -> &mut Self {
+        self.stream_usage = Some(stream_usage);
+        self
+    }
+
+    /// Authenticates with the `api-key` header instead of reading
+    /// `AZURE_OPENAI_API_KEY` from the environment.
+    pub fn api_key(&mut self, api_key: String) -> &mut Self {
+        self.auth = Some(AzureAuth::ApiKey(CredentialProvider::fixed(api_key)));
+        self
+    }
+
+    /// Authenticates with the `api-key` header, looking the key up with
+    /// `credentials`.
+    pub fn credentials(&mut self, credentials: CredentialProvider) -> &mut Self {
+        self.auth = Some(AzureAuth::ApiKey(credentials));
+        self
+    }
+
+    /// Authenticates with a Microsoft Entra ID access token for the Cognitive
+    /// Services scope, looked up with `credentials`. Tokens expire, so this is
+    /// usually a `CredentialProvider::rotating` that fetches a new one in time.
+    pub fn token_provider(&mut self, credentials: CredentialProvider) -> &mut Self {
+        self.auth = Some(AzureAuth::Token(credentials));
+        self
+    }
+
+    common_builder_methods!();
+
+    /// Builds the model.
+    ///
+    /// # Panics
+    ///
+    /// If the credentials are known to be unavailable or the HTTP client cannot
+    /// be created; see `try_build`.
+    pub fn build(&self) -> AzureOpenAIModel {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds the model, or fails with `Error::Credentials` if the API key
+    /// comes from an environment variable that is not set or a file that
+    /// cannot be read, or with `Error::Transport` if the HTTP client cannot be
+    /// created.
+    pub fn try_build(&self) -> Result<AzureOpenAIModel, Error> {
+        let auth = match self.auth.clone() {
+            Some(AzureAuth::ApiKey(credentials)) => {
+                AzureAuth::ApiKey(self.common.checked_credentials(credentials)?)
+            }
+            Some(AzureAuth::Token(credentials)) => {
+                AzureAuth::Token(self.common.checked_credentials(credentials)?)
+            }
+            None => AzureAuth::ApiKey(self.common.credentials(AZURE_OPENAI_API_KEY_NAME)?),
+        };
+        Ok(AzureOpenAIModel {
+            deployment: self.deployment.clone(),
+            endpoint: self.endpoint.clone(),
+            api_version: self.api_version.clone(),
+            auth,
+            stream_usage: self
+                .stream_usage
+                .unwrap_or(self.api_version.as_str() >= STREAM_USAGE_API_VERSION),
+            config: self.common.build()?,
+        })
+    }
+}
+
+impl AzureOpenAIModel {
+    /// Creates a model that reads its API key from `AZURE_OPENAI_API_KEY`.
+    ///
+    /// # Panics
+    ///
+    /// If `AZURE_OPENAI_API_KEY` is not set; `try_new` returns an error
+    /// instead.
+    pub fn new(resource: String, deployment: String) -> Self {
+        AzureOpenAIModelBuilder::new(resource, deployment).build()
+    }
+
+    /// Like `new`, but fails with `Error::Credentials` if
+    /// `AZURE_OPENAI_API_KEY` is not set.
+    pub fn try_new(resource: String, deployment: String) -> Result<Self, Error> {
+        AzureOpenAIModelBuilder::new(resource, deployment).try_build()
+    }
+
+    pub fn with_api_key(resource: String, deployment: String, api_key: String) -> Self {
+        AzureOpenAIModelBuilder::new(resource, deployment)
+            .api_key(api_key)
+            .build()
+    }
+
+    /// Creates a model that sends its requests with an existing client.
+    pub fn with_client(resource: String, deployment: String, client: reqwest::Client) -> Self {
+        AzureOpenAIModelBuilder::new(resource, deployment)
+            .client(client)
+            .build()
+    }
+}
+
+impl AzureOpenAIModel {
+    async fn chat_request(&self) -> Result<reqwest::RequestBuilder, Error> {
+        let url = format!(
+            "{}/openai/deployments/{}/chat/completions",
+            self.endpoint, self.deployment
+        );
+        let request = self
+            .config
+            .client
+            .post(url)
+            .query(&[("api-version", &self.api_version)]);
+        Ok(match &self.auth {
+            AzureAuth::ApiKey(credentials) => request.header("api-key", credentials.fetch().await?),
+            AzureAuth::Token(credentials) => request.header(
+                "Authorization",
+                format!("Bearer {}", credentials.fetch().await?),
+            ),
+        })
+    }
+}
+
+impl fmt::Debug for AzureOpenAIModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("AzureOpenAIModel")
+            .field("deployment", &self.deployment)
+            .field("endpoint", &self.endpoint)
+            .field("api_version", &self.api_version)
+            .field("auth", &self.auth)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ChatModel for AzureOpenAIModel {
+    async fn generate_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<Generation, Error> {
+        let body = ChatRequestBody::new(self.deployment.clone(), messages, &options);
+        let request = with_timeout(self.chat_request().await?, options.timeout);
+        send_chat_request(
+            request,
+            &body,
+            options.force_json,
+            &self.config.retry_policy,
+        )
+        .await
+    }
+
+    fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    fn model_name(&self) -> &str {
+        &self.deployment
+    }
+
+    fn base_url(&self) -> &str {
+        &self.endpoint
+    }
+
+    async fn stream_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<TextStream, Error> {
+        let body = ChatRequestBody::new(self.deployment.clone(), messages, &options)
+            .streaming(self.stream_usage);
+        let request = with_timeout(self.chat_request().await?, options.timeout);
+        send_chat_stream(request, &body, &self.config.retry_policy).await
+    }
+}
+
+impl Model for AzureOpenAIModel {
+    fn cost_tracker(&self) -> &CostTracker {
+        &self.config.cost_tracker
+    }
+
+    async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        primitives::classify(self, instruction, text, choices, options).await
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        primitives::binary_classify(self, instruction, text, options).await
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        primitives::generate_text(self, instruction, text, options).await
+    }
+
+    async fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        primitives::generate_text_stream(self, instruction, text, options).await
+    }
+
+    async fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        primitives::score_float(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        primitives::score_int(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        primitives::parse(self, text, options).await
+    }
+
+    async fn parse_value_with_options(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        primitives::parse_value(self, text, schema, options).await
+    }
+
+    async fn classify_vote(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &VoteOptions,
+    ) -> Result<Response<ClassifyVote>, Error> {
+        vote::classify(self, instruction, text, choices, options).await
+    }
+
+    async fn score_float_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<f64>>, Error> {
+        vote::score_float(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn score_int_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<i64>>, Error> {
+        vote::score_int(self, instruction, text, min_bound, max_bound, options).await
+    }
+}
@@ -0,0 +1,193 @@
+use futures::{stream, Future, StreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::Notify;
+
+use crate::{CallOptions, Error};
+
+const DEFAULT_CONCURRENCY: usize = 8;
+
+/// How far a batch has got, passed to the `on_progress` hook after every item.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchProgress {
+    /// Position of the item that just finished in the input.
+    pub index: usize,
+    /// Items finished so far, including failed ones.
+    pub completed: usize,
+    pub failed: usize,
+    pub total: usize,
+}
+
+pub type ProgressHook = Arc<dyn Fn(&BatchProgress) + Send + Sync>;
+
+/// Stops a running batch. Items that have not finished yet fail with
+/// `Error::Cancelled`; requests in flight are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    inner: Arc<CancelTokenInner>,
+}
+
+#[derive(Debug, Default)]
+struct CancelTokenInner {
+    cancelled: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    async fn cancelled(&self) {
+        loop {
+            // Register before checking so a `cancel` in between is not missed.
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Settings for the `*_many` methods on `Model`.
+#[derive(Clone)]
+pub struct BatchOptions {
+    concurrency: usize,
+    call_options: CallOptions,
+    on_progress: Option<ProgressHook>,
+    cancel: Option<CancelToken>,
+}
+
+pub struct BatchOptionsBuilder {
+    concurrency: usize,
+    call_options: CallOptions,
+    on_progress: Option<ProgressHook>,
+    cancel: Option<CancelToken>,
+}
+
+impl BatchOptionsBuilder {
+    pub fn new() -> Self {
+        BatchOptionsBuilder {
+            concurrency: DEFAULT_CONCURRENCY,
+            call_options: CallOptions::default(),
+            on_progress: None,
+            cancel: None,
+        }
+    }
+
+    /// Maximum number of items in flight at once. Defaults to 8.
+    pub fn concurrency(&mut self, concurrency: usize) -> &mut Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Options applied to every item.
+    pub fn call_options(&mut self, call_options: CallOptions) -> &mut Self {
+        self.call_options = call_options;
+        self
+    }
+
+    /// Called after every item, successful or not.
+    pub fn on_progress<F>(&mut self, on_progress: F) -> &mut Self
+    where
+        F: Fn(&BatchProgress) + Send + Sync + 'static,
+    {
+        self.on_progress = Some(Arc::new(on_progress));
+        self
+    }
+
+    pub fn cancel_token(&mut self, cancel: CancelToken) -> &mut Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    pub fn build(&self) -> BatchOptions {
+        BatchOptions {
+            concurrency: self.concurrency,
+            call_options: self.call_options.clone(),
+            on_progress: self.on_progress.clone(),
+            cancel: self.cancel.clone(),
+        }
+    }
+}
+
+impl Default for BatchOptionsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        BatchOptionsBuilder::new().build()
+    }
+}
+
+impl BatchOptions {
+    pub(crate) fn call_options(&self) -> &CallOptions {
+        &self.call_options
+    }
+}
+
+/// Runs `call` on every input with at most `options.concurrency` in flight and
+/// returns the results in input order. A failed item does not stop the others.
+pub(crate) async fn run<I, T, F, Fut>(
+    inputs: Vec<I>,
+    options: &BatchOptions,
+    call: F,
+) -> Vec<Result<T, Error>>
+where
+    F: Fn(I) -> Fut,
+    Fut: Future<Output = Result<T, Error>>,
+{
+    let total = inputs.len();
+    let mut results: Vec<Option<Result<T, Error>>> = (0..total).map(|_| None).collect();
+    let mut completed = 0;
+    let mut failed = 0;
+    let cancel = options.cancel.as_ref();
+    let mut finished = stream::iter(inputs.into_iter().enumerate())
+        .map(|(index, input)| {
+            let call = call(input);
+            async move {
+                let result = match cancel {
+                    Some(cancel) if cancel.is_cancelled() => Err(Error::Cancelled),
+                    Some(cancel) => tokio::select! {
+                        result = call => result,
+                        _ = cancel.cancelled() => Err(Error::Cancelled),
+                    },
+                    None => call.await,
+                };
+                (index, result)
+            }
+        })
+        .buffer_unordered(options.concurrency);
+    while let Some((index, result)) = finished.next().await {
+        completed += 1;
+        if result.is_err() {
+            failed += 1;
+        }
+        if let Some(on_progress) = &options.on_progress {
+            on_progress(&BatchProgress {
+                index,
+                completed,
+                failed,
+                total,
+            });
+        }
+        results[index] = Some(result);
+    }
+    results
+        .into_iter()
+        .map(|result| result.expect("every item finishes"))
+        .collect()
+}
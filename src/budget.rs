@@ -0,0 +1,188 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::{Error, Usage};
+
+/// Which limit of a `Budget` was reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetLimit {
+    Tokens,
+    Cost,
+    Requests,
+}
+
+/// What is left of a `Budget`. Fields are `None` for limits that are not set.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BudgetRemaining {
+    pub tokens: Option<u64>,
+    pub cost: Option<f64>,
+    pub requests: Option<u64>,
+    /// Time until the current window ends and the budget is refilled, if it
+    /// has a window.
+    pub resets_in: Option<Duration>,
+}
+
+/// A cap on how many tokens, dollars or requests models may spend.
+///
+/// Requests are refused with `Error::BudgetExceeded` before they are sent once
+/// any limit is reached. Token and cost limits are checked against what has
+/// been reported so far, so the request that crosses a limit still completes,
+/// and requests in flight together can each cross it before any of them
+/// reports usage.
+/// Clones share the same allowance, so one budget can cover several models.
+#[derive(Debug, Clone)]
+pub struct Budget {
+    max_tokens: Option<u64>,
+    max_cost: Option<f64>,
+    max_requests: Option<u64>,
+    window: Option<Duration>,
+    state: Arc<Mutex<BudgetState>>,
+}
+
+#[derive(Debug)]
+struct BudgetState {
+    window_start: Instant,
+    tokens: u64,
+    cost: f64,
+    requests: u64,
+}
+
+pub struct BudgetBuilder {
+    max_tokens: Option<u64>,
+    max_cost: Option<f64>,
+    max_requests: Option<u64>,
+    window: Option<Duration>,
+}
+
+impl BudgetBuilder {
+    pub fn new() -> Self {
+        BudgetBuilder {
+            max_tokens: None,
+            max_cost: None,
+            max_requests: None,
+            window: None,
+        }
+    }
+
+    /// Input plus output tokens.
+    pub fn max_tokens(&mut self, max_tokens: u64) -> &mut Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Dollars, as computed from each model's price table. Requests to models
+    /// without a price count as free.
+    pub fn max_cost(&mut self, max_cost: f64) -> &mut Self {
+        self.max_cost = Some(max_cost);
+        self
+    }
+
+    pub fn max_requests(&mut self, max_requests: u64) -> &mut Self {
+        self.max_requests = Some(max_requests);
+        self
+    }
+
+    /// Refills the budget every `window`, starting when it is built. Without a
+    /// window the limits apply for the lifetime of the budget.
+    pub fn window(&mut self, window: Duration) -> &mut Self {
+        self.window = Some(window);
+        self
+    }
+
+    pub fn build(&self) -> Budget {
+        Budget {
+            max_tokens: self.max_tokens,
+            max_cost: self.max_cost,
+            max_requests: self.max_requests,
+            window: self.window,
+            state: Arc::new(Mutex::new(BudgetState {
+                window_start: Instant::now(),
+                tokens: 0,
+                cost: 0.0,
+                requests: 0,
+            })),
+        }
+    }
+}
+
+impl Default for BudgetBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Budget {
+    pub fn remaining(&self) -> BudgetRemaining {
+        let state = self.current_state();
+        BudgetRemaining {
+            tokens: self
+                .max_tokens
+                .map(|max_tokens| max_tokens.saturating_sub(state.tokens)),
+            cost: self
+                .max_cost
+                .map(|max_cost| (max_cost - state.cost).max(0.0)),
+            requests: self
+                .max_requests
+                .map(|max_requests| max_requests.saturating_sub(state.requests)),
+            resets_in: self.resets_in(&state),
+        }
+    }
+
+    /// Takes one request from the budget, or fails if any limit is reached.
+    pub(crate) fn acquire(&self) -> Result<(), Error> {
+        self.acquire_many(1)
+    }
+
+    /// Takes `requests` requests from the budget at once, or none of them if
+    /// they do not all fit or any other limit is reached.
+    pub(crate) fn acquire_many(&self, requests: u64) -> Result<(), Error> {
+        let mut state = self.current_state();
+        let exceeded = if self
+            .max_requests
+            .is_some_and(|max| state.requests + requests > max)
+        {
+            Some(BudgetLimit::Requests)
+        } else if self.max_tokens.is_some_and(|max| state.tokens >= max) {
+            Some(BudgetLimit::Tokens)
+        } else if self.max_cost.is_some_and(|max| state.cost >= max) {
+            Some(BudgetLimit::Cost)
+        } else {
+            None
+        };
+        if let Some(limit) = exceeded {
+            return Err(Error::BudgetExceeded {
+                limit,
+                resets_in: self.resets_in(&state),
+            });
+        }
+        state.requests += requests;
+        Ok(())
+    }
+
+    pub(crate) fn record(&self, usage: Usage, cost: Option<f64>) {
+        let mut state = self.current_state();
+        state.tokens += usage.input_tokens + usage.output_tokens;
+        state.cost += cost.unwrap_or(0.0);
+    }
+
+    /// Locks the state, starting a new window first if the current one ended.
+    fn current_state(&self) -> MutexGuard<'_, BudgetState> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(window) = self.window {
+            let elapsed = state.window_start.elapsed();
+            if elapsed >= window {
+                let windows_passed = elapsed.as_nanos() / window.as_nanos().max(1);
+                state.window_start += window.mul_f64(windows_passed as f64);
+                state.tokens = 0;
+                state.cost = 0.0;
+                state.requests = 0;
+            }
+        }
+        state
+    }
+
+    fn resets_in(&self, state: &BudgetState) -> Option<Duration> {
+        self.window
+            .map(|window| window.saturating_sub(state.window_start.elapsed()))
+    }
+}
@@ -0,0 +1,317 @@
+use async_trait::async_trait;
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{GenerateMessageOptions, Generation, Message, MessageRole, Usage};
+
+/// Numbers the temporary files of `DiskCacheStore::put`.
+static TEMPORARY_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A stored response, as kept by a `CacheStore`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// The full request the response belongs to, compared on lookup so that a
+    /// hash collision is a miss rather than a wrong answer.
+    request: String,
+    content: String,
+    obj: Option<Map<String, Value>>,
+    usage: Option<Usage>,
+    model: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    created_at_ms: u64,
+}
+
+/// Where a `Cache` keeps its entries. Keys are hex strings that are safe to use
+/// as file names.
+///
+/// Stores should treat failures as misses: a cache that cannot be read or
+/// written must not fail the call.
+#[async_trait]
+pub trait CacheStore: Send + Sync {
+    async fn get(&self, key: &str) -> Option<CacheEntry>;
+
+    async fn put(&self, key: &str, entry: CacheEntry);
+}
+
+/// Hit and miss counts of a `Cache`. Requests that cannot be cached, and calls
+/// that bypass the cache, are not counted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+/// Reuses responses to identical requests.
+///
+/// Requests are identified by the base URL, the model name, the messages and
+/// every option that affects the answer. Requests sampled with a temperature above 0 are
+/// only cached when they also set a seed, since they are not expected to repeat
+/// otherwise. Streaming calls are never cached. A response served from the
+/// cache reports no usage or cost.
+///
+/// Clones share the same store and statistics.
+#[derive(Clone)]
+pub struct Cache {
+    store: Arc<dyn CacheStore>,
+    ttl: Option<Duration>,
+    stats: Arc<Mutex<CacheStats>>,
+}
+
+pub struct CacheBuilder {
+    store: Arc<dyn CacheStore>,
+    ttl: Option<Duration>,
+}
+
+impl CacheBuilder {
+    pub fn new<S: CacheStore + 'static>(store: S) -> Self {
+        CacheBuilder {
+            store: Arc::new(store),
+            ttl: None,
+        }
+    }
+
+    /// Ignores entries older than `ttl`. Without one, entries never expire.
+    pub fn ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn build(&self) -> Cache {
+        Cache {
+            store: self.store.clone(),
+            ttl: self.ttl,
+            stats: Arc::new(Mutex::new(CacheStats::default())),
+        }
+    }
+}
+
+impl Cache {
+    pub fn new<S: CacheStore + 'static>(store: S) -> Self {
+        CacheBuilder::new(store).build()
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        *self.stats.lock().unwrap()
+    }
+
+    pub(crate) async fn get(&self, request: &str) -> Option<Generation> {
+        let entry = self
+            .store
+            .get(&cache_key(request))
+            .await
+            .filter(|entry| entry.request == request)
+            .filter(|entry| {
+                self.ttl.is_none_or(|ttl| {
+                    Duration::from_millis(now_ms().saturating_sub(entry.created_at_ms)) < ttl
+                })
+            });
+        let mut stats = self.stats.lock().unwrap();
+        match entry {
+            Some(entry) => {
+                stats.hits += 1;
+                Some(Generation {
+                    message: Message {
+                        role: MessageRole::Assistant,
+                        content: entry.content,
+                        obj: entry.obj,
+                    },
+                    usage: entry.usage,
+                    model: entry.model,
+                    headers: HeaderMap::new(),
+                    alternatives: vec![],
+                })
+            }
+            None => {
+                stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub(crate) async fn put(&self, request: &str, generation: &Generation) {
+        let entry = CacheEntry {
+            request: request.to_string(),
+            content: generation.message.content.clone(),
+            obj: generation.message.obj.clone(),
+            usage: generation.usage,
+            model: generation.model.clone(),
+            created_at_ms: now_ms(),
+        };
+        self.store.put(&cache_key(request), entry).await;
+    }
+}
+
+/// Keeps the most recently used entries in memory.
+pub struct MemoryCacheStore {
+    capacity: usize,
+    state: Mutex<MemoryCacheState>,
+}
+
+#[derive(Default)]
+struct MemoryCacheState {
+    tick: u64,
+    entries: HashMap<String, (CacheEntry, u64)>,
+    /// Keys by the tick they were last used at, oldest first.
+    recency: BTreeMap<u64, String>,
+}
+
+impl MemoryCacheStore {
+    /// A store that evicts the least recently used entry once it holds
+    /// `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        MemoryCacheStore {
+            capacity: capacity.max(1),
+            state: Mutex::new(MemoryCacheState::default()),
+        }
+    }
+}
+
+impl MemoryCacheState {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some((_, last_used)) = self.entries.get_mut(key) {
+            self.recency.remove(last_used);
+            *last_used = tick;
+            self.recency.insert(tick, key.to_string());
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryCacheStore {
+    async fn get(&self, key: &str) -> Option<CacheEntry> {
+        let mut state = self.state.lock().unwrap();
+        state.touch(key);
+        state.entries.get(key).map(|(entry, _)| entry.clone())
+    }
+
+    async fn put(&self, key: &str, entry: CacheEntry) {
+        let mut state = self.state.lock().unwrap();
+        if let Some((_, last_used)) = state.entries.remove(key) {
+            state.recency.remove(&last_used);
+        }
+        while state.entries.len() >= self.capacity {
+            let Some((_, oldest)) = state.recency.pop_first() else {
+                break;
+            };
+            state.entries.remove(&oldest);
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.entries.insert(key.to_string(), (entry, tick));
+        state.recency.insert(tick, key.to_string());
+    }
+}
+
+/// Keeps each entry as a JSON file in a directory, so the cache survives
+/// restarts and can be shared between processes.
+pub struct DiskCacheStore {
+    dir: PathBuf,
+}
+
+impl DiskCacheStore {
+    /// A store in `dir`, which is created on the first write if needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        DiskCacheStore { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[async_trait]
+impl CacheStore for DiskCacheStore {
+    async fn get(&self, key: &str) -> Option<CacheEntry> {
+        let raw = tokio::fs::read(self.path(key)).await.ok()?;
+        serde_json::from_slice(&raw).ok()
+    }
+
+    async fn put(&self, key: &str, entry: CacheEntry) {
+        let Ok(raw) = serde_json::to_vec(&entry) else {
+            return;
+        };
+        if tokio::fs::create_dir_all(&self.dir).await.is_err() {
+            return;
+        }
+        // Write to a temporary file first so readers never see half an entry.
+        // The counter keeps concurrent writes of the same key in this process
+        // apart.
+        let temporary = self.dir.join(format!(
+            "{}.{}.{}.tmp",
+            key,
+            std::process::id(),
+            TEMPORARY_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        if tokio::fs::write(&temporary, raw).await.is_ok()
+            && tokio::fs::rename(&temporary, self.path(key)).await.is_err()
+        {
+            let _ = tokio::fs::remove_file(&temporary).await;
+        }
+    }
+}
+
+/// Whether the answer to a request with `options` is expected to repeat.
+pub(crate) fn is_cacheable(options: &GenerateMessageOptions) -> bool {
+    // Only one completion is stored per entry.
+    options.n.is_none_or(|n| n <= 1) && (options.temperature <= 0.0 || options.seed.is_some())
+}
+
+/// A canonical description of a request. Everything that can change the
+/// answer is included; the timeout is not.
+pub(crate) fn request_key(
+    base_url: &str,
+    model: &str,
+    messages: &[Message],
+    options: &GenerateMessageOptions,
+) -> String {
+    let logit_bias = options
+        .logit_bias
+        .as_ref()
+        .map(|logit_bias| logit_bias.iter().collect::<BTreeMap<_, _>>());
+    json!({
+        "base_url": base_url,
+        "model": model,
+        "messages": messages
+            .iter()
+            .map(|message| json!({"role": message.role, "content": message.content}))
+            .collect::<Vec<_>>(),
+        "temperature": options.temperature,
+        "force_json": options.force_json,
+        "json_schema": options.json_schema,
+        "top_p": options.top_p,
+        "max_tokens": options.max_tokens,
+        "stop": options.stop,
+        "seed": options.seed,
+        "presence_penalty": options.presence_penalty,
+        "frequency_penalty": options.frequency_penalty,
+        "logit_bias": logit_bias,
+        "user": options.user,
+    })
+    .to_string()
+}
+
+/// A stable 128-bit FNV-1a hash of `request` in hex. Unlike `std`'s hashers
+/// it does not change between runs or Rust versions, so disk entries stay
+/// valid.
+fn cache_key(request: &str) -> String {
+    const PRIME: u128 = 0x0000000001000000000000000000013B;
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    let hash = request.bytes().fold(OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u128).wrapping_mul(PRIME)
+    });
+    format!("{:032x}", hash)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
@@ -0,0 +1,222 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::Error;
+
+/// Whether a `Cassette` writes down real HTTP exchanges or plays them back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+/// Records the HTTP exchanges of a model to a JSON file and serves them back
+/// later without a network connection, so tests can run the real request
+/// building and response decoding offline.
+///
+/// In replay mode a request is answered with the first unused recorded
+/// exchange that has the same method, URL and body. A request without one
+/// fails with `Error::Cassette`. Request headers are never written to the
+/// file, so API keys stay out of it, and a replaying model needs no key: its
+/// credentials are neither checked nor looked up. Streamed responses are
+/// recorded once they have been received in full.
+///
+/// Clones share the same recording.
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    mode: CassetteMode,
+    path: PathBuf,
+    state: Arc<Mutex<CassetteState>>,
+    /// Held while the file is written, so that writes do not interleave.
+    writing: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Debug, Default)]
+struct CassetteState {
+    interactions: Vec<Interaction>,
+    used: Vec<bool>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CassetteFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    /// JSON bodies are kept as JSON so that they match regardless of
+    /// formatting. Streaming bodies, such as file uploads, are not kept.
+    body: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: String,
+}
+
+impl Cassette {
+    /// Records every exchange to `path`, replacing what is there.
+    pub fn record<P: Into<PathBuf>>(path: P) -> Self {
+        Cassette {
+            mode: CassetteMode::Record,
+            path: path.into(),
+            state: Arc::new(Mutex::new(CassetteState::default())),
+            writing: Arc::new(tokio::sync::Mutex::new(())),
+        }
+    }
+
+    /// Serves the exchanges recorded in `path`.
+    pub fn replay<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        let raw = std::fs::read_to_string(&path)
+            .map_err(|e| Error::Cassette(format!("cannot read {}: {}", path.display(), e)))?;
+        let file = serde_json::from_str::<CassetteFile>(&raw)
+            .map_err(|e| Error::Cassette(format!("cannot parse {}: {}", path.display(), e)))?;
+        let used = vec![false; file.interactions.len()];
+        Ok(Cassette {
+            mode: CassetteMode::Replay,
+            path,
+            state: Arc::new(Mutex::new(CassetteState {
+                interactions: file.interactions,
+                used,
+            })),
+            writing: Arc::new(tokio::sync::Mutex::new(())),
+        })
+    }
+
+    /// Replays `path` if it exists and records to it otherwise, so a test
+    /// records on its first run and is offline from then on.
+    pub fn auto<P: Into<PathBuf>>(path: P) -> Result<Self, Error> {
+        let path = path.into();
+        if path.exists() {
+            Cassette::replay(path)
+        } else {
+            Ok(Cassette::record(path))
+        }
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sends one attempt of `request`, or answers it from the recording.
+    pub(crate) async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        let (client, request) = request.build_split();
+        let request = request?;
+        let recorded_request = RecordedRequest {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            body: request.body().and_then(|body| body.as_bytes()).map(|body| {
+                match serde_json::from_slice(body) {
+                    Ok(json) => json,
+                    Err(_) => Value::String(String::from_utf8_lossy(body).into_owned()),
+                }
+            }),
+        };
+        match self.mode {
+            CassetteMode::Replay => self.replay_response(&recorded_request),
+            CassetteMode::Record => {
+                let response = client.execute(request).await?;
+                let recorded_response = RecordedResponse {
+                    status: response.status().as_u16(),
+                    headers: response
+                        .headers()
+                        .iter()
+                        .filter(|(name, _)| *name != reqwest::header::SET_COOKIE)
+                        .filter_map(|(name, value)| {
+                            Some((name.to_string(), value.to_str().ok()?.to_string()))
+                        })
+                        .collect(),
+                    body: response.text().await?,
+                };
+                let response = to_response(&recorded_response)?;
+                self.save(Interaction {
+                    request: recorded_request,
+                    response: recorded_response,
+                })
+                .await?;
+                Ok(response)
+            }
+        }
+    }
+
+    fn replay_response(&self, request: &RecordedRequest) -> Result<reqwest::Response, Error> {
+        let mut state = self.state.lock().unwrap();
+        let CassetteState { interactions, used } = &mut *state;
+        let found = interactions
+            .iter()
+            .zip(used.iter_mut())
+            .find(|(interaction, used)| !**used && interaction.request == *request);
+        match found {
+            Some((interaction, used)) => {
+                *used = true;
+                to_response(&interaction.response)
+            }
+            None => Err(Error::Cassette(format!(
+                "no recorded response in {} for {} {} with body {}",
+                self.path.display(),
+                request.method,
+                request.url,
+                request
+                    .body
+                    .as_ref()
+                    .map_or_else(|| String::from("<none>"), Value::to_string)
+            ))),
+        }
+    }
+
+    /// Appends `interaction` and rewrites the file, so the recording is
+    /// complete even if the process does not exit cleanly.
+    async fn save(&self, interaction: Interaction) -> Result<(), Error> {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.interactions.push(interaction);
+            state.used.push(true);
+        }
+        // Every write takes what has been recorded by the time it starts, so
+        // the last one to finish leaves the whole recording in the file.
+        let _writing = self.writing.lock().await;
+        let file = CassetteFile {
+            interactions: self.state.lock().unwrap().interactions.clone(),
+        };
+        let raw = serde_json::to_string_pretty(&file).expect("cassettes serialize");
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            tokio::fs::create_dir_all(dir)
+                .await
+                .map_err(|e| Error::Cassette(format!("cannot create {}: {}", dir.display(), e)))?;
+        }
+        tokio::fs::write(&self.path, raw)
+            .await
+            .map_err(|e| Error::Cassette(format!("cannot write {}: {}", self.path.display(), e)))
+    }
+}
+
+fn to_response(recorded: &RecordedResponse) -> Result<reqwest::Response, Error> {
+    let mut response = http::Response::builder().status(recorded.status);
+    for (name, value) in &recorded.headers {
+        response = response.header(name, value);
+    }
+    let response = response
+        .body(recorded.body.clone())
+        .map_err(|e| Error::Cassette(format!("invalid recorded response: {}", e)))?;
+    Ok(reqwest::Response::from(response))
+}
@@ -0,0 +1,196 @@
+use crate::{
+    Budget, Cache, CallOptions, Cassette, CassetteMode, CostTracker, CredentialProvider, Error,
+    HttpConfig, PriceTable, RateLimiter, RetryPolicy,
+};
+
+/// The key of a model that replays a cassette, which never sends a request.
+const REPLAY_API_KEY: &str = "replay";
+
+/// Settings every backend's builder has. Each builder keeps one in a `common`
+/// field and gets the setters from `common_builder_methods!`.
+#[derive(Clone)]
+pub(crate) struct CommonConfig {
+    pub(crate) credentials: Option<CredentialProvider>,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) client: Option<reqwest::Client>,
+    pub(crate) http_config: HttpConfig,
+    pub(crate) default_options: CallOptions,
+    pub(crate) price_table: PriceTable,
+    pub(crate) cost_tracker: CostTracker,
+    pub(crate) budget: Option<Budget>,
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    pub(crate) cache: Option<Cache>,
+    pub(crate) cassette: Option<Cassette>,
+}
+
+/// What a built model keeps of its builder's `CommonConfig`.
+pub(crate) struct ModelConfig {
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) client: reqwest::Client,
+    pub(crate) default_options: CallOptions,
+    pub(crate) price_table: PriceTable,
+    pub(crate) cost_tracker: CostTracker,
+    pub(crate) budget: Option<Budget>,
+    pub(crate) rate_limiter: Option<RateLimiter>,
+    pub(crate) cache: Option<Cache>,
+}
+
+impl CommonConfig {
+    pub(crate) fn new() -> Self {
+        CommonConfig {
+            credentials: None,
+            retry_policy: RetryPolicy::none(),
+            client: None,
+            http_config: HttpConfig::default(),
+            default_options: CallOptions::default(),
+            price_table: PriceTable::new(),
+            cost_tracker: CostTracker::new(),
+            budget: None,
+            rate_limiter: None,
+            cache: None,
+            cassette: None,
+        }
+    }
+
+    /// The configured credentials, or the environment variable `env_name`,
+    /// as checked by `checked_credentials`.
+    pub(crate) fn credentials(&self, env_name: &str) -> Result<CredentialProvider, Error> {
+        let credentials = self
+            .credentials
+            .clone()
+            .unwrap_or_else(|| CredentialProvider::env(env_name.to_string()));
+        self.checked_credentials(credentials)
+    }
+
+    /// `credentials`, failing if they are known to be unavailable. A model
+    /// that replays a cassette needs no key, so it gets a placeholder instead
+    /// and works without one.
+    pub(crate) fn checked_credentials(
+        &self,
+        credentials: CredentialProvider,
+    ) -> Result<CredentialProvider, Error> {
+        let replaying = self
+            .cassette
+            .as_ref()
+            .is_some_and(|cassette| cassette.mode() == CassetteMode::Replay);
+        if replaying {
+            return Ok(CredentialProvider::fixed(REPLAY_API_KEY.to_string()));
+        }
+        credentials.check()?;
+        Ok(credentials)
+    }
+
+    /// Fails with `Error::Transport` if no client was given and one cannot be
+    /// created from the `http_config`.
+    pub(crate) fn build(&self) -> Result<ModelConfig, Error> {
+        let client = match &self.client {
+            Some(client) => client.clone(),
+            None => self.http_config.client()?,
+        };
+        Ok(ModelConfig {
+            retry_policy: self
+                .retry_policy
+                .clone()
+                .with_cassette(self.cassette.clone()),
+            client,
+            default_options: self.default_options.clone(),
+            price_table: self.price_table.clone(),
+            cost_tracker: self.cost_tracker.clone(),
+            budget: self.budget.clone(),
+            rate_limiter: self.rate_limiter.clone(),
+            cache: self.cache.clone(),
+        })
+    }
+}
+
+/// The `api_key` and `credentials` setters of a builder whose key defaults to
+/// the environment variable `$env_name`.
+macro_rules! credential_builder_methods {
+    ($env_name:literal) => {
+        #[doc = concat!("Uses this key instead of reading `", $env_name, "` from the environment.")]
+        pub fn api_key(&mut self, api_key: String) -> &mut Self {
+            self.common.credentials = Some($crate::CredentialProvider::fixed(api_key));
+            self
+        }
+
+        #[doc = concat!("Looks up the API key with `credentials` instead of reading `", $env_name, "` from the environment.")]
+        pub fn credentials(&mut self, credentials: $crate::CredentialProvider) -> &mut Self {
+            self.common.credentials = Some(credentials);
+            self
+        }
+    };
+}
+
+/// The setters for the fields of `CommonConfig` other than credentials.
+macro_rules! common_builder_methods {
+    () => {
+        /// Retries failed requests according to `retry_policy`. By default every
+        /// request is sent once.
+        pub fn retry_policy(&mut self, retry_policy: $crate::RetryPolicy) -> &mut Self {
+            self.common.retry_policy = retry_policy;
+            self
+        }
+
+        /// Sends requests with `client`, e.g. to share one connection pool across
+        /// many models. The builder's `http_config` is ignored in that case.
+        pub fn client(&mut self, client: reqwest::Client) -> &mut Self {
+            self.common.client = Some(client);
+            self
+        }
+
+        /// Timeouts, proxy and other settings for the client the model creates
+        /// when none is given.
+        pub fn http_config(&mut self, http_config: $crate::HttpConfig) -> &mut Self {
+            self.common.http_config = http_config;
+            self
+        }
+
+        /// Options used for every call unless the call overrides them.
+        pub fn default_options(&mut self, default_options: $crate::CallOptions) -> &mut Self {
+            self.common.default_options = default_options;
+            self
+        }
+
+        /// Prices used to compute the cost of each call.
+        pub fn price_table(&mut self, price_table: $crate::PriceTable) -> &mut Self {
+            self.common.price_table = price_table;
+            self
+        }
+
+        /// Accumulates usage and cost in `cost_tracker`, e.g. to share one total
+        /// across several models. Each model gets its own tracker by default.
+        pub fn cost_tracker(&mut self, cost_tracker: $crate::CostTracker) -> &mut Self {
+            self.common.cost_tracker = cost_tracker;
+            self
+        }
+
+        /// Refuses requests once `budget` is used up. Clones of a budget share
+        /// their allowance, so it can be given to several models.
+        pub fn budget(&mut self, budget: $crate::Budget) -> &mut Self {
+            self.common.budget = Some(budget);
+            self
+        }
+
+        /// Throttles requests to stay within `rate_limiter`'s limits. Models that
+        /// use the same API key should share one limiter.
+        pub fn rate_limiter(&mut self, rate_limiter: $crate::RateLimiter) -> &mut Self {
+            self.common.rate_limiter = Some(rate_limiter);
+            self
+        }
+
+        /// Answers repeated requests from `cache` instead of sending them again.
+        pub fn cache(&mut self, cache: $crate::Cache) -> &mut Self {
+            self.common.cache = Some(cache);
+            self
+        }
+
+        /// Records requests to `cassette` or serves them from it, e.g. to run
+        /// tests offline.
+        pub fn cassette(&mut self, cassette: $crate::Cassette) -> &mut Self {
+            self.common.cassette = Some(cassette);
+            self
+        }
+    };
+}
+
+pub(crate) use {common_builder_methods, credential_builder_methods};
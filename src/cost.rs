@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::Usage;
+
+/// Dollar prices per million tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+    /// Price of cached input tokens. Cached tokens are billed as regular input
+    /// when this is `None`.
+    pub cached_input: Option<f64>,
+}
+
+impl Price {
+    pub fn new(input: f64, output: f64) -> Self {
+        Price {
+            input,
+            output,
+            cached_input: None,
+        }
+    }
+
+    pub fn with_cached_input(mut self, cached_input: f64) -> Self {
+        self.cached_input = Some(cached_input);
+        self
+    }
+
+    /// Dollar cost of `usage` at these prices.
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        let cached_input_tokens = usage.cached_input_tokens.min(usage.input_tokens);
+        let uncached_input_tokens = usage.input_tokens - cached_input_tokens;
+        (uncached_input_tokens as f64 * self.input
+            + cached_input_tokens as f64 * self.cached_input.unwrap_or(self.input)
+            + usage.output_tokens as f64 * self.output)
+            / 1_000_000.0
+    }
+}
+
+/// Prices by model name.
+///
+/// No prices are built in, since they change more often than this crate does.
+/// A model without an exact entry uses the longest entry its name starts with
+/// followed by a version, so `gpt-4o` also prices `gpt-4o-2024-08-06` and
+/// `gemini-1.5-pro` prices `gemini-1.5-pro-002`. Other models that share a
+/// prefix are not priced by it: `gpt-4o-mini` needs its own entry.
+#[derive(Debug, Clone, Default)]
+pub struct PriceTable {
+    prices: HashMap<String, Price>,
+}
+
+impl PriceTable {
+    pub fn new() -> Self {
+        PriceTable {
+            prices: HashMap::new(),
+        }
+    }
+
+    pub fn price(&mut self, model: String, price: Price) -> &mut Self {
+        self.prices.insert(model, price);
+        self
+    }
+
+    pub fn get(&self, model: &str) -> Option<&Price> {
+        self.prices.get(model).or_else(|| {
+            self.prices
+                .iter()
+                .filter(|(name, _)| {
+                    model
+                        .strip_prefix(name.as_str())
+                        .is_some_and(is_version_suffix)
+                })
+                .max_by_key(|(name, _)| name.len())
+                .map(|(_, price)| price)
+        })
+    }
+
+    /// Dollar cost of `usage` on `model`, or `None` if the model has no price.
+    pub fn cost(&self, model: &str, usage: &Usage) -> Option<f64> {
+        self.get(model).map(|price| price.cost(usage))
+    }
+}
+
+/// Whether `suffix` is a separator followed by a version, date or size, as in
+/// `gpt-4o-2024-08-06`, `claude-3-5-sonnet@20240620` or `llama3:8b`.
+fn is_version_suffix(suffix: &str) -> bool {
+    let mut chars = suffix.chars();
+    matches!(chars.next(), Some('-' | '@' | ':'))
+        && chars.next().is_some_and(|c| c.is_ascii_digit())
+}
+
+/// Running totals of what a model has used.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CostTotals {
+    /// Requests that reported usage.
+    pub requests: u64,
+    pub usage: Usage,
+    /// Dollar cost of the requests whose model has a price.
+    pub cost: f64,
+}
+
+/// Accumulates usage and cost across calls. Clones share the same totals, so
+/// one tracker can be given to several models to account for them together.
+#[derive(Debug, Clone, Default)]
+pub struct CostTracker {
+    totals: Arc<Mutex<CostTotals>>,
+}
+
+impl CostTracker {
+    pub fn new() -> Self {
+        CostTracker::default()
+    }
+
+    pub fn totals(&self) -> CostTotals {
+        *self.totals.lock().unwrap()
+    }
+
+    pub fn reset(&self) {
+        *self.totals.lock().unwrap() = CostTotals::default();
+    }
+
+    /// A tracker that is never recorded to, so its totals stay at zero.
+    pub(crate) fn untracked() -> &'static CostTracker {
+        static UNTRACKED: OnceLock<CostTracker> = OnceLock::new();
+        UNTRACKED.get_or_init(CostTracker::new)
+    }
+
+    pub(crate) fn record(&self, usage: Usage, cost: Option<f64>) {
+        let mut totals = self.totals.lock().unwrap();
+        totals.requests += 1;
+        totals.usage += usage;
+        totals.cost += cost.unwrap_or(0.0);
+    }
+}
@@ -0,0 +1,194 @@
+use std::fmt;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+use crate::Error;
+
+pub type CredentialFuture =
+    Pin<Box<dyn Future<Output = Result<String, Box<dyn std::error::Error + Send + Sync>>> + Send>>;
+
+type Fetch = Arc<dyn Fn() -> CredentialFuture + Send + Sync>;
+
+/// Where a model gets its API key from. The key is looked up before every
+/// request, so a key that changes in the environment or on disk is picked up
+/// without rebuilding the model.
+///
+/// Debug output never contains the key.
+#[derive(Clone)]
+pub struct CredentialProvider {
+    source: CredentialSource,
+}
+
+#[derive(Clone)]
+enum CredentialSource {
+    Fixed(String),
+    Env(String),
+    File(PathBuf),
+    Callback(Fetch),
+    Rotating {
+        fetch: Fetch,
+        interval: Duration,
+        current: Arc<Mutex<Option<(String, Instant)>>>,
+    },
+}
+
+impl CredentialProvider {
+    /// Always uses `api_key`.
+    pub fn fixed(api_key: String) -> Self {
+        CredentialProvider {
+            source: CredentialSource::Fixed(api_key),
+        }
+    }
+
+    /// Reads the key from the environment variable `name`.
+    pub fn env(name: String) -> Self {
+        CredentialProvider {
+            source: CredentialSource::Env(name),
+        }
+    }
+
+    /// Reads the key from the file at `path`, ignoring surrounding whitespace,
+    /// e.g. a mounted secret.
+    pub fn file<P: Into<PathBuf>>(path: P) -> Self {
+        CredentialProvider {
+            source: CredentialSource::File(path.into()),
+        }
+    }
+
+    /// Asks `callback` for the key before every request, so it should cache
+    /// keys itself.
+    pub fn callback<F, Fut>(callback: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, Box<dyn std::error::Error + Send + Sync>>>
+            + Send
+            + 'static,
+    {
+        CredentialProvider {
+            source: CredentialSource::Callback(Arc::new(move || Box::pin(callback()))),
+        }
+    }
+
+    /// Asks `fetch` for a key on first use and again once the key is older
+    /// than `interval`, e.g. for keys that a secret manager rotates.
+    ///
+    /// If a refresh fails, the previous key is used and the refresh is tried
+    /// again on the next request, but only until the key is twice `interval`
+    /// old. From then on the refresh error fails the request, so a fetch that
+    /// keeps failing is noticed before the key is revoked.
+    pub fn rotating<F, Fut>(interval: Duration, fetch: F) -> Self
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, Box<dyn std::error::Error + Send + Sync>>>
+            + Send
+            + 'static,
+    {
+        CredentialProvider {
+            source: CredentialSource::Rotating {
+                fetch: Arc::new(move || Box::pin(fetch())),
+                interval,
+                current: Arc::new(Mutex::new(None)),
+            },
+        }
+    }
+
+    /// Looks up the key. Useful to check callback and rotating providers at
+    /// startup, since building a model can only check the other kinds.
+    pub async fn fetch(&self) -> Result<String, Error> {
+        match &self.source {
+            CredentialSource::Fixed(api_key) => Ok(api_key.clone()),
+            CredentialSource::Env(name) => read_env(name),
+            CredentialSource::File(path) => {
+                let raw = tokio::fs::read_to_string(path).await.map_err(|e| {
+                    Error::Credentials(format!("cannot read {}: {}", path.display(), e).into())
+                })?;
+                non_empty(raw.trim(), || format!("{} is empty", path.display()))
+            }
+            CredentialSource::Callback(callback) => callback().await.map_err(Error::Credentials),
+            CredentialSource::Rotating {
+                fetch,
+                interval,
+                current,
+            } => {
+                // Held across the fetch so concurrent requests wait for a
+                // single refresh instead of each starting their own.
+                let mut current = current.lock().await;
+                match &*current {
+                    Some((api_key, fetched_at)) if fetched_at.elapsed() < *interval => {
+                        return Ok(api_key.clone());
+                    }
+                    _ => {}
+                }
+                match fetch().await {
+                    Ok(api_key) => {
+                        *current = Some((api_key.clone(), Instant::now()));
+                        Ok(api_key)
+                    }
+                    Err(e) => match &*current {
+                        Some((api_key, fetched_at))
+                            if fetched_at.elapsed() < interval.saturating_mul(2) =>
+                        {
+                            Ok(api_key.clone())
+                        }
+                        _ => Err(Error::Credentials(e)),
+                    },
+                }
+            }
+        }
+    }
+
+    /// Fails if the key is known to be unavailable without waiting on I/O:
+    /// an unset environment variable or an unreadable file.
+    pub(crate) fn check(&self) -> Result<(), Error> {
+        match &self.source {
+            CredentialSource::Env(name) => read_env(name).map(|_| ()),
+            CredentialSource::File(path) => match std::fs::metadata(path) {
+                Ok(metadata) if metadata.is_file() => Ok(()),
+                Ok(_) => Err(Error::Credentials(
+                    format!("{} is not a file", path.display()).into(),
+                )),
+                Err(e) => Err(Error::Credentials(
+                    format!("cannot read {}: {}", path.display(), e).into(),
+                )),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+fn read_env(name: &str) -> Result<String, Error> {
+    let api_key = std::env::var(name).unwrap_or_default();
+    non_empty(&api_key, || {
+        format!("{} not found in environment variables", name)
+    })
+}
+
+fn non_empty(api_key: &str, message: impl FnOnce() -> String) -> Result<String, Error> {
+    if api_key.is_empty() {
+        Err(Error::Credentials(message().into()))
+    } else {
+        Ok(api_key.to_string())
+    }
+}
+
+impl fmt::Debug for CredentialProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.source {
+            CredentialSource::Fixed(_) => f
+                .debug_tuple("Fixed")
+                .field(&format_args!("<redacted>"))
+                .finish(),
+            CredentialSource::Env(name) => f.debug_tuple("Env").field(name).finish(),
+            CredentialSource::File(path) => f.debug_tuple("File").field(path).finish(),
+            CredentialSource::Callback(_) => f.write_str("Callback"),
+            CredentialSource::Rotating { interval, .. } => f
+                .debug_struct("Rotating")
+                .field("interval", interval)
+                .finish_non_exhaustive(),
+        }
+    }
+}
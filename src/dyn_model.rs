@@ -0,0 +1,402 @@
+use async_trait::async_trait;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::{
+    struct_to_json_schema, CallOptions, ClassifyVote, CostTracker, Error, Model, Response,
+    ScoreVote, TextStream, VoteOptions,
+};
+
+/// An object-safe version of `Model`, so that a model can be kept behind
+/// `Box<dyn DynModel>` or `Arc<dyn DynModel>`, e.g. when the backend is picked
+/// from configuration at runtime.
+///
+/// Every `Model` implements it. `parse` is replaced by `dyn_parse_value`,
+/// which takes the schema as JSON, and the `*_vote` methods are kept so a
+/// backend can still ask for every sample in one request. The methods are
+/// prefixed with `dyn_` so they do not clash with `Model`'s when both traits
+/// are in scope; wrap a `dyn DynModel` in an `AnyModel` to call it through
+/// `Model` instead.
+#[async_trait]
+pub trait DynModel: Send + Sync {
+    fn dyn_cost_tracker(&self) -> &CostTracker;
+
+    async fn dyn_classify(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error>;
+
+    async fn dyn_binary_classify(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error>;
+
+    async fn dyn_generate_text(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error>;
+
+    async fn dyn_generate_text_stream(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error>;
+
+    async fn dyn_score_float(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error>;
+
+    async fn dyn_score_int(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error>;
+
+    async fn dyn_parse_value(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error>;
+
+    async fn dyn_classify_vote(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &VoteOptions,
+    ) -> Result<Response<ClassifyVote>, Error>;
+
+    async fn dyn_score_float_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<f64>>, Error>;
+
+    async fn dyn_score_int_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<i64>>, Error>;
+}
+
+#[async_trait]
+impl<M: Model + Send + Sync> DynModel for M {
+    fn dyn_cost_tracker(&self) -> &CostTracker {
+        self.cost_tracker()
+    }
+
+    async fn dyn_classify(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        self.classify_with_options(instruction, text, choices, options)
+            .await
+    }
+
+    async fn dyn_binary_classify(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        self.binary_classify_with_options(instruction, text, options)
+            .await
+    }
+
+    async fn dyn_generate_text(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        self.generate_text_with_options(instruction, text, options)
+            .await
+    }
+
+    async fn dyn_generate_text_stream(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        self.generate_text_stream_with_options(instruction, text, options)
+            .await
+    }
+
+    async fn dyn_score_float(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        self.score_float_with_options(instruction, text, min_bound, max_bound, options)
+            .await
+    }
+
+    async fn dyn_score_int(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        self.score_int_with_options(instruction, text, min_bound, max_bound, options)
+            .await
+    }
+
+    async fn dyn_parse_value(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        self.parse_value_with_options(text, schema, options).await
+    }
+
+    async fn dyn_classify_vote(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &VoteOptions,
+    ) -> Result<Response<ClassifyVote>, Error> {
+        self.classify_vote(instruction, text, choices, options)
+            .await
+    }
+
+    async fn dyn_score_float_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<f64>>, Error> {
+        self.score_float_vote(instruction, text, min_bound, max_bound, options)
+            .await
+    }
+
+    async fn dyn_score_int_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<i64>>, Error> {
+        self.score_int_vote(instruction, text, min_bound, max_bound, options)
+            .await
+    }
+}
+
+/// A `Model` backed by any `DynModel`, which gives a model chosen at runtime
+/// the whole `Model` API back, including `parse::<T>()` and the batch
+/// methods.
+///
+/// `parse` asks for `T`'s schema through `dyn_parse_value`, which checks the
+/// answer against it and repairs it like the concrete model would, then
+/// decodes the JSON into `T`.
+///
+/// Clones share the same model.
+#[derive(Clone)]
+pub struct AnyModel {
+    model: Arc<dyn DynModel>,
+}
+
+impl AnyModel {
+    pub fn new<M: DynModel + 'static>(model: M) -> Self {
+        AnyModel {
+            model: Arc::new(model),
+        }
+    }
+
+    pub fn inner(&self) -> &Arc<dyn DynModel> {
+        &self.model
+    }
+}
+
+impl From<Arc<dyn DynModel>> for AnyModel {
+    fn from(model: Arc<dyn DynModel>) -> Self {
+        AnyModel { model }
+    }
+}
+
+impl From<Box<dyn DynModel>> for AnyModel {
+    fn from(model: Box<dyn DynModel>) -> Self {
+        AnyModel {
+            model: Arc::from(model),
+        }
+    }
+}
+
+impl Model for AnyModel {
+    fn cost_tracker(&self) -> &CostTracker {
+        self.model.dyn_cost_tracker()
+    }
+
+    async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        self.model
+            .dyn_classify(instruction, text, choices, options)
+            .await
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        self.model
+            .dyn_binary_classify(instruction, text, options)
+            .await
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        self.model
+            .dyn_generate_text(instruction, text, options)
+            .await
+    }
+
+    async fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        self.model
+            .dyn_generate_text_stream(instruction, text, options)
+            .await
+    }
+
+    async fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        self.model
+            .dyn_score_float(instruction, text, min_bound, max_bound, options)
+            .await
+    }
+
+    async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        self.model
+            .dyn_score_int(instruction, text, min_bound, max_bound, options)
+            .await
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        let response = self
+            .model
+            .dyn_parse_value(text, struct_to_json_schema::<T>(), options)
+            .await?;
+        let value = T::deserialize(&response.value)
+            .map_err(|e| Error::from_serde(response.value.to_string(), e))?;
+        Ok(response.map(|_| value))
+    }
+
+    async fn parse_value_with_options(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        self.model.dyn_parse_value(text, schema, options).await
+    }
+
+    async fn classify_vote(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &VoteOptions,
+    ) -> Result<Response<ClassifyVote>, Error> {
+        self.model
+            .dyn_classify_vote(instruction, text, choices, options)
+            .await
+    }
+
+    async fn score_float_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<f64>>, Error> {
+        self.model
+            .dyn_score_float_vote(instruction, text, min_bound, max_bound, options)
+            .await
+    }
+
+    async fn score_int_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<i64>>, Error> {
+        self.model
+            .dyn_score_int_vote(instruction, text, min_bound, max_bound, options)
+            .await
+    }
+}
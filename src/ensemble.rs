@@ -0,0 +1,456 @@
+use futures::future::join_all;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+
+use crate::{batch, AnyModel, BatchOptions, CallOptions, DynModel, Error, Model, Response};
+
+/// One model's answer in an `EnsembleItem`.
+#[derive(Debug)]
+pub struct ModelAnswer<T> {
+    /// The name the model was added with.
+    pub model: String,
+    pub result: Result<Response<T>, Error>,
+}
+
+/// Every model's answer to one call of an `EnsembleModel`, and how far they
+/// agree.
+#[derive(Debug)]
+pub struct EnsembleItem<T> {
+    /// One answer per model, in the order the models were added.
+    pub answers: Vec<ModelAnswer<T>>,
+    /// The answer more models gave than any other. `None` on a tie or when
+    /// every model failed.
+    pub majority: Option<T>,
+    /// Share of the models that answered that gave the most common answer,
+    /// from 0 to 1. 0 when every model failed.
+    pub agreement: f64,
+    /// Whether the models that answered gave different answers.
+    pub disagreement: bool,
+    /// Whether any model failed. Its answer might have changed the outcome,
+    /// so these items need review as much as disagreements do.
+    pub incomplete: bool,
+}
+
+impl<T: Eq + Hash + Clone> EnsembleItem<T> {
+    fn new(answers: Vec<ModelAnswer<T>>) -> Self {
+        let counts = counts(
+            answers
+                .iter()
+                .filter_map(|answer| answer.result.as_ref().ok())
+                .map(|response| &response.value),
+        );
+        let answered: usize = counts.values().sum();
+        let top = counts.values().copied().max().unwrap_or(0);
+        let mut leaders = counts.iter().filter(|(_, count)| **count == top);
+        let majority = match (leaders.next(), leaders.next()) {
+            (Some((value, _)), None) => Some((*value).clone()),
+            _ => None,
+        };
+        EnsembleItem {
+            majority,
+            agreement: if answered == 0 {
+                0.0
+            } else {
+                top as f64 / answered as f64
+            },
+            disagreement: counts.len() > 1,
+            incomplete: answers.iter().any(|answer| answer.result.is_err()),
+            answers,
+        }
+    }
+}
+
+impl<T> EnsembleItem<T> {
+    /// The answer of the model added as `model`, if it did not fail.
+    pub fn value(&self, model: &str) -> Option<&T> {
+        self.answers
+            .iter()
+            .find(|answer| answer.model == model)
+            .and_then(|answer| answer.result.as_ref().ok())
+            .map(|response| &response.value)
+    }
+
+    /// Number of models that failed.
+    pub fn failed(&self) -> usize {
+        self.answers
+            .iter()
+            .filter(|answer| answer.result.is_err())
+            .count()
+    }
+}
+
+/// Cohen's kappa between two models in an `EnsembleReport`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairwiseKappa {
+    pub first: String,
+    pub second: String,
+    /// Over the items both models answered; see `cohen_kappa`.
+    pub kappa: Option<f64>,
+}
+
+/// The answers of an `EnsembleModel` to a batch of texts, with agreement
+/// statistics across the batch.
+#[derive(Debug)]
+pub struct EnsembleReport<T> {
+    /// One item per text, in input order.
+    pub items: Vec<EnsembleItem<T>>,
+    /// Fleiss' kappa over the items every model answered; see `fleiss_kappa`.
+    pub fleiss_kappa: Option<f64>,
+    /// Cohen's kappa of every pair of models, in the order the models were
+    /// added.
+    pub cohen_kappa: Vec<PairwiseKappa>,
+}
+
+impl<T: Eq + Hash> EnsembleReport<T> {
+    fn new(models: &[&str], items: Vec<EnsembleItem<T>>) -> Self {
+        let values: Vec<Vec<Option<&T>>> = items
+            .iter()
+            .map(|item| {
+                item.answers
+                    .iter()
+                    .map(|answer| answer.result.as_ref().ok().map(|response| &response.value))
+                    .collect()
+            })
+            .collect();
+        let complete: Vec<Vec<&T>> = values
+            .iter()
+            .filter_map(|item| item.iter().copied().collect())
+            .collect();
+        let mut pairs = vec![];
+        for first in 0..models.len() {
+            for second in first + 1..models.len() {
+                let (first_values, second_values): (Vec<&T>, Vec<&T>) = values
+                    .iter()
+                    .filter_map(|item| Some((item[first]?, item[second]?)))
+                    .unzip();
+                pairs.push(PairwiseKappa {
+                    first: models[first].to_string(),
+                    second: models[second].to_string(),
+                    kappa: cohen_kappa(&first_values, &second_values),
+                });
+            }
+        }
+        let fleiss_kappa = fleiss_kappa(&complete);
+        EnsembleReport {
+            items,
+            fleiss_kappa,
+            cohen_kappa: pairs,
+        }
+    }
+}
+
+impl<T> EnsembleReport<T> {
+    /// The items to send for review, with their position in the input: those
+    /// the models disagreed on and those some model failed on.
+    pub fn disagreements(&self) -> impl Iterator<Item = (usize, &EnsembleItem<T>)> {
+        self.items
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| item.disagreement || item.incomplete)
+    }
+}
+
+/// Cohen's kappa: how far two raters agree beyond what their label frequencies
+/// would give by chance. 1 is perfect agreement, 0 chance level.
+///
+/// `None` without ratings, or when both raters gave every item the same label,
+/// where chance already explains all agreement.
+///
+/// # Panics
+///
+/// If `first` and `second` have different lengths.
+pub fn cohen_kappa<T: Eq + Hash>(first: &[T], second: &[T]) -> Option<f64> {
+    assert_eq!(
+        first.len(),
+        second.len(),
+        "both raters must rate the same items"
+    );
+    if first.is_empty() {
+        return None;
+    }
+    let total = first.len() as f64;
+    let observed = first.iter().zip(second).filter(|(a, b)| a == b).count() as f64 / total;
+    let second_counts = counts(second.iter());
+    let expected = counts(first.iter())
+        .iter()
+        .map(|(label, count)| {
+            (*count * second_counts.get(label).copied().unwrap_or(0)) as f64 / (total * total)
+        })
+        .sum();
+    kappa(observed, expected)
+}
+
+/// Fleiss' kappa: Cohen's kappa generalised to any number of raters. `ratings`
+/// holds the labels each item was given, one per rater.
+///
+/// `None` without items, with fewer than two raters, or when every rating is
+/// the same label.
+///
+/// # Panics
+///
+/// If the items do not all have the same number of ratings.
+pub fn fleiss_kappa<T: Eq + Hash>(ratings: &[Vec<T>]) -> Option<f64> {
+    let raters = ratings.first()?.len();
+    assert!(
+        ratings.iter().all(|item| item.len() == raters),
+        "every item must have the same number of ratings"
+    );
+    if raters < 2 {
+        return None;
+    }
+    let mut totals: HashMap<&T, usize> = HashMap::new();
+    let mut observed = 0.0;
+    for item in ratings {
+        let item_counts = counts(item.iter());
+        let agreeing_pairs: usize = item_counts.values().map(|count| count * (count - 1)).sum();
+        observed += agreeing_pairs as f64 / (raters * (raters - 1)) as f64;
+        for (label, count) in item_counts {
+            *totals.entry(label).or_default() += count;
+        }
+    }
+    observed /= ratings.len() as f64;
+    let all = (ratings.len() * raters) as f64;
+    let expected = totals
+        .values()
+        .map(|count| (*count as f64 / all).powi(2))
+        .sum();
+    kappa(observed, expected)
+}
+
+fn kappa(observed: f64, expected: f64) -> Option<f64> {
+    if expected >= 1.0 {
+        None
+    } else {
+        Some((observed - expected) / (1.0 - expected))
+    }
+}
+
+fn counts<'a, T: Eq + Hash>(labels: impl Iterator<Item = &'a T>) -> HashMap<&'a T, usize> {
+    let mut counts = HashMap::new();
+    for label in labels {
+        *counts.entry(label).or_default() += 1;
+    }
+    counts
+}
+
+/// Sends the same call to several models and returns every model's answer
+/// with how far they agree, e.g. to audit labels and send the items models
+/// disagree on to a human.
+///
+/// The models are called concurrently. A model that fails does not fail the
+/// call; its error is kept in its `ModelAnswer` and it is left out of the
+/// agreement statistics.
+///
+/// Clones share the same models.
+#[derive(Clone)]
+pub struct EnsembleModel {
+    models: Vec<(String, AnyModel)>,
+}
+
+pub struct EnsembleModelBuilder {
+    models: Vec<(String, AnyModel)>,
+}
+
+impl EnsembleModelBuilder {
+    pub fn new() -> Self {
+        EnsembleModelBuilder { models: vec![] }
+    }
+
+    /// Adds `model` after the ones added so far. `name` identifies its
+    /// answers.
+    pub fn model<M: DynModel + 'static>(&mut self, name: String, model: M) -> &mut Self {
+        self.models.push((name, AnyModel::new(model)));
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If no model was added.
+    pub fn build(&self) -> EnsembleModel {
+        assert!(
+            !self.models.is_empty(),
+            "an EnsembleModel needs at least one model"
+        );
+        EnsembleModel {
+            models: self.models.clone(),
+        }
+    }
+}
+
+impl Default for EnsembleModelBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EnsembleModel {
+    /// Names of the models, in the order their answers are listed.
+    pub fn model_names(&self) -> Vec<&str> {
+        self.models.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    pub async fn classify(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+    ) -> EnsembleItem<usize> {
+        self.classify_with_options(instruction, text, choices, &CallOptions::default())
+            .await
+    }
+
+    /// Asks every model to `classify` the text. The answers are indices into
+    /// `choices`.
+    pub async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> EnsembleItem<usize> {
+        self.ask(|model| {
+            model.classify_with_options(instruction.clone(), text.clone(), choices.clone(), options)
+        })
+        .await
+    }
+
+    pub async fn score_int(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+    ) -> EnsembleItem<i64> {
+        self.score_int_with_options(
+            instruction,
+            text,
+            min_bound,
+            max_bound,
+            &CallOptions::default(),
+        )
+        .await
+    }
+
+    /// Asks every model to `score_int` the text. Scores only agree when they
+    /// are equal, so kappa treats them as labels rather than as a scale.
+    pub async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> EnsembleItem<i64> {
+        self.ask(|model| {
+            model.score_int_with_options(
+                instruction.clone(),
+                text.clone(),
+                min_bound,
+                max_bound,
+                options,
+            )
+        })
+        .await
+    }
+
+    /// Runs `classify` on every text and reports agreement across them.
+    ///
+    /// Each model's call counts as one item of the batch, so `concurrency` and
+    /// the `on_progress` hook see texts times models calls. Calls cut short by
+    /// the cancel token end up as `Error::Cancelled` answers.
+    pub async fn classify_many<I>(
+        &self,
+        instruction: String,
+        texts: I,
+        choices: Vec<String>,
+        options: &BatchOptions,
+    ) -> EnsembleReport<usize>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.ask_many(texts.into_iter().collect(), options, |model, text| {
+            model.classify_with_options(
+                instruction.clone(),
+                text,
+                choices.clone(),
+                options.call_options(),
+            )
+        })
+        .await
+    }
+
+    /// Like `classify_many`, for `score_int`.
+    pub async fn score_int_many<I>(
+        &self,
+        instruction: String,
+        texts: I,
+        min_bound: i64,
+        max_bound: i64,
+        options: &BatchOptions,
+    ) -> EnsembleReport<i64>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.ask_many(texts.into_iter().collect(), options, |model, text| {
+            model.score_int_with_options(
+                instruction.clone(),
+                text,
+                min_bound,
+                max_bound,
+                options.call_options(),
+            )
+        })
+        .await
+    }
+
+    fn answers<T>(&self, results: Vec<Result<Response<T>, Error>>) -> Vec<ModelAnswer<T>> {
+        self.models
+            .iter()
+            .zip(results)
+            .map(|((name, _), result)| ModelAnswer {
+                model: name.clone(),
+                result,
+            })
+            .collect()
+    }
+
+    async fn ask<'a, T, F, Fut>(&'a self, call: F) -> EnsembleItem<T>
+    where
+        T: Eq + Hash + Clone,
+        F: Fn(&'a AnyModel) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Error>>,
+    {
+        let results = join_all(self.models.iter().map(|(_, model)| call(model))).await;
+        EnsembleItem::new(self.answers(results))
+    }
+
+    async fn ask_many<'a, T, F, Fut>(
+        &'a self,
+        texts: Vec<String>,
+        options: &BatchOptions,
+        call: F,
+    ) -> EnsembleReport<T>
+    where
+        T: Eq + Hash + Clone,
+        F: Fn(&'a AnyModel, String) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Error>>,
+    {
+        let text_count = texts.len();
+        let calls: Vec<(usize, String)> = texts
+            .into_iter()
+            .flat_map(|text| (0..self.models.len()).map(move |index| (index, text.clone())))
+            .collect();
+        let mut results = batch::run(calls, options, |(index, text)| {
+            call(&self.models[index].1, text)
+        })
+        .await
+        .into_iter();
+        let items = (0..text_count)
+            .map(|_| {
+                EnsembleItem::new(self.answers(results.by_ref().take(self.models.len()).collect()))
+            })
+            .collect();
+        EnsembleReport::new(&self.model_names(), items)
+    }
+}
@@ -0,0 +1,184 @@
+use std::time::Duration;
+
+use crate::retry::retry_after;
+use crate::BudgetLimit;
+
+/// Everything that can go wrong when calling a primitive.
+///
+/// Transport and API failures keep the status code and body returned by the
+/// provider; decoding failures keep the raw completion so it can be logged or
+/// fed back to the model.
+#[derive(Debug)]
+pub enum Error {
+    /// The API returned a non-success status not covered by a more specific variant.
+    Http { status: u16, body: String },
+    /// The API returned 429. `retry_after` is the delay the server asked for, if any.
+    RateLimited {
+        retry_after: Option<Duration>,
+        body: String,
+    },
+    /// The API rejected the credentials (401 or 403).
+    Auth { status: u16, body: String },
+    /// Credentials could not be obtained before sending the request.
+    Credentials(Box<dyn std::error::Error + Send + Sync>),
+    /// The request did not complete in time.
+    Timeout,
+    /// The request could not be sent or the response could not be read.
+    Transport(reqwest::Error),
+    /// The response was expected to be JSON but is not.
+    MalformedJson {
+        raw: String,
+        serde_error: serde_json::Error,
+    },
+    /// The response is JSON but does not have the expected shape.
+    SchemaMismatch {
+        raw: String,
+        serde_error: serde_json::Error,
+    },
+    /// `classify` answered with a label that is not one of the choices.
+    InvalidChoice { raw: String },
+    /// A score fell outside the requested bounds.
+    OutOfRange {
+        value: f64,
+        min_bound: f64,
+        max_bound: f64,
+    },
+    /// The provider refused to answer because of its safety filters.
+    ContentFiltered { reason: String },
+    /// A streamed response ended before the provider signalled its end, so
+    /// the text received so far may be cut off.
+    IncompleteStream,
+    /// The model's budget is used up, so the request was not sent.
+    BudgetExceeded {
+        limit: BudgetLimit,
+        resets_in: Option<Duration>,
+    },
+    /// The call was cancelled before it completed.
+    Cancelled,
+    /// A batch job, or one request in it, failed without an HTTP response.
+    BatchFailed { message: String },
+    /// A cassette has no recorded response for the request, or could not be
+    /// read or written.
+    Cassette(String),
+}
+
+impl Error {
+    /// Maps a non-success response onto the matching variant, consuming its body.
+    pub(crate) async fn from_response(response: reqwest::Response) -> Error {
+        let status = response.status();
+        let retry_after = retry_after(response.headers());
+        let body = match response.text().await {
+            Ok(body) => body,
+            Err(e) => return Error::from(e),
+        };
+        Error::from_status(status.as_u16(), body, retry_after)
+    }
+
+    /// Maps a non-success status and its body onto the matching variant.
+    pub(crate) fn from_status(status: u16, body: String, retry_after: Option<Duration>) -> Error {
+        match status {
+            401 | 403 => Error::Auth { status, body },
+            429 => Error::RateLimited { retry_after, body },
+            status => Error::Http { status, body },
+        }
+    }
+
+    /// Classifies a serde failure on `raw` as either invalid JSON or a shape mismatch.
+    pub(crate) fn from_serde(raw: String, serde_error: serde_json::Error) -> Error {
+        if serde_error.is_syntax() || serde_error.is_eof() {
+            Error::MalformedJson { raw, serde_error }
+        } else {
+            Error::SchemaMismatch { raw, serde_error }
+        }
+    }
+
+    /// A shape mismatch that was detected by hand rather than by serde.
+    pub(crate) fn schema_mismatch(raw: String, message: &str) -> Error {
+        Error::SchemaMismatch {
+            raw,
+            serde_error: serde::de::Error::custom(message),
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Error::Timeout
+        } else {
+            Error::Transport(e)
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Http { status, body } => write!(f, "HTTP {}: {}", status, body),
+            Error::RateLimited { retry_after, body } => match retry_after {
+                Some(retry_after) => {
+                    write!(f, "rate limited, retry after {:?}: {}", retry_after, body)
+                }
+                None => write!(f, "rate limited: {}", body),
+            },
+            Error::Auth { status, body } => {
+                write!(f, "authentication failed with HTTP {}: {}", status, body)
+            }
+            Error::Credentials(e) => write!(f, "failed to obtain credentials: {}", e),
+            Error::Timeout => write!(f, "request timed out"),
+            Error::Transport(e) => write!(f, "transport error: {}", e),
+            Error::MalformedJson { raw, serde_error } => {
+                write!(f, "malformed JSON ({}): {}", serde_error, raw)
+            }
+            Error::SchemaMismatch { raw, serde_error } => {
+                write!(
+                    f,
+                    "response does not match schema ({}): {}",
+                    serde_error, raw
+                )
+            }
+            Error::InvalidChoice { raw } => write!(f, "invalid classification: {}", raw),
+            Error::OutOfRange {
+                value,
+                min_bound,
+                max_bound,
+            } => write!(
+                f,
+                "score {} is outside [{}, {}]",
+                value, min_bound, max_bound
+            ),
+            Error::ContentFiltered { reason } => {
+                write!(f, "blocked by content filter: {}", reason)
+            }
+            Error::IncompleteStream => write!(f, "stream ended before it was complete"),
+            Error::BudgetExceeded { limit, resets_in } => {
+                let limit = match limit {
+                    BudgetLimit::Tokens => "token",
+                    BudgetLimit::Cost => "cost",
+                    BudgetLimit::Requests => "request",
+                };
+                match resets_in {
+                    Some(resets_in) => {
+                        write!(f, "{} budget exceeded, resets in {:?}", limit, resets_in)
+                    }
+                    None => write!(f, "{} budget exceeded", limit),
+                }
+            }
+            Error::Cancelled => write!(f, "cancelled"),
+            Error::BatchFailed { message } => write!(f, "batch failed: {}", message),
+            Error::Cassette(message) => write!(f, "cassette: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Credentials(e) => Some(e.as_ref()),
+            Error::Transport(e) => Some(e),
+            Error::MalformedJson { serde_error, .. }
+            | Error::SchemaMismatch { serde_error, .. } => Some(serde_error),
+            _ => None,
+        }
+    }
+}
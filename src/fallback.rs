@@ -0,0 +1,472 @@
+use futures::StreamExt;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{
+    AnyModel, CallOptions, ClassifyVote, CostTracker, DynModel, Error, Model, Response, ScoreVote,
+    TextStream, VoteOptions,
+};
+
+/// What happened when a `FallbackModel` tried one of its models, passed to the
+/// `on_attempt` hook.
+#[derive(Debug)]
+pub struct FallbackAttempt<'a> {
+    /// The name the model was added with.
+    pub model: &'a str,
+    pub elapsed: Duration,
+    /// `Ok` if the model served the call, or the error it failed with.
+    pub outcome: Result<(), &'a Error>,
+    /// Whether the call moves on to the next model.
+    pub falls_back: bool,
+}
+
+pub type FallbackHook = Arc<dyn Fn(&FallbackAttempt) + Send + Sync>;
+
+pub type FallbackPredicate = Arc<dyn Fn(&Error) -> bool + Send + Sync>;
+
+/// A `Model` that tries a list of models in order and answers with the first
+/// one that succeeds, e.g. to move from one provider to another during an
+/// outage.
+///
+/// A call moves on to the next model only when the error is one that another
+/// model might not have; see `FallbackModelBuilder::fallback_on`. Any other
+/// error, and the error of the last model, is returned as is. The `model` of a
+/// response is the one reported by the model that served it, and its
+/// `served_by` is the name that model was added with.
+///
+/// With a circuit breaker, a model that keeps failing is skipped for a while
+/// so calls do not wait on it. If every model is being skipped, all of them
+/// are tried anyway.
+///
+/// Usage and cost of the calls it serves are added to its own cost tracker,
+/// on top of the tracker of the model that served them.
+#[derive(Clone)]
+pub struct FallbackModel {
+    models: Vec<(String, AnyModel)>,
+    fallback_on: FallbackPredicate,
+    circuit_breaker: Option<CircuitBreaker>,
+    on_attempt: Option<FallbackHook>,
+    cost_tracker: CostTracker,
+}
+
+pub struct FallbackModelBuilder {
+    models: Vec<(String, AnyModel)>,
+    fallback_on: FallbackPredicate,
+    circuit_breaker: Option<(u32, Duration)>,
+    on_attempt: Option<FallbackHook>,
+    cost_tracker: CostTracker,
+}
+
+#[derive(Clone)]
+struct CircuitBreaker {
+    failure_threshold: u32,
+    cooldown: Duration,
+    states: Arc<Mutex<Vec<CircuitState>>>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct CircuitState {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+}
+
+impl FallbackModelBuilder {
+    pub fn new() -> Self {
+        FallbackModelBuilder {
+            models: vec![],
+            fallback_on: Arc::new(is_fallback_error),
+            circuit_breaker: None,
+            on_attempt: None,
+            cost_tracker: CostTracker::new(),
+        }
+    }
+
+    /// Adds `model` after the ones added so far. `name` identifies it in
+    /// `FallbackAttempt`s.
+    pub fn model<M: DynModel + 'static>(&mut self, name: String, model: M) -> &mut Self {
+        self.models.push((name, AnyModel::new(model)));
+        self
+    }
+
+    /// Moves on to the next model when `fallback_on` returns true for the
+    /// error. By default that is rate limits, timeouts, connection failures,
+    /// 408, 409 and 5xx statuses and exhausted budgets, but not errors about
+    /// the answer itself. Rejected or missing credentials are returned too, so
+    /// a misconfigured model is not hidden behind the next one.
+    pub fn fallback_on<F>(&mut self, fallback_on: F) -> &mut Self
+    where
+        F: Fn(&Error) -> bool + Send + Sync + 'static,
+    {
+        self.fallback_on = Arc::new(fallback_on);
+        self
+    }
+
+    /// Skips a model for `cooldown` once it has failed `failure_threshold`
+    /// calls in a row with errors that fall back. After the cooldown it is
+    /// tried again; one more failure skips it for another cooldown.
+    pub fn circuit_breaker(&mut self, failure_threshold: u32, cooldown: Duration) -> &mut Self {
+        self.circuit_breaker = Some((failure_threshold.max(1), cooldown));
+        self
+    }
+
+    /// Called after every model that was tried, whether it served the call or
+    /// not.
+    pub fn on_attempt<F>(&mut self, on_attempt: F) -> &mut Self
+    where
+        F: Fn(&FallbackAttempt) + Send + Sync + 'static,
+    {
+        self.on_attempt = Some(Arc::new(on_attempt));
+        self
+    }
+
+    /// Accumulates usage and cost of the calls the fallback model serves in
+    /// `cost_tracker`.
+    pub fn cost_tracker(&mut self, cost_tracker: CostTracker) -> &mut Self {
+        self.cost_tracker = cost_tracker;
+        self
+    }
+
+    /// # Panics
+    ///
+    /// If no model was added.
+    pub fn build(&self) -> FallbackModel {
+        assert!(
+            !self.models.is_empty(),
+            "a FallbackModel needs at least one model"
+        );
+        FallbackModel {
+            models: self.models.clone(),
+            fallback_on: self.fallback_on.clone(),
+            circuit_breaker: self.circuit_breaker.map(|(failure_threshold, cooldown)| {
+                CircuitBreaker {
+                    failure_threshold,
+                    cooldown,
+                    states: Arc::new(Mutex::new(vec![CircuitState::default(); self.models.len()])),
+                }
+            }),
+            on_attempt: self.on_attempt.clone(),
+            cost_tracker: self.cost_tracker.clone(),
+        }
+    }
+}
+
+impl Default for FallbackModelBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The default `fallback_on` rule.
+fn is_fallback_error(error: &Error) -> bool {
+    match error {
+        Error::Http { status, .. } => *status == 408 || *status == 409 || *status >= 500,
+        Error::RateLimited { .. }
+        | Error::Timeout
+        | Error::Transport(_)
+        | Error::BudgetExceeded { .. } => true,
+        _ => false,
+    }
+}
+
+impl CircuitBreaker {
+    fn is_open(&self, index: usize, now: Instant) -> bool {
+        self.states.lock().unwrap()[index]
+            .open_until
+            .is_some_and(|open_until| now < open_until)
+    }
+
+    fn record_success(&self, index: usize) {
+        self.states.lock().unwrap()[index] = CircuitState::default();
+    }
+
+    fn record_failure(&self, index: usize) {
+        let mut states = self.states.lock().unwrap();
+        let state = &mut states[index];
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.failure_threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+impl FallbackModel {
+    /// Names of the models, in the order they are tried.
+    pub fn model_names(&self) -> Vec<&str> {
+        self.models.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Whether the circuit breaker is currently skipping the model added as
+    /// `name`.
+    pub fn is_circuit_open(&self, name: &str) -> bool {
+        let Some(circuit_breaker) = &self.circuit_breaker else {
+            return false;
+        };
+        let now = Instant::now();
+        self.models
+            .iter()
+            .position(|(model_name, _)| model_name == name)
+            .is_some_and(|index| circuit_breaker.is_open(index, now))
+    }
+
+    /// Indices of the models to try, in order.
+    fn candidates(&self) -> Vec<usize> {
+        let all: Vec<usize> = (0..self.models.len()).collect();
+        let Some(circuit_breaker) = &self.circuit_breaker else {
+            return all;
+        };
+        let now = Instant::now();
+        let closed: Vec<usize> = all
+            .iter()
+            .copied()
+            .filter(|&index| !circuit_breaker.is_open(index, now))
+            .collect();
+        if closed.is_empty() {
+            all
+        } else {
+            closed
+        }
+    }
+
+    /// Runs `call` on each candidate model until one succeeds or fails with an
+    /// error that does not fall back. Returns the name of the model that
+    /// succeeded with its result.
+    async fn call<'a, T, F, Fut>(&'a self, call: F) -> Result<(&'a str, T), Error>
+    where
+        F: Fn(&'a AnyModel) -> Fut,
+        Fut: Future<Output = Result<T, Error>>,
+    {
+        let candidates = self.candidates();
+        let mut last_error = None;
+        for (position, &index) in candidates.iter().enumerate() {
+            let (name, model) = &self.models[index];
+            let started = Instant::now();
+            let result = call(model).await;
+            let falls_back = match &result {
+                Ok(_) => false,
+                Err(e) => (self.fallback_on)(e),
+            };
+            if let Some(circuit_breaker) = &self.circuit_breaker {
+                match &result {
+                    Ok(_) => circuit_breaker.record_success(index),
+                    Err(_) if falls_back => circuit_breaker.record_failure(index),
+                    Err(_) => {}
+                }
+            }
+            let falls_back = falls_back && position + 1 < candidates.len();
+            if let Some(on_attempt) = &self.on_attempt {
+                on_attempt(&FallbackAttempt {
+                    model: name,
+                    elapsed: started.elapsed(),
+                    outcome: result.as_ref().map(|_| ()),
+                    falls_back,
+                });
+            }
+            match result {
+                Err(e) if falls_back => last_error = Some(e),
+                result => return result.map(|value| (name.as_str(), value)),
+            }
+        }
+        Err(last_error.expect("a FallbackModel has at least one model"))
+    }
+
+    /// Like `call`, for calls that return a `Response`.
+    async fn respond<'a, T, F, Fut>(&'a self, call: F) -> Result<Response<T>, Error>
+    where
+        F: Fn(&'a AnyModel) -> Fut,
+        Fut: Future<Output = Result<Response<T>, Error>>,
+    {
+        let (name, mut response) = self.call(call).await?;
+        if let Some(usage) = response.usage {
+            self.cost_tracker.record(usage, response.cost);
+        }
+        response.served_by = Some(name.to_string());
+        Ok(response)
+    }
+}
+
+impl Model for FallbackModel {
+    fn cost_tracker(&self) -> &CostTracker {
+        &self.cost_tracker
+    }
+
+    async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        self.respond(|model| {
+            model.classify_with_options(instruction.clone(), text.clone(), choices.clone(), options)
+        })
+        .await
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        self.respond(|model| {
+            model.binary_classify_with_options(instruction.clone(), text.clone(), options)
+        })
+        .await
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        self.respond(|model| {
+            model.generate_text_with_options(instruction.clone(), text.clone(), options)
+        })
+        .await
+    }
+
+    /// Falls back only while no model has started streaming; an error in the
+    /// middle of a stream is yielded by the stream.
+    async fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        let (name, stream) = self
+            .call(|model| {
+                model.generate_text_stream_with_options(instruction.clone(), text.clone(), options)
+            })
+            .await?;
+        let name = name.to_string();
+        let cost_tracker = self.cost_tracker.clone();
+        Ok(Box::pin(stream.map(move |delta| {
+            let mut delta = delta?;
+            if let Some(usage) = delta.usage {
+                cost_tracker.record(usage, delta.cost);
+            }
+            delta.served_by = Some(name.clone());
+            Ok(delta)
+        })))
+    }
+
+    async fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        self.respond(|model| {
+            model.score_float_with_options(
+                instruction.clone(),
+                text.clone(),
+                min_bound,
+                max_bound,
+                options,
+            )
+        })
+        .await
+    }
+
+    async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        self.respond(|model| {
+            model.score_int_with_options(
+                instruction.clone(),
+                text.clone(),
+                min_bound,
+                max_bound,
+                options,
+            )
+        })
+        .await
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        self.respond(|model| model.parse_with_options::<T>(text.clone(), options))
+            .await
+    }
+
+    async fn parse_value_with_options(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        self.respond(|model| model.parse_value_with_options(text.clone(), schema.clone(), options))
+            .await
+    }
+
+    async fn classify_vote(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &VoteOptions,
+    ) -> Result<Response<ClassifyVote>, Error> {
+        self.respond(|model| {
+            model.classify_vote(instruction.clone(), text.clone(), choices.clone(), options)
+        })
+        .await
+    }
+
+    async fn score_float_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<f64>>, Error> {
+        self.respond(|model| {
+            model.score_float_vote(
+                instruction.clone(),
+                text.clone(),
+                min_bound,
+                max_bound,
+                options,
+            )
+        })
+        .await
+    }
+
+    async fn score_int_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<i64>>, Error> {
+        self.respond(|model| {
+            model.score_int_vote(
+                instruction.clone(),
+                text.clone(),
+                min_bound,
+                max_bound,
+                options,
+            )
+        })
+        .await
+    }
+}
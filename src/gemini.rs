@@ -0,0 +1,646 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fmt;
+
+use crate::config::{
+    common_builder_methods, credential_builder_methods, CommonConfig, ModelConfig,
+};
+use crate::http::with_timeout;
+use crate::stream::{text_stream, Framing, StreamState};
+use crate::{
+    decode_json, extract_json_object, primitives, CallOptions, ChatModel, CostTracker,
+    CredentialProvider, Error, GenerateMessageOptions, Generation, Message, MessageRole, Model,
+    Response, TextStream, Usage,
+};
+
+pub const GEMINI_API_KEY_NAME: &str = "GEMINI_API_KEY";
+pub const GEMINI_API_BASE: &str = "generativelanguage.googleapis.com/v1beta";
+pub const GEMINI_API_GENERATE_CONTENT_METHOD: &str = ":generateContent";
+pub const GEMINI_API_STREAM_GENERATE_CONTENT_METHOD: &str = ":streamGenerateContent";
+
+/// Finish reasons that mean the candidate was withheld rather than completed.
+const BLOCKED_FINISH_REASONS: [&str; 6] = [
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "IMAGE_SAFETY",
+];
+
+/// Schemas nested deeper than this are cut off, which keeps recursive types
+/// from expanding forever once their `$ref`s are inlined.
+const MAX_SCHEMA_DEPTH: usize = 16;
+
+const SUPPORTED_FORMATS: [&str; 6] = ["int32", "int64", "float", "double", "enum", "date-time"];
+
+pub struct GeminiModel {
+    model: String,
+    credentials: CredentialProvider,
+    base_url: String,
+    config: ModelConfig,
+}
+
+pub struct GeminiModelBuilder {
+    model: String,
+    base_url: String,
+    common: CommonConfig,
+}
+
+impl GeminiModelBuilder {
+    pub fn new(model: String) -> Self {
+        GeminiModelBuilder {
+            model,
+            base_url: format!("https://{}", GEMINI_API_BASE),
+            common: CommonConfig::new(),
+        }
+    }
+
+    credential_builder_methods!("GEMINI_API_KEY");
+
+    /// Full base URL including the scheme, e.g. `http://localhost:8080/v1beta`.
+    pub fn base_url(&mut self, base_url: String) -> &mut Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    common_builder_methods!();
+
+    /// Builds the model.
+    ///
+    /// # Panics
+    ///
+    /// If the credentials are known to be unavailable or the HTTP client cannot
+    /// be created; see `try_build`.
+    pub fn build(&self) -> GeminiModel {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds the model, or fails with `Error::Credentials` if the API key
+    /// comes from an environment variable that is not set or a file that
+    /// cannot be read, or with `Error::Transport` if the HTTP client cannot be
+    /// created.
+    pub fn try_build(&self) -> Result<GeminiModel, Error> {
+        let credentials = self.common.credentials(GEMINI_API_KEY_NAME)?;
+        Ok(GeminiModel {
+            model: self.model.trim_start_matches("models/").to_string(),
+            credentials,
+            base_url: self.base_url.clone(),
+            config: self.common.build()?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GeminiContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(default)]
+    parts: Vec<GeminiPart>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct GeminiPart {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiGenerationConfig {
+    temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_schema: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiRequestBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<GeminiContent>,
+    contents: Vec<GeminiContent>,
+    generation_config: GeminiGenerationConfig,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiResponse {
+    #[serde(default)]
+    candidates: Vec<GeminiCandidate>,
+    prompt_feedback: Option<GeminiPromptFeedback>,
+    usage_metadata: Option<GeminiUsageMetadata>,
+    model_version: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiCandidate {
+    content: Option<GeminiContent>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiPromptFeedback {
+    block_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiUsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
+    #[serde(default)]
+    cached_content_token_count: u64,
+    #[serde(default)]
+    thoughts_token_count: u64,
+}
+
+impl GeminiUsageMetadata {
+    /// Thinking tokens are reported apart from the candidates but billed as
+    /// output, so they are counted in `output_tokens`.
+    fn usage(&self) -> Usage {
+        Usage {
+            input_tokens: self.prompt_token_count,
+            output_tokens: self.candidates_token_count + self.thoughts_token_count,
+            cached_input_tokens: self.cached_content_token_count,
+            reasoning_tokens: self.thoughts_token_count,
+        }
+    }
+}
+
+impl GeminiModel {
+    /// Creates a model that reads its API key from `GEMINI_API_KEY`.
+    ///
+    /// # Panics
+    ///
+    /// If `GEMINI_API_KEY` is not set; `try_new` returns an error instead.
+    pub fn new(model: String) -> Self {
+        GeminiModelBuilder::new(model).build()
+    }
+
+    /// Like `new`, but fails with `Error::Credentials` if `GEMINI_API_KEY` is
+    /// not set.
+    pub fn try_new(model: String) -> Result<Self, Error> {
+        GeminiModelBuilder::new(model).try_build()
+    }
+
+    pub fn with_api_key(model: String, api_key: String) -> Self {
+        GeminiModelBuilder::new(model).api_key(api_key).build()
+    }
+
+    /// Creates a model that sends its requests with an existing client.
+    pub fn with_client(model: String, client: reqwest::Client) -> Self {
+        GeminiModelBuilder::new(model).client(client).build()
+    }
+}
+
+impl GeminiModel {
+    async fn content_request(
+        &self,
+        messages: Vec<Message>,
+        options: &GenerateMessageOptions,
+        method: &str,
+    ) -> Result<reqwest::RequestBuilder, Error> {
+        let api_key = self.credentials.fetch().await?;
+        let url = format!("{}/models/{}{}", self.base_url, self.model, method);
+        let mut system_parts = vec![];
+        let mut contents = vec![];
+        for message in messages {
+            let role = match message.role {
+                MessageRole::System => {
+                    system_parts.push(GeminiPart {
+                        text: message.content,
+                    });
+                    continue;
+                }
+                MessageRole::User => "user",
+                MessageRole::Assistant => "model",
+            };
+            contents.push(GeminiContent {
+                role: Some(String::from(role)),
+                parts: vec![GeminiPart {
+                    text: message.content,
+                }],
+            });
+        }
+        let body = GeminiRequestBody {
+            system_instruction: if system_parts.is_empty() {
+                None
+            } else {
+                Some(GeminiContent {
+                    role: None,
+                    parts: system_parts,
+                })
+            },
+            contents,
+            generation_config: GeminiGenerationConfig {
+                temperature: options.temperature,
+                top_p: options.top_p,
+                max_output_tokens: options.max_tokens,
+                stop_sequences: options.stop.clone(),
+                seed: options.seed,
+                presence_penalty: options.presence_penalty,
+                frequency_penalty: options.frequency_penalty,
+                response_mime_type: options.force_json.then(|| String::from("application/json")),
+                response_schema: options
+                    .json_schema
+                    .as_ref()
+                    .filter(|_| options.force_json)
+                    .map(to_gemini_schema),
+            },
+        };
+        let request = self
+            .config
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .header("x-goog-api-key", api_key)
+            .json(&body);
+        Ok(with_timeout(request, options.timeout))
+    }
+}
+
+/// Decodes one chunk of a streamed response. A blocked finish reason is only
+/// an error if nothing was streamed before it; otherwise it is reported as the
+/// finish reason of a cut-off stream. Gemini has no end-of-stream event, so
+/// the stream is complete once a finish reason arrives.
+fn decode_stream_chunk(
+    payload: &str,
+    state: &mut StreamState,
+    received_text: bool,
+) -> Result<Option<String>, Error> {
+    let chunk = decode_json::<GeminiResponse>(payload.to_string())?;
+    if let Some(block_reason) = chunk
+        .prompt_feedback
+        .and_then(|feedback| feedback.block_reason)
+    {
+        return Err(Error::ContentFiltered {
+            reason: block_reason,
+        });
+    }
+    if let Some(usage) = chunk.usage_metadata {
+        state.usage = Some(usage.usage());
+    }
+    let Some(candidate) = chunk.candidates.into_iter().next() else {
+        return Ok(None);
+    };
+    let text: String = candidate
+        .content
+        .map(|content| content.parts.into_iter().map(|part| part.text).collect())
+        .unwrap_or_default();
+    if let Some(finish_reason) = candidate.finish_reason {
+        if !received_text
+            && text.is_empty()
+            && BLOCKED_FINISH_REASONS.contains(&finish_reason.as_str())
+        {
+            return Err(Error::ContentFiltered {
+                reason: finish_reason,
+            });
+        }
+        state.finish_reason = Some(finish_reason);
+        state.complete = true;
+    }
+    Ok(Some(text))
+}
+
+impl fmt::Debug for GeminiModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GeminiModel")
+            .field("model", &self.model)
+            .field("base_url", &self.base_url)
+            .field("credentials", &self.credentials)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ChatModel for GeminiModel {
+    async fn generate_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<Generation, Error> {
+        let request = self
+            .content_request(messages, &options, GEMINI_API_GENERATE_CONTENT_METHOD)
+            .await?;
+        let response = self.config.retry_policy.send(request).await?;
+        let headers = response.headers().clone();
+        let raw = response.text().await?;
+        let gemini_response = decode_json::<GeminiResponse>(raw.clone())?;
+        if let Some(block_reason) = gemini_response
+            .prompt_feedback
+            .and_then(|feedback| feedback.block_reason)
+        {
+            return Err(Error::ContentFiltered {
+                reason: block_reason,
+            });
+        }
+        let Some(candidate) = gemini_response.candidates.into_iter().next() else {
+            return Err(Error::schema_mismatch(raw, "no candidates in response"));
+        };
+        let content: String = candidate
+            .content
+            .map(|content| content.parts.into_iter().map(|part| part.text).collect())
+            .unwrap_or_default();
+        if let Some(finish_reason) = candidate.finish_reason {
+            if content.is_empty() && BLOCKED_FINISH_REASONS.contains(&finish_reason.as_str()) {
+                return Err(Error::ContentFiltered {
+                    reason: finish_reason,
+                });
+            }
+        }
+        let obj = if options.force_json {
+            Some(extract_json_object(&content)?)
+        } else {
+            None
+        };
+        Ok(Generation {
+            message: Message {
+                role: MessageRole::Assistant,
+                content,
+                obj,
+            },
+            usage: gemini_response
+                .usage_metadata
+                .as_ref()
+                .map(GeminiUsageMetadata::usage),
+            model: gemini_response.model_version,
+            headers,
+            alternatives: vec![],
+        })
+    }
+
+    fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    fn model_name(&self) -> &str {
+        &self.model
+    }
+
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn stream_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<TextStream, Error> {
+        let request = self
+            .content_request(
+                messages,
+                &options,
+                GEMINI_API_STREAM_GENERATE_CONTENT_METHOD,
+            )
+            .await?
+            .query(&[("alt", "sse")]);
+        let response = self.config.retry_policy.send(request).await?;
+        let mut received_text = false;
+        Ok(text_stream(
+            response,
+            Framing::ServerSentEvents,
+            move |payload, state| {
+                let text = decode_stream_chunk(payload, state, received_text)?;
+                received_text |= text.as_ref().is_some_and(|text| !text.is_empty());
+                Ok(text)
+            },
+        ))
+    }
+}
+
+impl Model for GeminiModel {
+    fn cost_tracker(&self) -> &CostTracker {
+        &self.config.cost_tracker
+    }
+
+    async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        primitives::classify(self, instruction, text, choices, options).await
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        primitives::binary_classify(self, instruction, text, options).await
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        primitives::generate_text(self, instruction, text, options).await
+    }
+
+    async fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        primitives::generate_text_stream(self, instruction, text, options).await
+    }
+
+    async fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        primitives::score_float(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        primitives::score_int(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        primitives::parse(self, text, options).await
+    }
+
+    async fn parse_value_with_options(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        primitives::parse_value(self, text, schema, options).await
+    }
+}
+
+/// Rewrites a schemars JSON schema into the OpenAPI subset accepted by
+/// Gemini's `responseSchema`: local `$ref`s, whether into `definitions` or
+/// `$defs`, are inlined, `null` unions become `nullable`, and keywords Gemini
+/// rejects are dropped.
+fn to_gemini_schema(schema: &Value) -> Value {
+    convert_schema(schema, schema, 0)
+}
+
+/// Converts `schema`, resolving `$ref`s as JSON pointers into `root`.
+fn convert_schema(schema: &Value, root: &Value, depth: usize) -> Value {
+    let Value::Object(obj) = schema else {
+        return Value::Object(Map::new());
+    };
+    if depth > MAX_SCHEMA_DEPTH {
+        return serde_json::json!({ "type": "OBJECT" });
+    }
+    if let Some(reference) = obj.get("$ref").and_then(Value::as_str) {
+        let definition = reference
+            .strip_prefix('#')
+            .and_then(|pointer| root.pointer(pointer));
+        let mut resolved = match definition {
+            Some(definition) => convert_schema(definition, root, depth + 1),
+            None => Value::Object(Map::new()),
+        };
+        if let (Some(description), Value::Object(resolved)) =
+            (obj.get("description"), &mut resolved)
+        {
+            resolved.insert(String::from("description"), description.clone());
+        }
+        return resolved;
+    }
+
+    let mut converted = Map::new();
+    for (key, value) in obj {
+        match key.as_str() {
+            "type" => match value {
+                Value::String(schema_type) => {
+                    converted.insert(key.clone(), Value::String(schema_type.to_uppercase()));
+                }
+                Value::Array(schema_types) => {
+                    let mut nullable = false;
+                    for schema_type in schema_types.iter().filter_map(Value::as_str) {
+                        if schema_type == "null" {
+                            nullable = true;
+                        } else if !converted.contains_key("type") {
+                            converted
+                                .insert(key.clone(), Value::String(schema_type.to_uppercase()));
+                        }
+                    }
+                    if nullable {
+                        converted.insert(String::from("nullable"), Value::Bool(true));
+                    }
+                }
+                _ => {}
+            },
+            "properties" => {
+                let properties = value
+                    .as_object()
+                    .map(|properties| {
+                        properties
+                            .iter()
+                            .map(|(name, property)| {
+                                (name.clone(), convert_schema(property, root, depth + 1))
+                            })
+                            .collect::<Map<String, Value>>()
+                    })
+                    .unwrap_or_default();
+                converted.insert(key.clone(), Value::Object(properties));
+            }
+            "items" => {
+                converted.insert(key.clone(), convert_schema(value, root, depth + 1));
+            }
+            "allOf" | "anyOf" | "oneOf" => {
+                let all_variants = value.as_array().cloned().unwrap_or_default();
+                let variants: Vec<&Value> = all_variants
+                    .iter()
+                    .filter(|variant| variant.get("type") != Some(&Value::from("null")))
+                    .collect();
+                let nullable = variants.len() != all_variants.len();
+                if variants.len() == 1 {
+                    if let Value::Object(variant) = convert_schema(variants[0], root, depth + 1) {
+                        for (variant_key, variant_value) in variant {
+                            converted.entry(variant_key).or_insert(variant_value);
+                        }
+                    }
+                } else if !variants.is_empty() {
+                    converted.insert(
+                        String::from("anyOf"),
+                        Value::Array(
+                            variants
+                                .into_iter()
+                                .map(|variant| convert_schema(variant, root, depth + 1))
+                                .collect(),
+                        ),
+                    );
+                }
+                if nullable {
+                    converted.insert(String::from("nullable"), Value::Bool(true));
+                }
+            }
+            "format"
+                if value
+                    .as_str()
+                    .is_some_and(|format| SUPPORTED_FORMATS.contains(&format)) =>
+            {
+                converted.insert(key.clone(), value.clone());
+            }
+            "description" | "required" | "nullable" | "minItems" | "maxItems" | "minimum"
+            | "maximum" => {
+                converted.insert(key.clone(), value.clone());
+            }
+            _ => {}
+        }
+    }
+    // Gemini only accepts enums of strings, so an enum is kept only on a
+    // string schema. Without a type, an enum of strings implies one.
+    if let Some(values) = obj.get("enum").and_then(Value::as_array) {
+        let values: Vec<Value> = values
+            .iter()
+            .filter(|value| !value.is_null())
+            .cloned()
+            .collect();
+        if !values.is_empty() && values.iter().all(Value::is_string) {
+            let schema_type = converted
+                .entry(String::from("type"))
+                .or_insert_with(|| Value::from("STRING"));
+            if schema_type == "STRING" {
+                converted.insert(String::from("enum"), Value::Array(values));
+            }
+        }
+    }
+    Value::Object(converted)
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use crate::Error;
+
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(600);
+const DEFAULT_USER_AGENT: &str = concat!("llm-primitives/", env!("CARGO_PKG_VERSION"));
+
+/// Settings for the HTTP client a model creates for itself.
+///
+/// The client is created once per model and reused for every request, so
+/// connections and TLS sessions are pooled. To share one pool across several
+/// models, build a `reqwest::Client` yourself and pass it to each builder's
+/// `client` instead.
+#[derive(Clone)]
+pub struct HttpConfig {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxy: Option<reqwest::Proxy>,
+    http2_prior_knowledge: bool,
+    user_agent: String,
+}
+
+pub struct HttpConfigBuilder {
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxy: Option<reqwest::Proxy>,
+    http2_prior_knowledge: bool,
+    user_agent: String,
+}
+
+impl HttpConfigBuilder {
+    pub fn new() -> Self {
+        HttpConfigBuilder {
+            connect_timeout: Some(DEFAULT_CONNECT_TIMEOUT),
+            timeout: Some(DEFAULT_TIMEOUT),
+            proxy: None,
+            http2_prior_knowledge: false,
+            user_agent: String::from(DEFAULT_USER_AGENT),
+        }
+    }
+
+    /// Time allowed to establish a connection. Defaults to 10 seconds.
+    pub fn connect_timeout(&mut self, connect_timeout: Option<Duration>) -> &mut Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+
+    /// Time allowed for a whole request, including reading the response body
+    /// of a stream. Defaults to 10 minutes.
+    pub fn timeout(&mut self, timeout: Option<Duration>) -> &mut Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Routes requests through `proxy`. Without one, the `HTTP_PROXY` and
+    /// `HTTPS_PROXY` environment variables are respected.
+    pub fn proxy(&mut self, proxy: reqwest::Proxy) -> &mut Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Speaks HTTP/2 without negotiating it first. HTTPS endpoints negotiate
+    /// HTTP/2 on their own; this is only needed for plain-text HTTP/2 servers.
+    pub fn http2_prior_knowledge(&mut self, http2_prior_knowledge: bool) -> &mut Self {
+        self.http2_prior_knowledge = http2_prior_knowledge;
+        self
+    }
+
+    pub fn user_agent(&mut self, user_agent: String) -> &mut Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    pub fn build(&self) -> HttpConfig {
+        HttpConfig {
+            connect_timeout: self.connect_timeout,
+            timeout: self.timeout,
+            proxy: self.proxy.clone(),
+            http2_prior_knowledge: self.http2_prior_knowledge,
+            user_agent: self.user_agent.clone(),
+        }
+    }
+}
+
+impl Default for HttpConfigBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HttpConfig {
+    /// Creates a client with these settings, or fails with `Error::Transport`
+    /// if e.g. the TLS backend cannot be initialized.
+    pub(crate) fn client(&self) -> Result<reqwest::Client, Error> {
+        let mut builder = reqwest::Client::builder().user_agent(&self.user_agent);
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        if self.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        Ok(builder.build()?)
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfigBuilder::new().build()
+    }
+}
+
+/// Applies a per-call timeout, if any, on top of the client's.
+pub(crate) fn with_timeout(
+    request: reqwest::RequestBuilder,
+    timeout: Option<Duration>,
+) -> reqwest::RequestBuilder {
+    match timeout {
+        Some(timeout) => request.timeout(timeout),
+        None => request,
+    }
+}
+
+/// The id a provider assigned to a request, from whichever header it uses.
+pub(crate) fn request_id(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    ["x-request-id", "request-id", "apim-request-id"]
+        .iter()
+        .find_map(|name| headers.get(*name))
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+}
@@ -1,34 +1,251 @@
 use async_trait::async_trait;
+use reqwest::header::HeaderMap;
 use schemars::{schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
-use serde_json::{to_string, to_string_pretty, Map, Value};
+use serde_json::{json, to_string, to_string_pretty, Map, Value};
+use std::collections::HashMap;
 use std::future::Future;
-use std::{collections::HashMap, fmt::Error};
-
-pub const OPENAI_API_KEY_NAME: &'static str = "OPENAI_API_KEY";
-pub const OPENAI_API_BASE: &'static str = "api.openai.com/v1";
-pub const OPENAI_API_CHAT_ENDPOINT: &'static str = "/chat/completions";
+use std::time::{Duration, Instant};
+
+mod anthropic;
+mod azure;
+mod batch;
+mod budget;
+mod cache;
+mod cassette;
+mod config;
+mod cost;
+mod credentials;
+mod dyn_model;
+mod ensemble;
+mod error;
+mod fallback;
+mod gemini;
+mod http;
+mod mock;
+mod ollama;
+mod openai;
+mod openai_batch;
+mod options;
+mod primitives;
+mod rate_limit;
+mod response;
+mod retry;
+mod router;
+mod schema;
+mod stream;
+mod vote;
+
+pub use anthropic::{
+    AnthropicModel, AnthropicModelBuilder, ANTHROPIC_API_BASE, ANTHROPIC_API_KEY_NAME,
+    ANTHROPIC_API_MESSAGES_ENDPOINT, ANTHROPIC_API_VERSION,
+};
+pub use azure::{
+    AzureOpenAIModel, AzureOpenAIModelBuilder, AZURE_OPENAI_API_KEY_NAME, AZURE_OPENAI_API_VERSION,
+};
+pub use batch::{BatchOptions, BatchOptionsBuilder, BatchProgress, CancelToken, ProgressHook};
+pub use budget::{Budget, BudgetBuilder, BudgetLimit, BudgetRemaining};
+pub use cache::{
+    Cache, CacheBuilder, CacheEntry, CacheStats, CacheStore, DiskCacheStore, MemoryCacheStore,
+};
+pub use cassette::{Cassette, CassetteMode};
+pub use cost::{CostTotals, CostTracker, Price, PriceTable};
+pub use credentials::{CredentialFuture, CredentialProvider};
+pub use dyn_model::{AnyModel, DynModel};
+pub use ensemble::{
+    cohen_kappa, fleiss_kappa, EnsembleItem, EnsembleModel, EnsembleModelBuilder, EnsembleReport,
+    ModelAnswer, PairwiseKappa,
+};
+pub use error::Error;
+pub use fallback::{
+    FallbackAttempt, FallbackHook, FallbackModel, FallbackModelBuilder, FallbackPredicate,
+};
+pub use gemini::{
+    GeminiModel, GeminiModelBuilder, GEMINI_API_BASE, GEMINI_API_GENERATE_CONTENT_METHOD,
+    GEMINI_API_KEY_NAME, GEMINI_API_STREAM_GENERATE_CONTENT_METHOD,
+};
+pub use http::{HttpConfig, HttpConfigBuilder};
+pub use mock::{MockCall, MockModel};
+pub use ollama::{
+    OllamaModel, OllamaModelBuilder, OLLAMA_API_BASE, OLLAMA_API_CHAT_ENDPOINT,
+    OLLAMA_API_PULL_ENDPOINT, OLLAMA_API_TAGS_ENDPOINT,
+};
+pub use openai::{
+    OpenAIModel, OpenAIModelBuilder, OPENAI_API_BASE, OPENAI_API_CHAT_ENDPOINT, OPENAI_API_KEY_NAME,
+};
+pub use openai_batch::{
+    OpenAIBatch, OpenAIBatchRequestCounts, OpenAIBatchRequests, OpenAIBatchStatus,
+    OPENAI_API_BATCHES_ENDPOINT, OPENAI_API_BATCH_CHAT_ENDPOINT, OPENAI_API_FILES_ENDPOINT,
+};
+pub use options::{CallOptions, CallOptionsBuilder};
+pub use rate_limit::{RateLimiter, RateLimiterBuilder};
+pub use response::{RepairAttempt, Response, Usage};
+pub use retry::{Attempt, AttemptHook, RetryPolicy, RetryPolicyBuilder};
+pub use router::{
+    Primitive, RouteMetrics, RoutePredicate, RouteRequest, RouteRule, RouterModel,
+    RouterModelBuilder,
+};
+pub use stream::{TextDelta, TextStream};
+pub use vote::{ClassifyVote, ScoreVote, VoteOptions, VoteOptionsBuilder};
+
+use config::ModelConfig;
 
 #[async_trait]
 pub trait Model {
+    /// Usage and cost accumulated by this model. Shared with every other model
+    /// that was built with the same tracker.
+    ///
+    /// The default is a tracker nothing records to, for models that do not
+    /// account for their usage.
+    fn cost_tracker(&self) -> &CostTracker {
+        CostTracker::untracked()
+    }
+
+    fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<usize>, Error>> + Send;
+
+    fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<bool>, Error>> + Send;
+
+    fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<String>, Error>> + Send;
+
+    /// Like `generate_text_with_options`, but yields the completion while it
+    /// is being generated. Request failures are returned before the stream
+    /// starts.
+    fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> impl Future<Output = Result<TextStream, Error>> + Send;
+
+    fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<f64>, Error>> + Send;
+
+    fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<i64>, Error>> + Send;
+
+    fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<T>, Error>> + Send
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema;
+
+    /// Like `parse_with_options`, for a JSON schema that is only known at
+    /// runtime. The value is the JSON object the model answered with, checked
+    /// against `schema`; an answer that does not fit fails with
+    /// `Error::SchemaMismatch`, or is repaired like any other if repairs are
+    /// enabled.
+    ///
+    /// The default asks for any JSON object and checks it against `schema`
+    /// afterwards, without repairs. The built-in models send `schema` along.
+    fn parse_value_with_options(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<Value>, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let response = self
+                .parse_with_options::<Map<String, Value>>(text, options)
+                .await?;
+            let value = Value::Object(response.value.clone());
+            schema::check(&value, &schema)
+                .map_err(|problem| Error::schema_mismatch(value.to_string(), &problem))?;
+            Ok(response.map(|_| value))
+        }
+    }
+
     fn classify(
         &self,
         instruction: String,
         text: String,
         choices: Vec<String>,
-    ) -> impl Future<Output = Result<usize, ClassifyError>> + Send;
+    ) -> impl Future<Output = Result<usize, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            self.classify_with_options(instruction, text, choices, &CallOptions::default())
+                .await
+                .map(|response| response.value)
+        }
+    }
 
     fn binary_classify(
         &self,
         instruction: String,
         text: String,
-    ) -> impl Future<Output = Result<bool, ClassifyError>> + Send;
+    ) -> impl Future<Output = Result<bool, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            self.binary_classify_with_options(instruction, text, &CallOptions::default())
+                .await
+                .map(|response| response.value)
+        }
+    }
 
     fn generate_text(
         &self,
         instruction: String,
         text: String,
-    ) -> impl Future<Output = Result<String, GenerateTextError>> + Send;
+    ) -> impl Future<Output = Result<String, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            self.generate_text_with_options(instruction, text, &CallOptions::default())
+                .await
+                .map(|response| response.value)
+        }
+    }
+
+    fn generate_text_stream(
+        &self,
+        instruction: String,
+        text: String,
+    ) -> impl Future<Output = Result<TextStream, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            self.generate_text_stream_with_options(instruction, text, &CallOptions::default())
+                .await
+        }
+    }
 
     fn score_float(
         &self,
@@ -36,7 +253,22 @@ pub trait Model {
         text: String,
         min_bound: f64,
         max_bound: f64,
-    ) -> impl Future<Output = Result<f64, ScoreFloatError>> + Send;
+    ) -> impl Future<Output = Result<f64, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            self.score_float_with_options(
+                instruction,
+                text,
+                min_bound,
+                max_bound,
+                &CallOptions::default(),
+            )
+            .await
+            .map(|response| response.value)
+        }
+    }
 
     fn score_int(
         &self,
@@ -44,16 +276,297 @@ pub trait Model {
         text: String,
         min_bound: i64,
         max_bound: i64,
-    ) -> impl Future<Output = Result<i64, ScoreIntError>> + Send;
+    ) -> impl Future<Output = Result<i64, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            self.score_int_with_options(
+                instruction,
+                text,
+                min_bound,
+                max_bound,
+                &CallOptions::default(),
+            )
+            .await
+            .map(|response| response.value)
+        }
+    }
 
-    fn parse<T>(&self, text: String) -> impl Future<Output = Result<T, ParseError>> + Send
+    fn parse<T>(&self, text: String) -> impl Future<Output = Result<T, Error>> + Send
     where
-        T: for<'de> Deserialize<'de> + JsonSchema;
+        T: for<'de> Deserialize<'de> + JsonSchema,
+        Self: Sync,
+    {
+        async move {
+            self.parse_with_options::<T>(text, &CallOptions::default())
+                .await
+                .map(|response| response.value)
+        }
+    }
+
+    fn parse_value(
+        &self,
+        text: String,
+        schema: Value,
+    ) -> impl Future<Output = Result<Value, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            self.parse_value_with_options(text, schema, &CallOptions::default())
+                .await
+                .map(|response| response.value)
+        }
+    }
+
+    /// Classifies every text in `texts` with at most `options.concurrency`
+    /// requests in flight. Results are in the same order as `texts`, and an
+    /// item that fails does not affect the others.
+    fn classify_many<I>(
+        &self,
+        instruction: String,
+        texts: I,
+        choices: Vec<String>,
+        options: &BatchOptions,
+    ) -> impl Future<Output = Vec<Result<Response<usize>, Error>>> + Send
+    where
+        I: IntoIterator<Item = String>,
+        Self: Sync,
+    {
+        let texts: Vec<String> = texts.into_iter().collect();
+        async move {
+            batch::run(texts, options, |text| {
+                self.classify_with_options(
+                    instruction.clone(),
+                    text,
+                    choices.clone(),
+                    options.call_options(),
+                )
+            })
+            .await
+        }
+    }
+
+    /// Like `classify_many`, for `score_float`.
+    fn score_float_many<I>(
+        &self,
+        instruction: String,
+        texts: I,
+        min_bound: f64,
+        max_bound: f64,
+        options: &BatchOptions,
+    ) -> impl Future<Output = Vec<Result<Response<f64>, Error>>> + Send
+    where
+        I: IntoIterator<Item = String>,
+        Self: Sync,
+    {
+        let texts: Vec<String> = texts.into_iter().collect();
+        async move {
+            batch::run(texts, options, |text| {
+                self.score_float_with_options(
+                    instruction.clone(),
+                    text,
+                    min_bound,
+                    max_bound,
+                    options.call_options(),
+                )
+            })
+            .await
+        }
+    }
+
+    /// Like `classify_many`, for `score_int`.
+    fn score_int_many<I>(
+        &self,
+        instruction: String,
+        texts: I,
+        min_bound: i64,
+        max_bound: i64,
+        options: &BatchOptions,
+    ) -> impl Future<Output = Vec<Result<Response<i64>, Error>>> + Send
+    where
+        I: IntoIterator<Item = String>,
+        Self: Sync,
+    {
+        let texts: Vec<String> = texts.into_iter().collect();
+        async move {
+            batch::run(texts, options, |text| {
+                self.score_int_with_options(
+                    instruction.clone(),
+                    text,
+                    min_bound,
+                    max_bound,
+                    options.call_options(),
+                )
+            })
+            .await
+        }
+    }
+
+    /// Like `classify_many`, for `parse`.
+    fn parse_many<T, I>(
+        &self,
+        texts: I,
+        options: &BatchOptions,
+    ) -> impl Future<Output = Vec<Result<Response<T>, Error>>> + Send
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema + Send,
+        I: IntoIterator<Item = String>,
+        Self: Sync,
+    {
+        let texts: Vec<String> = texts.into_iter().collect();
+        async move {
+            batch::run(texts, options, |text| {
+                self.parse_with_options::<T>(text, options.call_options())
+            })
+            .await
+        }
+    }
+
+    /// Samples `classify` `options.samples` times at a raised temperature and
+    /// returns the choice most samples agreed on, with the votes as a measure
+    /// of confidence. Samples that fail only lose their vote; the call fails
+    /// if every sample does.
+    ///
+    /// Backends that can return several completions per request ask for all
+    /// samples at once; others make one call per sample. Usage and cost cover
+    /// every sample.
+    fn classify_vote(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &VoteOptions,
+    ) -> impl Future<Output = Result<Response<ClassifyVote>, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let started = Instant::now();
+            let sample_options = options.sample_options();
+            let samples = vote::sample(options.samples(), options, || {
+                self.classify_with_options(
+                    instruction.clone(),
+                    text.clone(),
+                    choices.clone(),
+                    &sample_options,
+                )
+            })
+            .await;
+            vote::tally_choices(samples, choices.len(), started)
+        }
+    }
+
+    /// Like `classify_vote`, for `score_float`. Returns the mean, median and
+    /// spread of the scores.
+    fn score_float_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &VoteOptions,
+    ) -> impl Future<Output = Result<Response<ScoreVote<f64>>, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let started = Instant::now();
+            let sample_options = options.sample_options();
+            let samples = vote::sample(options.samples(), options, || {
+                self.score_float_with_options(
+                    instruction.clone(),
+                    text.clone(),
+                    min_bound,
+                    max_bound,
+                    &sample_options,
+                )
+            })
+            .await;
+            vote::summarize_scores(samples, started)
+        }
+    }
+
+    /// Like `score_float_vote`, for `score_int`.
+    fn score_int_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &VoteOptions,
+    ) -> impl Future<Output = Result<Response<ScoreVote<i64>>, Error>> + Send
+    where
+        Self: Sync,
+    {
+        async move {
+            let started = Instant::now();
+            let sample_options = options.sample_options();
+            let samples = vote::sample(options.samples(), options, || {
+                self.score_int_with_options(
+                    instruction.clone(),
+                    text.clone(),
+                    min_bound,
+                    max_bound,
+                    &sample_options,
+                )
+            })
+            .await;
+            vote::summarize_scores(samples, started)
+        }
+    }
 }
 
-pub struct OpenAIModel {
-    model: String,
-    api_key: String,
+/// A backend that can turn a list of messages into a single assistant message.
+///
+/// The primitives in `Model` are built on top of this, so a new provider only
+/// needs to speak its own wire format here.
+trait ChatModel: Model + Sync {
+    fn generate_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> impl Future<Output = Result<Generation, Error>> + Send;
+
+    /// The configured model name, used for pricing when the provider does not
+    /// report which model answered.
+    fn model_name(&self) -> &str;
+
+    /// Where requests are sent. Part of cache keys, since the same model name
+    /// can be served by different deployments.
+    fn base_url(&self) -> &str;
+
+    /// The settings the model was built with.
+    fn config(&self) -> &ModelConfig;
+
+    /// Options applied to every call before the per-call ones.
+    fn default_options(&self) -> &CallOptions {
+        &self.config().default_options
+    }
+
+    fn price_table(&self) -> &PriceTable {
+        &self.config().price_table
+    }
+
+    fn budget(&self) -> Option<&Budget> {
+        self.config().budget.as_ref()
+    }
+
+    fn rate_limiter(&self) -> Option<&RateLimiter> {
+        self.config().rate_limiter.as_ref()
+    }
+
+    fn cache(&self) -> Option<&Cache> {
+        self.config().cache.as_ref()
+    }
+
+    /// Streams the text of the next assistant message.
+    fn stream_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> impl Future<Output = Result<TextStream, Error>> + Send;
 }
 
 #[derive(Debug, Clone)]
@@ -98,441 +611,99 @@ struct Message {
     obj: Option<Map<String, Value>>,
 }
 
-#[derive(Debug, Serialize, Clone)]
-struct OpenAIMessage {
-    role: MessageRole,
-    content: String,
-}
-
-impl<'de> Deserialize<'de> for OpenAIMessage {
-    fn deserialize<D>(deserializer: D) -> Result<OpenAIMessage, D::Error>
-    where
-        D: serde::de::Deserializer<'de>,
-    {
-        let obj = Map::<String, Value>::deserialize(deserializer)?;
-        let role = obj
-            .get("role")
-            .ok_or(serde::de::Error::custom("role not found"))?;
-        let content = obj
-            .get("content")
-            .ok_or(serde::de::Error::custom("content not found"))?;
-        Ok(OpenAIMessage {
-            role: serde_json::from_value(role.clone()).unwrap(),
-            content: serde_json::from_value(content.clone()).unwrap(),
-        })
-    }
+/// An assistant message together with what the provider reported about the
+/// request that produced it.
+struct Generation {
+    message: Message,
+    usage: Option<Usage>,
+    /// The model that answered, if the provider says.
+    model: Option<String>,
+    headers: HeaderMap,
+    /// The other completions, when more than one was asked for with `n`.
+    alternatives: Vec<Message>,
 }
 
+#[derive(Clone)]
 struct GenerateMessageOptions {
     temperature: f64,
     force_json: bool,
+    json_schema: Option<Value>,
+    top_p: Option<f64>,
+    max_tokens: Option<u32>,
+    stop: Option<Vec<String>>,
+    seed: Option<u64>,
+    presence_penalty: Option<f64>,
+    frequency_penalty: Option<f64>,
+    logit_bias: Option<HashMap<u32, i32>>,
+    user: Option<String>,
+    timeout: Option<Duration>,
+    /// How many completions to generate, for backends that can return more
+    /// than one per request. Others ignore it.
+    n: Option<u32>,
 }
 
 struct GenerateMessageOptionsBuilder {
-    temperature: f64,
-    force_json: bool,
+    options: GenerateMessageOptions,
 }
 
 impl GenerateMessageOptionsBuilder {
     pub fn new() -> Self {
         GenerateMessageOptionsBuilder {
-            temperature: 0.0,
-            force_json: false,
+            options: GenerateMessageOptions {
+                temperature: 0.0,
+                force_json: false,
+                json_schema: None,
+                top_p: None,
+                max_tokens: None,
+                stop: None,
+                seed: None,
+                presence_penalty: None,
+                frequency_penalty: None,
+                logit_bias: None,
+                user: None,
+                timeout: None,
+                n: None,
+            },
         }
     }
 
     pub fn temperature(&mut self, temperature: f64) -> &mut Self {
-        self.temperature = temperature;
+        self.options.temperature = temperature;
         self
     }
 
     pub fn force_json(&mut self, force_json: bool) -> &mut Self {
-        self.force_json = force_json;
+        self.options.force_json = force_json;
         self
     }
 
-    pub fn build(&self) -> GenerateMessageOptions {
-        GenerateMessageOptions {
-            temperature: self.temperature,
-            force_json: self.force_json,
-        }
-    }
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ChatRequestBody {
-    model: String,
-    messages: Vec<OpenAIMessage>,
-    temperature: f64,
-    response_format: ResponseFormat,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ResponseFormat {
-    r#type: ResponseFormatType,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-enum ResponseFormatType {
-    JsonObject,
-    Text,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ChatResponse {
-    choices: Vec<Choice>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct Choice {
-    message: OpenAIMessage,
-}
-
-impl OpenAIModel {
-    pub fn new(model: String) -> Self {
-        if let Ok(api_key) = std::env::var(OPENAI_API_KEY_NAME) {
-            OpenAIModel { model, api_key }
-        } else {
-            panic!("{} not found in environment variables", OPENAI_API_KEY_NAME);
-        }
-    }
-
-    async fn generate_message(
-        &self,
-        messages: Vec<Message>,
-        options: GenerateMessageOptions,
-    ) -> Result<Message, ChatError> {
-        let url = format!("https://{}{}", OPENAI_API_BASE, OPENAI_API_CHAT_ENDPOINT);
-        let client = reqwest::Client::new();
-        let response_format_type = if options.force_json {
-            ResponseFormatType::JsonObject
-        } else {
-            ResponseFormatType::Text
-        };
-        let openai_messages: Vec<OpenAIMessage> = messages
-            .iter()
-            .map(|message| OpenAIMessage {
-                role: message.role.clone(),
-                content: message.content.clone(),
-            })
-            .collect();
-        let body = ChatRequestBody {
-            model: self.model.clone(),
-            messages: openai_messages,
-            temperature: options.temperature,
-            response_format: ResponseFormat {
-                r#type: response_format_type,
-            },
-        };
-        let response = client
-            .post(url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&body)
-            .send()
-            .await;
-        match response {
-            Ok(response) => {
-                if response.status() != reqwest::StatusCode::OK {
-                    return Err(ChatError {
-                        message: format!(
-                            "{}: {}",
-                            response.status(),
-                            response.text().await.unwrap()
-                        ),
-                    });
-                }
-                match response.json::<ChatResponse>().await {
-                    Ok(chat_response) => {
-                        if let Some(choice) = chat_response.choices.first() {
-                            let message = choice.message.clone();
-                            if options.force_json {
-                                let obj =
-                                    serde_json::from_str::<Map<String, Value>>(&message.content);
-                                if let Ok(obj) = obj {
-                                    return Ok(Message {
-                                        role: message.role,
-                                        content: message.content,
-                                        obj: Some(obj),
-                                    });
-                                } else {
-                                    return Err(ChatError {
-                                        message: String::from("Failed to parse response"),
-                                    });
-                                }
-                            } else {
-                                return Ok(Message {
-                                    role: message.role,
-                                    content: message.content,
-                                    obj: None,
-                                });
-                            }
-                        } else {
-                            return Err(ChatError {
-                                message: String::from("Choice not found in response"),
-                            });
-                        }
-                    }
-                    Err(e) => {
-                        return Err(ChatError {
-                            message: String::from("Failed to parse response, error: ")
-                                + &e.to_string(),
-                        });
-                    }
-                }
-            }
-            Err(e) => {
-                return Err(ChatError {
-                    message: format!("{}", e),
-                });
-            }
-        };
-    }
-}
-
-impl Model for OpenAIModel {
-    async fn classify(
-        &self,
-        instruction: String,
-        text: String,
-        choices: Vec<String>,
-    ) -> Result<usize, ClassifyError> {
-        let (choices_display, lookup_table) = display_choices(choices);
-        let input_text = format!(
-            "Instruction:\n{}\n\nText:\n{}\n\nChoices:\n{}\n\nValid JSON:",
-            instruction, text, choices_display
-        );
-        let messages = vec![
-            Message {
-                role: MessageRole::System,
-                content: String::from("Classify the following text with the provided instruction and choices. To classify, provide the key of the choice:\n{\"classification\": string}\n\nFor example, if the correct choice is 'Z. description of choice Z', then provide 'Z' as the classification as valid JSON:\n{\"classification\": \"Z\"}"),
-                obj: None,
-            },
-            Message {
-                role: MessageRole::User,
-                content: input_text,
-                obj: None,
-            },
-        ];
-        let options = GenerateMessageOptionsBuilder::new()
-            .temperature(0.0)
-            .force_json(true)
-            .build();
-        if let Ok(message) = self.generate_message(messages, options).await {
-            if let Some(obj) = message.obj {
-                if let Some(classification) = obj["classification"].as_str() {
-                    if let Some(choice_index) = lookup_table.get(classification) {
-                        return Ok(*choice_index);
-                    } else {
-                        return Err(ClassifyError {
-                            message: format!("Invalid classification: {}", classification),
-                        });
-                    }
-                } else {
-                    return Err(ClassifyError {
-                        message: String::from("Classification not found in response"),
-                    });
-                }
-            } else {
-                return Err(ClassifyError {
-                    message: String::from("Object not found in response"),
-                });
-            }
-        } else {
-            return Err(ClassifyError {
-                message: String::from("Failed to generate message"),
-            });
-        }
-    }
-
-    async fn binary_classify(
-        &self,
-        instruction: String,
-        text: String,
-    ) -> Result<bool, ClassifyError> {
-        self.classify(
-            instruction,
-            text,
-            vec!["true".to_string(), "false".to_string()],
-        )
-        .await
-        .map(|index| index == 0)
-    }
-
-    async fn generate_text(
-        &self,
-        instruction: String,
-        text: String,
-    ) -> Result<String, GenerateTextError> {
-        let messages = vec![
-            Message {
-                role: MessageRole::System,
-                content: instruction,
-                obj: None,
-            },
-            Message {
-                role: MessageRole::User,
-                content: text,
-                obj: None,
-            },
-        ];
-        let options = GenerateMessageOptionsBuilder::new()
-            .temperature(0.0)
-            .force_json(false)
-            .build();
-        if let Ok(message) = self.generate_message(messages, options).await {
-            Ok(message.content)
-        } else {
-            Err(GenerateTextError {
-                message: String::from("Failed to generate message"),
-            })
-        }
-    }
-
-    async fn score_float(
-        &self,
-        instruction: String,
-        text: String,
-        min_bound: f64,
-        max_bound: f64,
-    ) -> Result<f64, ScoreFloatError> {
-        let input_text = format!(
-            "Instruction:\n{}\n\nText:\n{}\n\nRange:\n[{}, {}]\n\nValid JSON:",
-            instruction, text, min_bound, max_bound
-        );
-        let messages = vec![
-            Message {
-                role: MessageRole::System,
-                content: String::from("Score the following text with the provided instruction and range as a float value as valid JSON:\n{\"score\": float}"),
-                obj: None,
-            },
-            Message {
-                role: MessageRole::User,
-                content: input_text,
-                obj: None,
-            },
-        ];
-        let options = GenerateMessageOptionsBuilder::new()
-            .temperature(0.0)
-            .force_json(true)
-            .build();
-        if let Ok(message) = self.generate_message(messages, options).await {
-            if let Some(obj) = message.obj {
-                if let Some(score) = obj["score"].as_f64() {
-                    return Ok(score);
-                } else {
-                    return Err(ScoreFloatError {
-                        message: String::from("Score not found in response"),
-                    });
-                }
-            } else {
-                return Err(ScoreFloatError {
-                    message: String::from("Object not found in response"),
-                });
-            }
-        } else {
-            Err(ScoreFloatError {
-                message: String::from("Failed to generate message"),
-            })
-        }
+    /// Schema the JSON response should follow, for backends that can enforce
+    /// one. Backends without schema support fall back to plain JSON mode.
+    pub fn json_schema(&mut self, json_schema: Value) -> &mut Self {
+        self.options.json_schema = Some(json_schema);
+        self
     }
 
-    async fn score_int(
-        &self,
-        instruction: String,
-        text: String,
-        min_bound: i64,
-        max_bound: i64,
-    ) -> Result<i64, ScoreIntError> {
-        let input_text = format!(
-            "Instruction:\n{}\n\nText:\n{}\n\nRange:\n[{}, {}]\n\nValid JSON:",
-            instruction, text, min_bound, max_bound
-        );
-        let messages = vec![
-            Message {
-                role: MessageRole::System,
-                content: String::from("Score the following text with the provided instruction and range as an integer value as valid JSON:\n{\"score\": int}"),
-                obj: None,
-            },
-            Message {
-                role: MessageRole::User,
-                content: input_text,
-                obj: None,
-            },
-        ];
-        let options = GenerateMessageOptionsBuilder::new()
-            .temperature(0.0)
-            .force_json(true)
-            .build();
-        if let Ok(message) = self.generate_message(messages, options).await {
-            if let Some(obj) = message.obj {
-                if let Some(score) = obj["score"].as_i64() {
-                    return Ok(score);
-                } else {
-                    return Err(ScoreIntError {
-                        message: String::from("Score not found in response"),
-                    });
-                }
-            } else {
-                return Err(ScoreIntError {
-                    message: String::from("Object not found in response"),
-                });
-            }
-        } else {
-            Err(ScoreIntError {
-                message: String::from("Failed to generate message"),
-            })
+    /// Applies the sampling settings and timeout of a call. The temperature is
+    /// only replaced if the call sets one.
+    pub fn call_options(&mut self, call_options: &CallOptions) -> &mut Self {
+        if let Some(temperature) = call_options.temperature {
+            self.options.temperature = temperature;
         }
+        self.options.top_p = call_options.top_p;
+        self.options.max_tokens = call_options.max_tokens;
+        self.options.stop = call_options.stop.clone();
+        self.options.seed = call_options.seed;
+        self.options.presence_penalty = call_options.presence_penalty;
+        self.options.frequency_penalty = call_options.frequency_penalty;
+        self.options.logit_bias = call_options.logit_bias.clone();
+        self.options.user = call_options.user.clone();
+        self.options.timeout = call_options.timeout;
+        self
     }
 
-    async fn parse<T>(&self, text: String) -> Result<T, ParseError>
-    where
-        T: for<'de> Deserialize<'de> + JsonSchema,
-    {
-        let json_schema_string = struct_to_json_schema_string::<T>();
-        let input_text = format!(
-            "Text:\n{}\n\nSchema:\n{}\n\nValid JSON:",
-            text, json_schema_string
-        );
-        let messages = vec![
-            Message {
-                role: MessageRole::System,
-                content: String::from("Parse the following text with the provided schema."),
-                obj: None,
-            },
-            Message {
-                role: MessageRole::User,
-                content: input_text,
-                obj: None,
-            },
-        ];
-        let options = GenerateMessageOptionsBuilder::new()
-            .temperature(0.0)
-            .force_json(true)
-            .build();
-        match self.generate_message(messages, options).await {
-            Ok(message) => {
-                if let Some(obj) = message.obj {
-                    if let Ok(parsed_obj) = json_response_to_obj::<T>(obj) {
-                        return Ok(parsed_obj);
-                    } else {
-                        return Err(ParseError {
-                            message: String::from("Failed to parse response"),
-                        });
-                    }
-                } else {
-                    return Err(ParseError {
-                        message: String::from("Object not found in response"),
-                    });
-                }
-            }
-            Err(e) => {
-                return Err(ParseError {
-                    message: format!("{}", e),
-                });
-            }
-        }
+    pub fn build(&self) -> GenerateMessageOptions {
+        self.options.clone()
     }
 }
 
@@ -541,17 +712,44 @@ fn struct_to_json_schema_string<T: JsonSchema>() -> String {
     to_string_pretty(&schema).unwrap()
 }
 
+fn struct_to_json_schema<T: JsonSchema>() -> Value {
+    serde_json::to_value(schema_for!(T)).unwrap()
+}
+
+/// Schema for a JSON object with a single required property.
+fn single_property_schema(name: &str, property: Value) -> Value {
+    json!({
+        "type": "object",
+        "properties": { name: property },
+        "required": [name],
+    })
+}
+
 fn json_response_to_obj<T>(json_response: Map<String, Value>) -> Result<T, Error>
 where
-    T: for<'de> Deserialize<'de> + JsonSchema,
+    T: for<'de> Deserialize<'de>,
 {
     let json_str = to_string(&json_response).unwrap();
-    let obj = serde_json::from_str::<T>(&json_str);
-    if let Ok(obj) = obj {
-        Ok(obj)
-    } else {
-        Err(Error)
-    }
+    serde_json::from_str::<T>(&json_str).map_err(|e| Error::from_serde(json_str, e))
+}
+
+/// Deserializes a response body, keeping the raw text around for the error.
+fn decode_json<T>(raw: String) -> Result<T, Error>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    serde_json::from_str::<T>(&raw).map_err(|e| Error::from_serde(raw, e))
+}
+
+/// Pulls the first JSON object out of a completion, skipping any leading prose
+/// or code fences and ignoring whatever follows the closing brace.
+fn extract_json_object(content: &str) -> Result<Map<String, Value>, Error> {
+    let start = content.find('{').unwrap_or(0);
+    serde_json::Deserializer::from_str(&content[start..])
+        .into_iter::<Map<String, Value>>()
+        .next()
+        .unwrap_or_else(|| serde_json::from_str(content))
+        .map_err(|e| Error::from_serde(content.to_string(), e))
 }
 
 fn display_choices(choices: Vec<String>) -> (String, HashMap<String, usize>) {
@@ -568,8 +766,8 @@ fn display_choices(choices: Vec<String>) -> (String, HashMap<String, usize>) {
 fn index_to_alpha(mut index: usize) -> String {
     let mut alpha = String::new();
     loop {
-        alpha = format!("{}{}", ('A' as u8 + (index % 26) as u8) as char, alpha);
-        index = index / 26;
+        alpha = format!("{}{}", (b'A' + (index % 26) as u8) as char, alpha);
+        index /= 26;
         if index <= 1 {
             break;
         }
@@ -577,68 +775,3 @@ fn index_to_alpha(mut index: usize) -> String {
     }
     alpha
 }
-
-#[derive(Debug, Clone)]
-pub struct ClassifyError {
-    message: String,
-}
-
-impl std::fmt::Display for ClassifyError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "ClassifyError: {}", self.message)
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct GenerateTextError {
-    message: String,
-}
-
-impl std::fmt::Display for GenerateTextError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "GenerateTextError: {}", self.message)
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct ScoreFloatError {
-    message: String,
-}
-
-impl std::fmt::Display for ScoreFloatError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "ScoreFloatError: {}", self.message)
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct ScoreIntError {
-    message: String,
-}
-
-impl std::fmt::Display for ScoreIntError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "ScoreIntError: {}", self.message)
-    }
-}
-
-#[derive(Debug, Clone)]
-pub struct ParseError {
-    message: String,
-}
-
-impl std::fmt::Display for ParseError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "ParseError: {}", self.message)
-    }
-}
-
-pub struct ChatError {
-    message: String,
-}
-
-impl std::fmt::Display for ChatError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "ChatError: {}", self.message)
-    }
-}
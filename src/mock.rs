@@ -0,0 +1,581 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::vote;
+use crate::{
+    CallOptions, ClassifyVote, CostTracker, Error, Model, Response, ScoreVote, TextDelta,
+    TextStream, VoteOptions,
+};
+
+const MOCK_MODEL_NAME: &str = "mock";
+
+/// One call made to a `MockModel`, with the arguments it was given.
+///
+/// A `*_vote` call is recorded as one call per sample, each with the options
+/// of that sample and the `VoteOptions` of the vote in `vote`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    Classify {
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: CallOptions,
+        vote: Option<VoteOptions>,
+    },
+    BinaryClassify {
+        instruction: String,
+        text: String,
+        options: CallOptions,
+    },
+    GenerateText {
+        instruction: String,
+        text: String,
+        options: CallOptions,
+    },
+    GenerateTextStream {
+        instruction: String,
+        text: String,
+        options: CallOptions,
+    },
+    ScoreFloat {
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: CallOptions,
+        vote: Option<VoteOptions>,
+    },
+    ScoreInt {
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: CallOptions,
+        vote: Option<VoteOptions>,
+    },
+    Parse {
+        text: String,
+        options: CallOptions,
+    },
+}
+
+impl MockCall {
+    /// The instruction, for every call except `parse`.
+    pub fn instruction(&self) -> Option<&str> {
+        match self {
+            MockCall::Classify { instruction, .. }
+            | MockCall::BinaryClassify { instruction, .. }
+            | MockCall::GenerateText { instruction, .. }
+            | MockCall::GenerateTextStream { instruction, .. }
+            | MockCall::ScoreFloat { instruction, .. }
+            | MockCall::ScoreInt { instruction, .. } => Some(instruction),
+            MockCall::Parse { .. } => None,
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        match self {
+            MockCall::Classify { text, .. }
+            | MockCall::BinaryClassify { text, .. }
+            | MockCall::GenerateText { text, .. }
+            | MockCall::GenerateTextStream { text, .. }
+            | MockCall::ScoreFloat { text, .. }
+            | MockCall::ScoreInt { text, .. }
+            | MockCall::Parse { text, .. } => text,
+        }
+    }
+
+    /// The options the call was made with. For a sample of a vote, these are
+    /// the options of that sample.
+    pub fn options(&self) -> &CallOptions {
+        match self {
+            MockCall::Classify { options, .. }
+            | MockCall::BinaryClassify { options, .. }
+            | MockCall::GenerateText { options, .. }
+            | MockCall::GenerateTextStream { options, .. }
+            | MockCall::ScoreFloat { options, .. }
+            | MockCall::ScoreInt { options, .. }
+            | MockCall::Parse { options, .. } => options,
+        }
+    }
+
+    /// The options of the vote the call is a sample of, if it is one.
+    pub fn vote_options(&self) -> Option<&VoteOptions> {
+        match self {
+            MockCall::Classify { vote, .. }
+            | MockCall::ScoreFloat { vote, .. }
+            | MockCall::ScoreInt { vote, .. } => vote.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The choices, for `classify` calls.
+    pub fn choices(&self) -> Option<&[String]> {
+        match self {
+            MockCall::Classify { choices, .. } => Some(choices),
+            _ => None,
+        }
+    }
+}
+
+type Handler<V> = Arc<dyn Fn(&MockCall) -> Result<V, Error> + Send + Sync>;
+
+/// Canned answers for one method: queued results first, then the handler.
+struct Responder<V> {
+    queue: VecDeque<Result<V, Error>>,
+    handler: Option<Handler<V>>,
+}
+
+impl<V> Default for Responder<V> {
+    fn default() -> Self {
+        Responder {
+            queue: VecDeque::new(),
+            handler: None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    calls: Vec<MockCall>,
+    latency: Duration,
+    classify: Responder<usize>,
+    binary_classify: Responder<bool>,
+    generate_text: Responder<String>,
+    generate_text_stream: Responder<Vec<String>>,
+    score_float: Responder<f64>,
+    score_int: Responder<i64>,
+    parse: Responder<Value>,
+}
+
+/// A `Model` that answers from a script instead of a provider, for unit tests
+/// of code that takes a model.
+///
+/// Each method answers with the results queued for it, in order, and once the
+/// queue is empty with its handler. A call with neither panics. Every call is
+/// recorded with its arguments so tests can assert on what was asked.
+///
+/// Clones share the same script and call log.
+#[derive(Clone, Default)]
+pub struct MockModel {
+    state: Arc<Mutex<MockState>>,
+    cost_tracker: CostTracker,
+}
+
+impl MockModel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues the result of the next `classify` call.
+    pub fn queue_classify(&self, result: Result<usize, Error>) -> &Self {
+        self.state.lock().unwrap().classify.queue.push_back(result);
+        self
+    }
+
+    /// Answers `classify` calls with `handler` once their queue is empty.
+    pub fn on_classify<F>(&self, handler: F) -> &Self
+    where
+        F: Fn(&MockCall) -> Result<usize, Error> + Send + Sync + 'static,
+    {
+        self.state.lock().unwrap().classify.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Queues the result of the next `binary_classify` call.
+    pub fn queue_binary_classify(&self, result: Result<bool, Error>) -> &Self {
+        self.state
+            .lock()
+            .unwrap()
+            .binary_classify
+            .queue
+            .push_back(result);
+        self
+    }
+
+    /// Answers `binary_classify` calls with `handler` once their queue is empty.
+    pub fn on_binary_classify<F>(&self, handler: F) -> &Self
+    where
+        F: Fn(&MockCall) -> Result<bool, Error> + Send + Sync + 'static,
+    {
+        self.state.lock().unwrap().binary_classify.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Queues the result of the next `generate_text` call.
+    pub fn queue_generate_text(&self, result: Result<String, Error>) -> &Self {
+        self.state
+            .lock()
+            .unwrap()
+            .generate_text
+            .queue
+            .push_back(result);
+        self
+    }
+
+    /// Answers `generate_text` calls with `handler` once their queue is empty.
+    pub fn on_generate_text<F>(&self, handler: F) -> &Self
+    where
+        F: Fn(&MockCall) -> Result<String, Error> + Send + Sync + 'static,
+    {
+        self.state.lock().unwrap().generate_text.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Queues the chunks the next `generate_text_stream` call yields.
+    pub fn queue_generate_text_stream(&self, result: Result<Vec<String>, Error>) -> &Self {
+        self.state
+            .lock()
+            .unwrap()
+            .generate_text_stream
+            .queue
+            .push_back(result);
+        self
+    }
+
+    /// Answers `generate_text_stream` calls with `handler` once their queue is empty.
+    pub fn on_generate_text_stream<F>(&self, handler: F) -> &Self
+    where
+        F: Fn(&MockCall) -> Result<Vec<String>, Error> + Send + Sync + 'static,
+    {
+        self.state.lock().unwrap().generate_text_stream.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Queues the result of the next `score_float` call.
+    pub fn queue_score_float(&self, result: Result<f64, Error>) -> &Self {
+        self.state
+            .lock()
+            .unwrap()
+            .score_float
+            .queue
+            .push_back(result);
+        self
+    }
+
+    /// Answers `score_float` calls with `handler` once their queue is empty.
+    pub fn on_score_float<F>(&self, handler: F) -> &Self
+    where
+        F: Fn(&MockCall) -> Result<f64, Error> + Send + Sync + 'static,
+    {
+        self.state.lock().unwrap().score_float.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Queues the result of the next `score_int` call.
+    pub fn queue_score_int(&self, result: Result<i64, Error>) -> &Self {
+        self.state.lock().unwrap().score_int.queue.push_back(result);
+        self
+    }
+
+    /// Answers `score_int` calls with `handler` once their queue is empty.
+    pub fn on_score_int<F>(&self, handler: F) -> &Self
+    where
+        F: Fn(&MockCall) -> Result<i64, Error> + Send + Sync + 'static,
+    {
+        self.state.lock().unwrap().score_int.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Queues the result of the next `parse` or `parse_value` call. The value
+    /// is converted to whatever type that call asks for through JSON.
+    pub fn queue_parse<T: Serialize>(&self, result: Result<T, Error>) -> &Self {
+        let result = result.map(|value| serde_json::to_value(value).expect("value serializes"));
+        self.state.lock().unwrap().parse.queue.push_back(result);
+        self
+    }
+
+    /// Answers `parse` and `parse_value` calls with the JSON `handler` returns
+    /// once their queue is empty.
+    pub fn on_parse<F>(&self, handler: F) -> &Self
+    where
+        F: Fn(&MockCall) -> Result<Value, Error> + Send + Sync + 'static,
+    {
+        self.state.lock().unwrap().parse.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Waits `latency` before every answer.
+    pub fn latency(&self, latency: Duration) -> &Self {
+        self.state.lock().unwrap().latency = latency;
+        self
+    }
+
+    /// Every call so far, oldest first.
+    pub fn calls(&self) -> Vec<MockCall> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    pub fn call_count(&self) -> usize {
+        self.state.lock().unwrap().calls.len()
+    }
+
+    /// Forgets the recorded calls, keeping the script.
+    pub fn clear_calls(&self) {
+        self.state.lock().unwrap().calls.clear();
+    }
+
+    async fn respond<V>(
+        &self,
+        call: MockCall,
+        responder: fn(&mut MockState) -> &mut Responder<V>,
+    ) -> Result<Response<V>, Error> {
+        let started = Instant::now();
+        let (queued, handler, latency) = {
+            let mut state = self.state.lock().unwrap();
+            state.calls.push(call.clone());
+            let latency = state.latency;
+            let responder = responder(&mut state);
+            (
+                responder.queue.pop_front(),
+                responder.handler.clone(),
+                latency,
+            )
+        };
+        if !latency.is_zero() {
+            tokio::time::sleep(latency).await;
+        }
+        let value = match (queued, handler) {
+            (Some(result), _) => result?,
+            (None, Some(handler)) => handler(&call)?,
+            (None, None) => panic!("MockModel has no response for {:?}", call),
+        };
+        Ok(Response {
+            value,
+            repairs: vec![],
+            usage: None,
+            cost: None,
+            model: String::from(MOCK_MODEL_NAME),
+            latency: started.elapsed(),
+            request_id: None,
+            cached: false,
+            served_by: None,
+        })
+    }
+}
+
+impl Model for MockModel {
+    fn cost_tracker(&self) -> &CostTracker {
+        &self.cost_tracker
+    }
+
+    async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        let call = MockCall::Classify {
+            instruction,
+            text,
+            choices,
+            options: options.clone(),
+            vote: None,
+        };
+        self.respond(call, |state| &mut state.classify).await
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        let call = MockCall::BinaryClassify {
+            instruction,
+            text,
+            options: options.clone(),
+        };
+        self.respond(call, |state| &mut state.binary_classify).await
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        let call = MockCall::GenerateText {
+            instruction,
+            text,
+            options: options.clone(),
+        };
+        self.respond(call, |state| &mut state.generate_text).await
+    }
+
+    async fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        let call = MockCall::GenerateTextStream {
+            instruction,
+            text,
+            options: options.clone(),
+        };
+        let chunks = self
+            .respond(call, |state| &mut state.generate_text_stream)
+            .await?
+            .value;
+        let deltas = chunks
+            .into_iter()
+            .map(|text| TextDelta {
+                text,
+                ..TextDelta::default()
+            })
+            .chain(std::iter::once(TextDelta {
+                finish_reason: Some(String::from("stop")),
+                ..TextDelta::default()
+            }))
+            .map(Ok);
+        Ok(Box::pin(futures::stream::iter(deltas)))
+    }
+
+    async fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        let call = MockCall::ScoreFloat {
+            instruction,
+            text,
+            min_bound,
+            max_bound,
+            options: options.clone(),
+            vote: None,
+        };
+        self.respond(call, |state| &mut state.score_float).await
+    }
+
+    async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        let call = MockCall::ScoreInt {
+            instruction,
+            text,
+            min_bound,
+            max_bound,
+            options: options.clone(),
+            vote: None,
+        };
+        self.respond(call, |state| &mut state.score_int).await
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        let call = MockCall::Parse {
+            text,
+            options: options.clone(),
+        };
+        let response = self.respond(call, |state| &mut state.parse).await?;
+        let value = T::deserialize(&response.value)
+            .map_err(|e| Error::from_serde(response.value.to_string(), e))?;
+        Ok(response.map(|_| value))
+    }
+
+    async fn parse_value_with_options(
+        &self,
+        text: String,
+        _schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        let call = MockCall::Parse {
+            text,
+            options: options.clone(),
+        };
+        self.respond(call, |state| &mut state.parse).await
+    }
+
+    async fn classify_vote(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &VoteOptions,
+    ) -> Result<Response<ClassifyVote>, Error> {
+        let started = Instant::now();
+        let sample_options = options.sample_options();
+        let samples = vote::sample(options.samples(), options, || {
+            let call = MockCall::Classify {
+                instruction: instruction.clone(),
+                text: text.clone(),
+                choices: choices.clone(),
+                options: sample_options.clone(),
+                vote: Some(options.clone()),
+            };
+            self.respond(call, |state| &mut state.classify)
+        })
+        .await;
+        vote::tally_choices(samples, choices.len(), started)
+    }
+
+    async fn score_float_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<f64>>, Error> {
+        let started = Instant::now();
+        let sample_options = options.sample_options();
+        let samples = vote::sample(options.samples(), options, || {
+            let call = MockCall::ScoreFloat {
+                instruction: instruction.clone(),
+                text: text.clone(),
+                min_bound,
+                max_bound,
+                options: sample_options.clone(),
+                vote: Some(options.clone()),
+            };
+            self.respond(call, |state| &mut state.score_float)
+        })
+        .await;
+        vote::summarize_scores(samples, started)
+    }
+
+    async fn score_int_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<i64>>, Error> {
+        let started = Instant::now();
+        let sample_options = options.sample_options();
+        let samples = vote::sample(options.samples(), options, || {
+            let call = MockCall::ScoreInt {
+                instruction: instruction.clone(),
+                text: text.clone(),
+                min_bound,
+                max_bound,
+                options: sample_options.clone(),
+                vote: Some(options.clone()),
+            };
+            self.respond(call, |state| &mut state.score_int)
+        })
+        .await;
+        vote::summarize_scores(samples, started)
+    }
+}
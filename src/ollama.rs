@@ -0,0 +1,491 @@
+use futures::StreamExt;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::time::Duration;
+
+use crate::config::{common_builder_methods, CommonConfig, ModelConfig};
+use crate::http::with_timeout;
+use crate::stream::{text_stream, Framing, StreamState};
+use crate::{
+    decode_json, extract_json_object, primitives, CallOptions, ChatModel, CostTracker, Error,
+    GenerateMessageOptions, Generation, Message, MessageRole, Model, Response, TextStream, Usage,
+};
+
+pub const OLLAMA_API_BASE: &str = "http://localhost:11434";
+pub const OLLAMA_API_CHAT_ENDPOINT: &str = "/api/chat";
+pub const OLLAMA_API_TAGS_ENDPOINT: &str = "/api/tags";
+pub const OLLAMA_API_PULL_ENDPOINT: &str = "/api/pull";
+
+const PULL_SUCCESS: &str = "success";
+
+/// Gives up on a pull once Ollama has not reported progress for this long.
+const PULL_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+pub struct OllamaModel {
+    model: String,
+    base_url: String,
+    keep_alive: Option<Value>,
+    options: Map<String, Value>,
+    config: ModelConfig,
+}
+
+pub struct OllamaModelBuilder {
+    model: String,
+    base_url: String,
+    keep_alive: Option<Value>,
+    options: Map<String, Value>,
+    common: CommonConfig,
+}
+
+impl OllamaModelBuilder {
+    pub fn new(model: String) -> Self {
+        OllamaModelBuilder {
+            model,
+            base_url: String::from(OLLAMA_API_BASE),
+            keep_alive: None,
+            options: Map::new(),
+            common: CommonConfig::new(),
+        }
+    }
+
+    /// Full base URL including the scheme. Defaults to `http://localhost:11434`.
+    pub fn base_url(&mut self, base_url: String) -> &mut Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// How long the model stays loaded after a request, e.g. `"10m"`, `"-1"` to
+    /// keep it loaded indefinitely, or `"0"` to unload it right away.
+    pub fn keep_alive(&mut self, keep_alive: String) -> &mut Self {
+        self.keep_alive = Some(Value::String(keep_alive));
+        self
+    }
+
+    /// Sets a model option such as `num_ctx` or `num_gpu` that is passed through
+    /// verbatim in the request `options`.
+    pub fn option(&mut self, name: String, value: Value) -> &mut Self {
+        self.options.insert(name, value);
+        self
+    }
+
+    common_builder_methods!();
+
+    /// Builds the model.
+    ///
+    /// # Panics
+    ///
+    /// If the HTTP client cannot be created; see `try_build`.
+    pub fn build(&self) -> OllamaModel {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds the model, or fails with `Error::Transport` if the HTTP client
+    /// cannot be created.
+    pub fn try_build(&self) -> Result<OllamaModel, Error> {
+        Ok(OllamaModel {
+            model: self.model.clone(),
+            base_url: self.base_url.clone(),
+            keep_alive: self.keep_alive.clone(),
+            options: self.options.clone(),
+            config: self.common.build()?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct OllamaMessage {
+    role: MessageRole,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaChatRequestBody {
+    model: String,
+    messages: Vec<OllamaMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<Value>,
+    options: Map<String, Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChatResponse {
+    message: OllamaMessage,
+    model: Option<String>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChatChunk {
+    message: Option<OllamaChunkMessage>,
+    #[serde(default)]
+    done: bool,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u64>,
+    eval_count: Option<u64>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaChunkMessage {
+    #[serde(default)]
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaPullRequestBody {
+    model: String,
+    stream: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaPullProgress {
+    #[serde(default)]
+    status: String,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaTagsResponse {
+    models: Vec<OllamaLocalModel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaLocalModel {
+    name: String,
+}
+
+impl OllamaModel {
+    pub fn new(model: String) -> Self {
+        OllamaModelBuilder::new(model).build()
+    }
+
+    /// Creates a model that sends its requests with an existing client.
+    pub fn with_client(model: String, client: reqwest::Client) -> Self {
+        OllamaModelBuilder::new(model).client(client).build()
+    }
+
+    /// Whether the model has already been pulled on the Ollama server.
+    pub async fn is_available(&self) -> Result<bool, Error> {
+        let url = format!("{}{}", self.base_url, OLLAMA_API_TAGS_ENDPOINT);
+        let response = self
+            .config
+            .retry_policy
+            .send(self.config.client.get(url))
+            .await?;
+        let tags = decode_json::<OllamaTagsResponse>(response.text().await?)?;
+        let tagged_model = if self.model.contains(':') {
+            self.model.clone()
+        } else {
+            format!("{}:latest", self.model)
+        };
+        Ok(tags
+            .models
+            .iter()
+            .any(|local_model| local_model.name == self.model || local_model.name == tagged_model))
+    }
+
+    /// Downloads the model, blocking until the pull has finished.
+    ///
+    /// Pulling a large model can take much longer than the client's timeout,
+    /// so the pull is not subject to it. Instead it follows Ollama's progress
+    /// reports and fails with `Error::Timeout` if none arrives for 5 minutes.
+    pub async fn pull(&self) -> Result<(), Error> {
+        let url = format!("{}{}", self.base_url, OLLAMA_API_PULL_ENDPOINT);
+        let body = OllamaPullRequestBody {
+            model: self.model.clone(),
+            stream: true,
+        };
+        let request = self
+            .config
+            .client
+            .post(url)
+            .json(&body)
+            .timeout(Duration::MAX);
+        let response = self.config.retry_policy.send(request).await?;
+        let mut progress = text_stream(response, Framing::JsonLines, decode_pull_progress);
+        let mut status = None;
+        loop {
+            match tokio::time::timeout(PULL_IDLE_TIMEOUT, progress.next()).await {
+                Err(_) => return Err(Error::Timeout),
+                // A pull that stops without reporting success fails below.
+                Ok(None) | Ok(Some(Err(Error::IncompleteStream))) => break,
+                Ok(Some(delta)) => {
+                    let delta = delta?;
+                    if delta.finish_reason.is_some() {
+                        status = delta.finish_reason;
+                    }
+                }
+            }
+        }
+        match status {
+            Some(status) if status == PULL_SUCCESS => Ok(()),
+            status => Err(Error::schema_mismatch(
+                status.unwrap_or_default(),
+                "expected pull status \"success\"",
+            )),
+        }
+    }
+
+    /// Pulls the model unless it is already present on the server.
+    pub async fn ensure_available(&self) -> Result<(), Error> {
+        if self.is_available().await? {
+            return Ok(());
+        }
+        self.pull().await
+    }
+}
+
+impl OllamaModel {
+    fn chat_request(
+        &self,
+        messages: Vec<Message>,
+        options: &GenerateMessageOptions,
+        stream: bool,
+    ) -> reqwest::RequestBuilder {
+        let url = format!("{}{}", self.base_url, OLLAMA_API_CHAT_ENDPOINT);
+        let format = match (options.force_json, &options.json_schema) {
+            (true, Some(json_schema)) => Some(json_schema.clone()),
+            (true, None) => Some(Value::String(String::from("json"))),
+            (false, _) => None,
+        };
+        let mut request_options = self.options.clone();
+        request_options.insert(
+            String::from("temperature"),
+            Value::from(options.temperature),
+        );
+        let sampling_options = [
+            ("top_p", options.top_p.map(Value::from)),
+            ("num_predict", options.max_tokens.map(Value::from)),
+            ("stop", options.stop.clone().map(Value::from)),
+            ("seed", options.seed.map(Value::from)),
+            (
+                "presence_penalty",
+                options.presence_penalty.map(Value::from),
+            ),
+            (
+                "frequency_penalty",
+                options.frequency_penalty.map(Value::from),
+            ),
+        ];
+        for (name, value) in sampling_options {
+            if let Some(value) = value {
+                request_options.insert(String::from(name), value);
+            }
+        }
+        let body = OllamaChatRequestBody {
+            model: self.model.clone(),
+            messages: messages
+                .into_iter()
+                .map(|message| OllamaMessage {
+                    role: message.role,
+                    content: message.content,
+                })
+                .collect(),
+            stream,
+            format,
+            options: request_options,
+            keep_alive: self.keep_alive.clone(),
+        };
+        let request = self
+            .config
+            .client
+            .post(url)
+            .header("Content-Type", "application/json")
+            .json(&body);
+        with_timeout(request, options.timeout)
+    }
+}
+
+fn decode_chat_chunk(payload: &str, state: &mut StreamState) -> Result<Option<String>, Error> {
+    let chunk = decode_json::<OllamaChatChunk>(payload.to_string())?;
+    if chunk.error.is_some() {
+        return Err(Error::Http {
+            status: 500,
+            body: payload.to_string(),
+        });
+    }
+    if chunk.done {
+        state.done = true;
+        state.finish_reason = chunk.done_reason;
+        state.usage = Some(Usage {
+            input_tokens: chunk.prompt_eval_count.unwrap_or_default(),
+            output_tokens: chunk.eval_count.unwrap_or_default(),
+            ..Usage::default()
+        });
+    }
+    Ok(chunk.message.map(|message| message.content))
+}
+
+/// Yields each status line of a pull, so that a stalled pull can be told
+/// apart from a slow one. The last status is kept as the finish reason.
+fn decode_pull_progress(payload: &str, state: &mut StreamState) -> Result<Option<String>, Error> {
+    let progress = decode_json::<OllamaPullProgress>(payload.to_string())?;
+    if progress.error.is_some() {
+        return Err(Error::Http {
+            status: 500,
+            body: payload.to_string(),
+        });
+    }
+    if progress.status == PULL_SUCCESS {
+        state.done = true;
+    }
+    state.finish_reason = Some(progress.status.clone());
+    Ok(Some(progress.status))
+}
+
+impl ChatModel for OllamaModel {
+    async fn generate_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<Generation, Error> {
+        let response = self
+            .config
+            .retry_policy
+            .send(self.chat_request(messages, &options, false))
+            .await?;
+        let headers = response.headers().clone();
+        let chat_response = decode_json::<OllamaChatResponse>(response.text().await?)?;
+        let message = chat_response.message;
+        let obj = if options.force_json {
+            Some(extract_json_object(&message.content)?)
+        } else {
+            None
+        };
+        // Ollama omits `prompt_eval_count` when the whole prompt was cached.
+        let usage = match (chat_response.prompt_eval_count, chat_response.eval_count) {
+            (None, None) => None,
+            (prompt_eval_count, eval_count) => Some(Usage {
+                input_tokens: prompt_eval_count.unwrap_or_default(),
+                output_tokens: eval_count.unwrap_or_default(),
+                ..Usage::default()
+            }),
+        };
+        Ok(Generation {
+            message: Message {
+                role: message.role,
+                content: message.content,
+                obj,
+            },
+            usage,
+            model: chat_response.model,
+            headers,
+            alternatives: vec![],
+        })
+    }
+
+    fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    fn model_name(&self) -> &str {
+        &self.model
+    }
+
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn stream_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<TextStream, Error> {
+        let response = self
+            .config
+            .retry_policy
+            .send(self.chat_request(messages, &options, true))
+            .await?;
+        Ok(text_stream(response, Framing::JsonLines, decode_chat_chunk))
+    }
+}
+
+impl Model for OllamaModel {
+    fn cost_tracker(&self) -> &CostTracker {
+        &self.config.cost_tracker
+    }
+
+    async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        primitives::classify(self, instruction, text, choices, options).await
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        primitives::binary_classify(self, instruction, text, options).await
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        primitives::generate_text(self, instruction, text, options).await
+    }
+
+    async fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        primitives::generate_text_stream(self, instruction, text, options).await
+    }
+
+    async fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        primitives::score_float(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        primitives::score_int(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        primitives::parse(self, text, options).await
+    }
+
+    async fn parse_value_with_options(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        primitives::parse_value(self, text, schema, options).await
+    }
+}
@@ -0,0 +1,680 @@
+use reqwest::header::HeaderMap;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::config::{
+    common_builder_methods, credential_builder_methods, CommonConfig, ModelConfig,
+};
+use crate::http::with_timeout;
+use crate::openai_batch::OPENAI_API_BATCH_CHAT_ENDPOINT;
+use crate::stream::{text_stream, Framing, StreamState};
+use crate::{
+    decode_json, primitives, vote, CallOptions, ChatModel, ClassifyVote, CostTracker,
+    CredentialProvider, Error, GenerateMessageOptions, Generation, Message, MessageRole, Model,
+    Response, RetryPolicy, ScoreVote, TextStream, Usage, VoteOptions,
+};
+
+pub const OPENAI_API_KEY_NAME: &str = "OPENAI_API_KEY";
+pub const OPENAI_API_BASE: &str = "api.openai.com/v1";
+pub const OPENAI_API_CHAT_ENDPOINT: &str = "/chat/completions";
+
+/// The finish reason of a choice withheld by OpenAI's or Azure's content filter.
+const CONTENT_FILTER_FINISH_REASON: &str = "content_filter";
+
+pub struct OpenAIModel {
+    model: String,
+    credentials: CredentialProvider,
+    base_url: String,
+    chat_path: String,
+    batch_endpoint: String,
+    headers: Vec<(String, String)>,
+    stream_usage: bool,
+    config: ModelConfig,
+}
+
+pub struct OpenAIModelBuilder {
+    model: String,
+    base_url: String,
+    chat_path: String,
+    batch_endpoint: String,
+    headers: Vec<(String, String)>,
+    stream_usage: bool,
+    common: CommonConfig,
+}
+
+impl OpenAIModelBuilder {
+    pub fn new(model: String) -> Self {
+        OpenAIModelBuilder {
+            model,
+            base_url: format!("https://{}", OPENAI_API_BASE),
+            chat_path: String::from(OPENAI_API_CHAT_ENDPOINT),
+            batch_endpoint: String::from(OPENAI_API_BATCH_CHAT_ENDPOINT),
+            headers: vec![],
+            stream_usage: true,
+            common: CommonConfig::new(),
+        }
+    }
+
+    credential_builder_methods!("OPENAI_API_KEY");
+
+    /// Full base URL including the scheme, e.g. `http://localhost:8000/v1` for
+    /// a local vLLM or llama.cpp server.
+    pub fn base_url(&mut self, base_url: String) -> &mut Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Path appended to the base URL for chat requests. Defaults to
+    /// `/chat/completions`.
+    pub fn chat_path(&mut self, chat_path: String) -> &mut Self {
+        self.chat_path = if chat_path.starts_with('/') {
+            chat_path
+        } else {
+            format!("/{}", chat_path)
+        };
+        self
+    }
+
+    /// The endpoint requests in a batch job are sent to, as the Batch API
+    /// expects it: a path without the base URL. Defaults to
+    /// `/v1/chat/completions`.
+    pub fn batch_endpoint(&mut self, batch_endpoint: String) -> &mut Self {
+        self.batch_endpoint = batch_endpoint;
+        self
+    }
+
+    /// Adds a header that is sent with every request.
+    pub fn header(&mut self, name: String, value: String) -> &mut Self {
+        self.headers.push((name, value));
+        self
+    }
+
+    /// Whether streams ask for a final chunk with token counts through
+    /// `stream_options.include_usage`. On by default; turn it off for
+    /// OpenAI-compatible servers that reject the option, at the cost of
+    /// streams reporting no usage or cost.
+    pub fn stream_usage(&mut self, stream_usage: bool) -> &mut Self {
+        self.stream_usage = stream_usage;
+        self
+    }
+
+    pub fn organization(&mut self, organization: String) -> &mut Self {
+        self.header(String::from("OpenAI-Organization"), organization)
+    }
+
+    pub fn project(&mut self, project: String) -> &mut Self {
+        self.header(String::from("OpenAI-Project"), project)
+    }
+
+    common_builder_methods!();
+
+    /// Builds the model.
+    ///
+    /// # Panics
+    ///
+    /// If the credentials are known to be unavailable or the HTTP client cannot
+    /// be created; see `try_build`.
+    pub fn build(&self) -> OpenAIModel {
+        self.try_build().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Builds the model, or fails with `Error::Credentials` if the API key
+    /// comes from an environment variable that is not set or a file that
+    /// cannot be read, or with `Error::Transport` if the HTTP client cannot be
+    /// created.
+    pub fn try_build(&self) -> Result<OpenAIModel, Error> {
+        let credentials = self.common.credentials(OPENAI_API_KEY_NAME)?;
+        Ok(OpenAIModel {
+            model: self.model.clone(),
+            credentials,
+            base_url: self.base_url.clone(),
+            chat_path: self.chat_path.clone(),
+            batch_endpoint: self.batch_endpoint.clone(),
+            headers: self.headers.clone(),
+            stream_usage: self.stream_usage,
+            config: self.common.build()?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Clone)]
+struct OpenAIMessage {
+    role: MessageRole,
+    content: String,
+}
+
+impl<'de> Deserialize<'de> for OpenAIMessage {
+    fn deserialize<D>(deserializer: D) -> Result<OpenAIMessage, D::Error>
+    where
+        D: serde::de::Deserializer<'de>,
+    {
+        let obj = Map::<String, Value>::deserialize(deserializer)?;
+        let role = obj
+            .get("role")
+            .ok_or(serde::de::Error::custom("role not found"))?;
+        let content = obj
+            .get("content")
+            .ok_or(serde::de::Error::custom("content not found"))?;
+        // A filtered or refused answer has `null` content; its finish reason
+        // says why.
+        let content: Option<String> =
+            serde_json::from_value(content.clone()).map_err(serde::de::Error::custom)?;
+        Ok(OpenAIMessage {
+            role: serde_json::from_value(role.clone()).map_err(serde::de::Error::custom)?,
+            content: content.unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ChatRequestBody {
+    model: String,
+    messages: Vec<OpenAIMessage>,
+    temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logit_bias: Option<HashMap<u32, i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    n: Option<u32>,
+    response_format: ResponseFormat,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ResponseFormat {
+    r#type: ResponseFormatType,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ResponseFormatType {
+    JsonObject,
+    Text,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatResponse {
+    choices: Vec<Choice>,
+    model: Option<String>,
+    usage: Option<ChatUsage>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Choice {
+    message: OpenAIMessage,
+    #[serde(default)]
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    usage: Option<ChatUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ChunkDelta {
+    content: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ChatUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+    prompt_tokens_details: Option<PromptTokensDetails>,
+    completion_tokens_details: Option<CompletionTokensDetails>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PromptTokensDetails {
+    #[serde(default)]
+    cached_tokens: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CompletionTokensDetails {
+    #[serde(default)]
+    reasoning_tokens: u64,
+}
+
+impl ChatUsage {
+    fn usage(&self) -> Usage {
+        Usage {
+            input_tokens: self.prompt_tokens,
+            output_tokens: self.completion_tokens,
+            cached_input_tokens: self
+                .prompt_tokens_details
+                .as_ref()
+                .map_or(0, |details| details.cached_tokens),
+            reasoning_tokens: self
+                .completion_tokens_details
+                .as_ref()
+                .map_or(0, |details| details.reasoning_tokens),
+        }
+    }
+}
+
+impl OpenAIModel {
+    /// Creates a model that reads its API key from `OPENAI_API_KEY`.
+    ///
+    /// # Panics
+    ///
+    /// If `OPENAI_API_KEY` is not set; `try_new` returns an error instead.
+    pub fn new(model: String) -> Self {
+        OpenAIModelBuilder::new(model).build()
+    }
+
+    /// Like `new`, but fails with `Error::Credentials` if `OPENAI_API_KEY` is
+    /// not set.
+    pub fn try_new(model: String) -> Result<Self, Error> {
+        OpenAIModelBuilder::new(model).try_build()
+    }
+
+    pub fn with_api_key(model: String, api_key: String) -> Self {
+        OpenAIModelBuilder::new(model).api_key(api_key).build()
+    }
+
+    /// Creates a model that sends its requests with an existing client.
+    pub fn with_client(model: String, client: reqwest::Client) -> Self {
+        OpenAIModelBuilder::new(model).client(client).build()
+    }
+}
+
+impl ChatRequestBody {
+    pub(crate) fn new(
+        model: String,
+        messages: Vec<Message>,
+        options: &GenerateMessageOptions,
+    ) -> Self {
+        let response_format_type = if options.force_json {
+            ResponseFormatType::JsonObject
+        } else {
+            ResponseFormatType::Text
+        };
+        let openai_messages: Vec<OpenAIMessage> = messages
+            .iter()
+            .map(|message| OpenAIMessage {
+                role: message.role.clone(),
+                content: message.content.clone(),
+            })
+            .collect();
+        ChatRequestBody {
+            model,
+            messages: openai_messages,
+            temperature: options.temperature,
+            top_p: options.top_p,
+            max_tokens: options.max_tokens,
+            stop: options.stop.clone(),
+            seed: options.seed,
+            presence_penalty: options.presence_penalty,
+            frequency_penalty: options.frequency_penalty,
+            logit_bias: options.logit_bias.clone(),
+            user: options.user.clone(),
+            n: options.n.filter(|n| *n > 1),
+            response_format: ResponseFormat {
+                r#type: response_format_type,
+            },
+            stream: false,
+            stream_options: None,
+        }
+    }
+
+    /// Asks for a server-sent event stream. `include_usage` requests a final
+    /// chunk with token counts, which not every deployment accepts.
+    pub(crate) fn streaming(mut self, include_usage: bool) -> Self {
+        self.stream = true;
+        self.stream_options = if include_usage {
+            Some(StreamOptions {
+                include_usage: true,
+            })
+        } else {
+            None
+        };
+        self
+    }
+}
+
+/// Sends a chat completions request and decodes the first choice. Shared by
+/// every deployment that speaks the OpenAI wire format; callers only differ in
+/// the URL and authentication headers on `request`.
+pub(crate) async fn send_chat_request(
+    request: reqwest::RequestBuilder,
+    body: &ChatRequestBody,
+    force_json: bool,
+    retry_policy: &RetryPolicy,
+) -> Result<Generation, Error> {
+    let response = retry_policy
+        .send(
+            request
+                .header("Content-Type", "application/json")
+                .json(body),
+        )
+        .await?;
+    let headers = response.headers().clone();
+    decode_chat_response(response.text().await?, force_json, headers)
+}
+
+/// Decodes a chat completions response body into a `Generation`.
+pub(crate) fn decode_chat_response(
+    raw: String,
+    force_json: bool,
+    headers: HeaderMap,
+) -> Result<Generation, Error> {
+    let chat_response = decode_json::<ChatResponse>(raw.clone())?;
+    if chat_response.choices.is_empty() {
+        return Err(Error::schema_mismatch(raw, "no choices in response"));
+    }
+    // With several choices one that is filtered or not JSON should not lose
+    // the others, so it is kept without an object and fails when it is decoded.
+    let lenient = chat_response.choices.len() > 1;
+    let mut messages = vec![];
+    for choice in chat_response.choices {
+        let message = choice.message;
+        if choice.finish_reason.as_deref() == Some(CONTENT_FILTER_FINISH_REASON) && !lenient {
+            return Err(Error::ContentFiltered {
+                reason: CONTENT_FILTER_FINISH_REASON.to_string(),
+            });
+        }
+        let obj = if force_json {
+            match serde_json::from_str::<Map<String, Value>>(&message.content) {
+                Ok(obj) => Some(obj),
+                Err(_) if lenient => None,
+                Err(e) => return Err(Error::from_serde(message.content, e)),
+            }
+        } else {
+            None
+        };
+        messages.push(Message {
+            role: message.role,
+            content: message.content,
+            obj,
+        });
+    }
+    let message = messages.remove(0);
+    Ok(Generation {
+        message,
+        usage: chat_response.usage.as_ref().map(ChatUsage::usage),
+        model: chat_response.model,
+        headers,
+        alternatives: messages,
+    })
+}
+
+/// Sends a streaming chat completions request. Like `send_chat_request`, this
+/// is shared by every deployment that speaks the OpenAI wire format.
+pub(crate) async fn send_chat_stream(
+    request: reqwest::RequestBuilder,
+    body: &ChatRequestBody,
+    retry_policy: &RetryPolicy,
+) -> Result<TextStream, Error> {
+    let response = retry_policy
+        .send(
+            request
+                .header("Content-Type", "application/json")
+                .header("Accept", "text/event-stream")
+                .json(body),
+        )
+        .await?;
+    Ok(text_stream(
+        response,
+        Framing::ServerSentEvents,
+        decode_chat_chunk,
+    ))
+}
+
+fn decode_chat_chunk(payload: &str, state: &mut StreamState) -> Result<Option<String>, Error> {
+    if payload.trim() == "[DONE]" {
+        state.done = true;
+        return Ok(None);
+    }
+    let chunk = decode_json::<ChatChunk>(payload.to_string())?;
+    if let Some(usage) = chunk.usage {
+        state.usage = Some(usage.usage());
+    }
+    let Some(choice) = chunk.choices.into_iter().next() else {
+        return Ok(None);
+    };
+    if choice.finish_reason.as_deref() == Some(CONTENT_FILTER_FINISH_REASON) {
+        return Err(Error::ContentFiltered {
+            reason: CONTENT_FILTER_FINISH_REASON.to_string(),
+        });
+    }
+    if choice.finish_reason.is_some() {
+        state.finish_reason = choice.finish_reason;
+    }
+    Ok(choice.delta.content)
+}
+
+impl OpenAIModel {
+    async fn chat_request(&self) -> Result<reqwest::RequestBuilder, Error> {
+        self.api_request(reqwest::Method::POST, &self.chat_path)
+            .await
+    }
+
+    /// A request to `path` under the base URL, with authentication and the
+    /// configured headers.
+    pub(crate) async fn api_request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+    ) -> Result<reqwest::RequestBuilder, Error> {
+        let api_key = self.credentials.fetch().await?;
+        let url = format!("{}{}", self.base_url, path);
+        let mut request = self
+            .config
+            .client
+            .request(method, url)
+            .header("Authorization", format!("Bearer {}", api_key));
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        Ok(request)
+    }
+
+    pub(crate) fn retry_policy(&self) -> &RetryPolicy {
+        &self.config.retry_policy
+    }
+
+    pub(crate) fn batch_endpoint(&self) -> &str {
+        &self.batch_endpoint
+    }
+}
+
+impl fmt::Debug for OpenAIModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OpenAIModel")
+            .field("model", &self.model)
+            .field("base_url", &self.base_url)
+            .field("credentials", &self.credentials)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ChatModel for OpenAIModel {
+    async fn generate_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<Generation, Error> {
+        let body = ChatRequestBody::new(self.model.clone(), messages, &options);
+        send_chat_request(
+            with_timeout(self.chat_request().await?, options.timeout),
+            &body,
+            options.force_json,
+            &self.config.retry_policy,
+        )
+        .await
+    }
+
+    fn config(&self) -> &ModelConfig {
+        &self.config
+    }
+
+    fn model_name(&self) -> &str {
+        &self.model
+    }
+
+    fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    async fn stream_message(
+        &self,
+        messages: Vec<Message>,
+        options: GenerateMessageOptions,
+    ) -> Result<TextStream, Error> {
+        let body = ChatRequestBody::new(self.model.clone(), messages, &options)
+            .streaming(self.stream_usage);
+        let request = with_timeout(self.chat_request().await?, options.timeout);
+        send_chat_stream(request, &body, &self.config.retry_policy).await
+    }
+}
+
+impl Model for OpenAIModel {
+    fn cost_tracker(&self) -> &CostTracker {
+        &self.config.cost_tracker
+    }
+
+    async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        primitives::classify(self, instruction, text, choices, options).await
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        primitives::binary_classify(self, instruction, text, options).await
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        primitives::generate_text(self, instruction, text, options).await
+    }
+
+    async fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        primitives::generate_text_stream(self, instruction, text, options).await
+    }
+
+    async fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        primitives::score_float(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        primitives::score_int(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        primitives::parse(self, text, options).await
+    }
+
+    async fn parse_value_with_options(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        primitives::parse_value(self, text, schema, options).await
+    }
+
+    async fn classify_vote(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &VoteOptions,
+    ) -> Result<Response<ClassifyVote>, Error> {
+        vote::classify(self, instruction, text, choices, options).await
+    }
+
+    async fn score_float_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<f64>>, Error> {
+        vote::score_float(self, instruction, text, min_bound, max_bound, options).await
+    }
+
+    async fn score_int_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<i64>>, Error> {
+        vote::score_int(self, instruction, text, min_bound, max_bound, options).await
+    }
+}
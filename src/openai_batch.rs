@@ -0,0 +1,568 @@
+use reqwest::header::HeaderMap;
+use reqwest::multipart::{Form, Part};
+use reqwest::Method;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::openai::{decode_chat_response, ChatRequestBody};
+use crate::primitives::{self, Prompt};
+use crate::{
+    decode_json, CallOptions, ChatModel, Error, Message, Model, OpenAIModel, Response, Usage,
+};
+
+pub const OPENAI_API_FILES_ENDPOINT: &str = "/files";
+pub const OPENAI_API_BATCHES_ENDPOINT: &str = "/batches";
+
+/// The endpoint every request in a batch is sent to, as the Batch API expects
+/// it: a path without the base URL.
+pub const OPENAI_API_BATCH_CHAT_ENDPOINT: &str = "/v1/chat/completions";
+const BATCH_COMPLETION_WINDOW: &str = "24h";
+/// Batch requests are billed at half the synchronous price.
+const BATCH_PRICE_FACTOR: f64 = 0.5;
+
+type Decoder<T> = Box<dyn Fn(Message) -> Result<T, Error> + Send + Sync>;
+
+/// Where a batch job is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpenAIBatchStatus {
+    Validating,
+    Failed,
+    InProgress,
+    Finalizing,
+    Completed,
+    Expired,
+    Cancelling,
+    Cancelled,
+}
+
+impl OpenAIBatchStatus {
+    /// Whether the job has stopped and will not change any more.
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            OpenAIBatchStatus::Failed
+                | OpenAIBatchStatus::Completed
+                | OpenAIBatchStatus::Expired
+                | OpenAIBatchStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct OpenAIBatchRequestCounts {
+    pub total: u64,
+    pub completed: u64,
+    pub failed: u64,
+}
+
+/// Primitive calls collected for one batch job, created with
+/// `OpenAIModel::batch_requests`.
+///
+/// Prompts are built exactly as the model builds them for the synchronous
+/// primitives, including its default options. Every call is identified by a
+/// `custom_id` that must be unique within the batch; results are keyed by it,
+/// and adding a call with a `custom_id` already in the batch fails with
+/// `Error::BatchFailed`.
+/// All calls in a batch decode to the same type, so e.g. classifications and
+/// scores go into separate batches.
+pub struct OpenAIBatchRequests<'a, T> {
+    model: &'a OpenAIModel,
+    lines: Vec<BatchRequestLine>,
+    decoders: HashMap<String, BatchDecoder<T>>,
+}
+
+struct BatchDecoder<T> {
+    force_json: bool,
+    decode: Decoder<T>,
+}
+
+#[derive(Serialize)]
+struct BatchRequestLine {
+    custom_id: String,
+    method: &'static str,
+    url: String,
+    body: ChatRequestBody,
+}
+
+/// A submitted batch job. Refresh it with `OpenAIModel::refresh_batch` or
+/// `OpenAIModel::wait_for_batch`, then fetch its results with
+/// `OpenAIModel::batch_results`.
+pub struct OpenAIBatch<T> {
+    id: String,
+    status: OpenAIBatchStatus,
+    request_counts: OpenAIBatchRequestCounts,
+    output_file_id: Option<String>,
+    error_file_id: Option<String>,
+    errors: Vec<String>,
+    latency: Duration,
+    /// Set once `batch_results` has added the results' cost to the tracker
+    /// and budget.
+    costs_recorded: bool,
+    decoders: HashMap<String, BatchDecoder<T>>,
+}
+
+#[derive(Deserialize)]
+struct FileObject {
+    id: String,
+}
+
+#[derive(Serialize)]
+struct CreateBatchBody<'a> {
+    input_file_id: &'a str,
+    endpoint: &'a str,
+    completion_window: &'static str,
+}
+
+#[derive(Deserialize)]
+struct BatchObject {
+    id: String,
+    status: OpenAIBatchStatus,
+    output_file_id: Option<String>,
+    error_file_id: Option<String>,
+    request_counts: Option<OpenAIBatchRequestCounts>,
+    errors: Option<BatchErrors>,
+    created_at: u64,
+    completed_at: Option<u64>,
+    failed_at: Option<u64>,
+    expired_at: Option<u64>,
+    cancelled_at: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct BatchErrors {
+    #[serde(default)]
+    data: Vec<BatchError>,
+}
+
+#[derive(Deserialize)]
+struct BatchError {
+    code: Option<String>,
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct BatchOutputLine {
+    custom_id: String,
+    response: Option<BatchOutputResponse>,
+    error: Option<BatchError>,
+}
+
+#[derive(Deserialize)]
+struct BatchOutputResponse {
+    status_code: u16,
+    request_id: Option<String>,
+    body: Value,
+}
+
+impl BatchError {
+    fn describe(&self) -> String {
+        match &self.code {
+            Some(code) => format!("{}: {}", code, self.message),
+            None => self.message.clone(),
+        }
+    }
+}
+
+impl<'a, T> OpenAIBatchRequests<'a, T> {
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    fn push<F>(&mut self, custom_id: String, prompt: Prompt<F>) -> Result<&mut Self, Error>
+    where
+        F: Fn(Message) -> Result<T, Error> + Send + Sync + 'static,
+    {
+        if self.decoders.contains_key(&custom_id) {
+            return Err(Error::BatchFailed {
+                message: format!("duplicate custom_id in batch: {}", custom_id),
+            });
+        }
+        self.decoders.insert(
+            custom_id.clone(),
+            BatchDecoder {
+                force_json: prompt.options.force_json,
+                decode: Box::new(prompt.decode),
+            },
+        );
+        self.lines.push(BatchRequestLine {
+            custom_id,
+            method: "POST",
+            url: self.model.batch_endpoint().to_string(),
+            body: ChatRequestBody::new(
+                self.model.model_name().to_string(),
+                prompt.messages,
+                &prompt.options,
+            ),
+        });
+        Ok(self)
+    }
+
+    fn call_options(&self, options: &CallOptions) -> CallOptions {
+        self.model.default_options().overridden_by(options)
+    }
+
+    /// Uploads the requests as a JSONL file and starts a batch job for them.
+    ///
+    /// Every request is taken from the model's budget up front, and the whole
+    /// batch is refused with `Error::BudgetExceeded` if it does not fit. The
+    /// tokens and cost of the results count against the budget once they are
+    /// fetched. The model's rate limiter does not apply to batch jobs.
+    pub async fn submit(self) -> Result<OpenAIBatch<T>, Error> {
+        if let Some(budget) = self.model.budget() {
+            budget.acquire_many(self.lines.len() as u64)?;
+        }
+        let mut jsonl = String::new();
+        for line in &self.lines {
+            jsonl.push_str(&serde_json::to_string(line).expect("batch lines serialize"));
+            jsonl.push('\n');
+        }
+        let file = Part::bytes(jsonl.into_bytes())
+            .file_name("batch.jsonl")
+            .mime_str("application/jsonl")?;
+        let form = Form::new().text("purpose", "batch").part("file", file);
+        let request = self
+            .model
+            .api_request(Method::POST, OPENAI_API_FILES_ENDPOINT)
+            .await?
+            .multipart(form);
+        let response = self.model.retry_policy().send(request).await?;
+        let input_file = decode_json::<FileObject>(response.text().await?)?;
+        let request = self
+            .model
+            .api_request(Method::POST, OPENAI_API_BATCHES_ENDPOINT)
+            .await?
+            .json(&CreateBatchBody {
+                input_file_id: &input_file.id,
+                endpoint: self.model.batch_endpoint(),
+                completion_window: BATCH_COMPLETION_WINDOW,
+            });
+        let response = self.model.retry_policy().send(request).await?;
+        let object = decode_json::<BatchObject>(response.text().await?)?;
+        let mut batch = OpenAIBatch {
+            id: object.id.clone(),
+            status: object.status,
+            request_counts: OpenAIBatchRequestCounts::default(),
+            output_file_id: None,
+            error_file_id: None,
+            errors: vec![],
+            latency: Duration::ZERO,
+            costs_recorded: false,
+            decoders: self.decoders,
+        };
+        batch.update(object);
+        Ok(batch)
+    }
+}
+
+impl OpenAIBatchRequests<'_, usize> {
+    pub fn classify(
+        &mut self,
+        custom_id: String,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<&mut Self, Error> {
+        let call_options = self.call_options(options);
+        let prompt = primitives::classify_prompt(instruction, text, choices, &call_options);
+        self.push(custom_id, prompt)
+    }
+}
+
+impl OpenAIBatchRequests<'_, bool> {
+    pub fn binary_classify(
+        &mut self,
+        custom_id: String,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<&mut Self, Error> {
+        let call_options = self.call_options(options);
+        let Prompt {
+            messages,
+            options,
+            decode,
+        } = primitives::classify_prompt(
+            instruction,
+            text,
+            vec!["true".to_string(), "false".to_string()],
+            &call_options,
+        );
+        let prompt = Prompt {
+            messages,
+            options,
+            decode: move |message| decode(message).map(|index| index == 0),
+        };
+        self.push(custom_id, prompt)
+    }
+}
+
+impl OpenAIBatchRequests<'_, String> {
+    pub fn generate_text(
+        &mut self,
+        custom_id: String,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<&mut Self, Error> {
+        let call_options = self.call_options(options);
+        let prompt = primitives::generate_text_prompt(instruction, text, &call_options);
+        self.push(custom_id, prompt)
+    }
+}
+
+impl OpenAIBatchRequests<'_, f64> {
+    pub fn score_float(
+        &mut self,
+        custom_id: String,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<&mut Self, Error> {
+        let call_options = self.call_options(options);
+        let prompt =
+            primitives::score_float_prompt(instruction, text, min_bound, max_bound, &call_options);
+        self.push(custom_id, prompt)
+    }
+}
+
+impl OpenAIBatchRequests<'_, i64> {
+    pub fn score_int(
+        &mut self,
+        custom_id: String,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<&mut Self, Error> {
+        let call_options = self.call_options(options);
+        let prompt =
+            primitives::score_int_prompt(instruction, text, min_bound, max_bound, &call_options);
+        self.push(custom_id, prompt)
+    }
+}
+
+impl<T> OpenAIBatchRequests<'_, T>
+where
+    T: for<'de> Deserialize<'de> + JsonSchema + 'static,
+{
+    pub fn parse(
+        &mut self,
+        custom_id: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<&mut Self, Error> {
+        let call_options = self.call_options(options);
+        let prompt = primitives::parse_prompt::<T>(text, &call_options);
+        self.push(custom_id, prompt)
+    }
+}
+
+impl<T> OpenAIBatch<T> {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The status as of the last refresh.
+    pub fn status(&self) -> OpenAIBatchStatus {
+        self.status
+    }
+
+    pub fn request_counts(&self) -> OpenAIBatchRequestCounts {
+        self.request_counts
+    }
+
+    fn update(&mut self, object: BatchObject) {
+        self.status = object.status;
+        self.request_counts = object.request_counts.unwrap_or_default();
+        self.output_file_id = object.output_file_id;
+        self.error_file_id = object.error_file_id;
+        self.errors = object
+            .errors
+            .map(|errors| errors.data.iter().map(BatchError::describe).collect())
+            .unwrap_or_default();
+        let finished_at = object
+            .completed_at
+            .or(object.failed_at)
+            .or(object.expired_at)
+            .or(object.cancelled_at);
+        if let Some(finished_at) = finished_at {
+            self.latency = Duration::from_secs(finished_at.saturating_sub(object.created_at));
+        }
+    }
+}
+
+impl OpenAIModel {
+    /// Starts collecting primitive calls for the Batch API, which runs them
+    /// within 24 hours at half the price.
+    pub fn batch_requests<T>(&self) -> OpenAIBatchRequests<'_, T> {
+        OpenAIBatchRequests {
+            model: self,
+            lines: vec![],
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Fetches the current status of `batch`.
+    pub async fn refresh_batch<T>(&self, batch: &mut OpenAIBatch<T>) -> Result<(), Error> {
+        let path = format!("{}/{}", OPENAI_API_BATCHES_ENDPOINT, batch.id);
+        let response = self
+            .retry_policy()
+            .send(self.api_request(Method::GET, &path).await?)
+            .await?;
+        batch.update(decode_json::<BatchObject>(response.text().await?)?);
+        Ok(())
+    }
+
+    /// Refreshes `batch` every `poll_interval` until it has finished.
+    pub async fn wait_for_batch<T>(
+        &self,
+        batch: &mut OpenAIBatch<T>,
+        poll_interval: Duration,
+    ) -> Result<(), Error> {
+        while !batch.status.is_finished() {
+            tokio::time::sleep(poll_interval).await;
+            self.refresh_batch(batch).await?;
+        }
+        Ok(())
+    }
+
+    /// Asks OpenAI to stop `batch`. Requests that already completed keep their
+    /// results.
+    pub async fn cancel_batch<T>(&self, batch: &mut OpenAIBatch<T>) -> Result<(), Error> {
+        let path = format!("{}/{}/cancel", OPENAI_API_BATCHES_ENDPOINT, batch.id);
+        let response = self
+            .retry_policy()
+            .send(self.api_request(Method::POST, &path).await?)
+            .await?;
+        batch.update(decode_json::<BatchObject>(response.text().await?)?);
+        Ok(())
+    }
+
+    /// Downloads and decodes the results of a finished batch, keyed by
+    /// `custom_id`.
+    ///
+    /// Each result is decoded like the synchronous primitive, without repairs.
+    /// Requests the job never ran, because it expired or was cancelled, fail
+    /// with `Error::Cancelled`. The cost of each result is the price table's
+    /// price with the batch discount applied. Usage and cost are added to the
+    /// model's cost tracker and budget the first time the results are fetched
+    /// in full, not again if they are fetched once more; a fetch that fails
+    /// part way records nothing.
+    pub async fn batch_results<T>(
+        &self,
+        batch: &mut OpenAIBatch<T>,
+    ) -> Result<HashMap<String, Result<Response<T>, Error>>, Error> {
+        if batch.status == OpenAIBatchStatus::Failed {
+            return Err(Error::BatchFailed {
+                message: batch.errors.join("; "),
+            });
+        }
+        if !batch.status.is_finished() {
+            return Err(Error::BatchFailed {
+                message: format!("batch {} has not finished", batch.id),
+            });
+        }
+        let mut results = HashMap::new();
+        let mut spent = vec![];
+        for file_id in [&batch.output_file_id, &batch.error_file_id]
+            .into_iter()
+            .flatten()
+        {
+            let path = format!("{}/{}/content", OPENAI_API_FILES_ENDPOINT, file_id);
+            let response = self
+                .retry_policy()
+                .send(self.api_request(Method::GET, &path).await?)
+                .await?;
+            for line in response.text().await?.lines() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let line = decode_json::<BatchOutputLine>(line.to_string())?;
+                let Some(decoder) = batch.decoders.get(&line.custom_id) else {
+                    continue;
+                };
+                let result = self.decode_batch_line(
+                    line.response,
+                    line.error,
+                    decoder,
+                    batch.latency,
+                    &mut spent,
+                );
+                results.insert(line.custom_id, result);
+            }
+        }
+        if !batch.costs_recorded {
+            for (usage, cost) in spent {
+                self.cost_tracker().record(usage, cost);
+                if let Some(budget) = self.budget() {
+                    budget.record(usage, cost);
+                }
+            }
+            batch.costs_recorded = true;
+        }
+        for custom_id in batch.decoders.keys() {
+            if !results.contains_key(custom_id) {
+                results.insert(custom_id.clone(), Err(Error::Cancelled));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Decodes one result, adding what it used to `spent`.
+    fn decode_batch_line<T>(
+        &self,
+        response: Option<BatchOutputResponse>,
+        error: Option<BatchError>,
+        decoder: &BatchDecoder<T>,
+        latency: Duration,
+        spent: &mut Vec<(Usage, Option<f64>)>,
+    ) -> Result<Response<T>, Error> {
+        let Some(response) = response else {
+            return Err(Error::BatchFailed {
+                message: error
+                    .map(|error| error.describe())
+                    .unwrap_or_else(|| String::from("no response")),
+            });
+        };
+        let body = response.body.to_string();
+        if !(200..300).contains(&response.status_code) {
+            return Err(Error::from_status(response.status_code, body, None));
+        }
+        let generation = decode_chat_response(body, decoder.force_json, HeaderMap::new())?;
+        let answered_by = generation
+            .model
+            .unwrap_or_else(|| self.model_name().to_string());
+        let cost = generation.usage.and_then(|usage| {
+            self.price_table()
+                .cost(&answered_by, &usage)
+                .map(|cost| cost * BATCH_PRICE_FACTOR)
+        });
+        if let Some(usage) = generation.usage {
+            spent.push((usage, cost));
+        }
+        let value = (decoder.decode)(generation.message)?;
+        Ok(Response {
+            value,
+            repairs: vec![],
+            usage: generation.usage,
+            cost,
+            model: answered_by,
+            latency,
+            request_id: response.request_id,
+            cached: false,
+            served_by: None,
+        })
+    }
+}
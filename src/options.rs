@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Per-call settings for the `*_with_options` methods on `Model`.
+///
+/// Every field is optional. Unset fields fall back to the defaults configured
+/// on the model, and then to the provider's own defaults, except for
+/// `temperature`, which the primitives set to 0 unless told otherwise.
+/// Settings a provider does not support are ignored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CallOptions {
+    pub(crate) max_repairs: Option<u32>,
+    pub(crate) temperature: Option<f64>,
+    pub(crate) top_p: Option<f64>,
+    pub(crate) max_tokens: Option<u32>,
+    pub(crate) stop: Option<Vec<String>>,
+    pub(crate) seed: Option<u64>,
+    pub(crate) presence_penalty: Option<f64>,
+    pub(crate) frequency_penalty: Option<f64>,
+    pub(crate) logit_bias: Option<HashMap<u32, i32>>,
+    pub(crate) user: Option<String>,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) bypass_cache: Option<bool>,
+}
+
+pub struct CallOptionsBuilder {
+    options: CallOptions,
+}
+
+impl CallOptionsBuilder {
+    pub fn new() -> Self {
+        CallOptionsBuilder {
+            options: CallOptions::default(),
+        }
+    }
+
+    /// How many times an invalid structured response is sent back to the model
+    /// together with the validation error before giving up. Defaults to 0,
+    /// which returns the first error as-is.
+    pub fn max_repairs(&mut self, max_repairs: u32) -> &mut Self {
+        self.options.max_repairs = Some(max_repairs);
+        self
+    }
+
+    pub fn temperature(&mut self, temperature: f64) -> &mut Self {
+        self.options.temperature = Some(temperature);
+        self
+    }
+
+    pub fn top_p(&mut self, top_p: f64) -> &mut Self {
+        self.options.top_p = Some(top_p);
+        self
+    }
+
+    /// Upper bound on the number of tokens generated.
+    pub fn max_tokens(&mut self, max_tokens: u32) -> &mut Self {
+        self.options.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Sequences that end generation when produced.
+    pub fn stop(&mut self, stop: Vec<String>) -> &mut Self {
+        self.options.stop = Some(stop);
+        self
+    }
+
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.options.seed = Some(seed);
+        self
+    }
+
+    pub fn presence_penalty(&mut self, presence_penalty: f64) -> &mut Self {
+        self.options.presence_penalty = Some(presence_penalty);
+        self
+    }
+
+    pub fn frequency_penalty(&mut self, frequency_penalty: f64) -> &mut Self {
+        self.options.frequency_penalty = Some(frequency_penalty);
+        self
+    }
+
+    /// Bias added to the logits of the given token ids, from -100 to 100.
+    pub fn logit_bias(&mut self, logit_bias: HashMap<u32, i32>) -> &mut Self {
+        self.options.logit_bias = Some(logit_bias);
+        self
+    }
+
+    /// An id for the end user, passed on to providers for abuse monitoring.
+    pub fn user(&mut self, user: String) -> &mut Self {
+        self.options.user = Some(user);
+        self
+    }
+
+    /// Time allowed for each HTTP request of the call, overriding the client's
+    /// timeout.
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    /// Neither reads from nor writes to the model's response cache.
+    pub fn bypass_cache(&mut self, bypass_cache: bool) -> &mut Self {
+        self.options.bypass_cache = Some(bypass_cache);
+        self
+    }
+
+    pub fn build(&self) -> CallOptions {
+        self.options.clone()
+    }
+}
+
+impl Default for CallOptionsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CallOptions {
+    /// These options with every field that is set in `overrides` replaced.
+    pub(crate) fn overridden_by(&self, overrides: &CallOptions) -> CallOptions {
+        CallOptions {
+            max_repairs: overrides.max_repairs.or(self.max_repairs),
+            temperature: overrides.temperature.or(self.temperature),
+            top_p: overrides.top_p.or(self.top_p),
+            max_tokens: overrides.max_tokens.or(self.max_tokens),
+            stop: overrides.stop.clone().or_else(|| self.stop.clone()),
+            seed: overrides.seed.or(self.seed),
+            presence_penalty: overrides.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: overrides.frequency_penalty.or(self.frequency_penalty),
+            logit_bias: overrides
+                .logit_bias
+                .clone()
+                .or_else(|| self.logit_bias.clone()),
+            user: overrides.user.clone().or_else(|| self.user.clone()),
+            timeout: overrides.timeout.or(self.timeout),
+            bypass_cache: overrides.bypass_cache.or(self.bypass_cache),
+        }
+    }
+}
@@ -0,0 +1,682 @@
+use futures::StreamExt;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, to_string_pretty, Value};
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::cache::{is_cacheable, request_key};
+use crate::http::request_id;
+use crate::rate_limit::estimate_tokens;
+use crate::schema;
+use crate::vote::Samples;
+use crate::{
+    display_choices, json_response_to_obj, single_property_schema, struct_to_json_schema,
+    struct_to_json_schema_string, Cache, CallOptions, ChatModel, Error, GenerateMessageOptions,
+    GenerateMessageOptionsBuilder, Generation, Message, MessageRole, RepairAttempt, Response,
+    TextDelta, TextStream, Usage,
+};
+
+#[derive(Deserialize)]
+struct ClassificationResponse {
+    classification: String,
+}
+
+#[derive(Deserialize)]
+struct ScoreResponse<S> {
+    score: S,
+}
+
+pub(crate) async fn classify<M: ChatModel>(
+    model: &M,
+    instruction: String,
+    text: String,
+    choices: Vec<String>,
+    call_options: &CallOptions,
+) -> Result<Response<usize>, Error> {
+    let call_options = model.default_options().overridden_by(call_options);
+    let prompt = classify_prompt(instruction, text, choices, &call_options);
+    generate_valid(model, prompt, &call_options).await
+}
+
+/// Builds the request for `classify`. `call_options` must already include the
+/// model's defaults.
+pub(crate) fn classify_prompt(
+    instruction: String,
+    text: String,
+    choices: Vec<String>,
+    call_options: &CallOptions,
+) -> Prompt<impl Fn(Message) -> Result<usize, Error> + Send + Sync> {
+    let (choices_display, lookup_table) = display_choices(choices);
+    let mut labels: Vec<&String> = lookup_table.keys().collect();
+    labels.sort_by_key(|label| lookup_table[*label]);
+    let json_schema =
+        single_property_schema("classification", json!({"type": "string", "enum": labels}));
+    let input_text = format!(
+        "Instruction:\n{}\n\nText:\n{}\n\nChoices:\n{}\n\nValid JSON:",
+        instruction, text, choices_display
+    );
+    let messages = vec![
+        Message {
+            role: MessageRole::System,
+            content: String::from("Classify the following text with the provided instruction and choices. To classify, provide the key of the choice:\n{\"classification\": string}\n\nFor example, if the correct choice is 'Z. description of choice Z', then provide 'Z' as the classification as valid JSON:\n{\"classification\": \"Z\"}"),
+            obj: None,
+        },
+        Message {
+            role: MessageRole::User,
+            content: input_text,
+            obj: None,
+        },
+    ];
+    let options = GenerateMessageOptionsBuilder::new()
+        .temperature(0.0)
+        .call_options(call_options)
+        .force_json(true)
+        .json_schema(json_schema)
+        .build();
+    Prompt {
+        messages,
+        options,
+        decode: move |message| decode_classification(message, &lookup_table),
+    }
+}
+
+pub(crate) async fn binary_classify<M: ChatModel>(
+    model: &M,
+    instruction: String,
+    text: String,
+    call_options: &CallOptions,
+) -> Result<Response<bool>, Error> {
+    classify(
+        model,
+        instruction,
+        text,
+        vec!["true".to_string(), "false".to_string()],
+        call_options,
+    )
+    .await
+    .map(|response| response.map(|index| index == 0))
+}
+
+pub(crate) async fn generate_text<M: ChatModel>(
+    model: &M,
+    instruction: String,
+    text: String,
+    call_options: &CallOptions,
+) -> Result<Response<String>, Error> {
+    let call_options = model.default_options().overridden_by(call_options);
+    let prompt = generate_text_prompt(instruction, text, &call_options);
+    generate_valid(model, prompt, &call_options).await
+}
+
+/// Builds the request for `generate_text`.
+pub(crate) fn generate_text_prompt(
+    instruction: String,
+    text: String,
+    call_options: &CallOptions,
+) -> Prompt<impl Fn(Message) -> Result<String, Error> + Send + Sync> {
+    let messages = vec![
+        Message {
+            role: MessageRole::System,
+            content: instruction,
+            obj: None,
+        },
+        Message {
+            role: MessageRole::User,
+            content: text,
+            obj: None,
+        },
+    ];
+    let options = GenerateMessageOptionsBuilder::new()
+        .temperature(0.0)
+        .call_options(call_options)
+        .force_json(false)
+        .build();
+    Prompt {
+        messages,
+        options,
+        decode: |message: Message| Ok(message.content),
+    }
+}
+
+pub(crate) async fn generate_text_stream<M: ChatModel>(
+    model: &M,
+    instruction: String,
+    text: String,
+    call_options: &CallOptions,
+) -> Result<TextStream, Error> {
+    let call_options = model.default_options().overridden_by(call_options);
+    let Prompt {
+        messages, options, ..
+    } = generate_text_prompt(instruction, text, &call_options);
+    if let Some(budget) = model.budget() {
+        budget.acquire()?;
+    }
+    let estimated_tokens = estimate_tokens(&messages, options.max_tokens);
+    if let Some(rate_limiter) = model.rate_limiter() {
+        rate_limiter.acquire(estimated_tokens).await;
+    }
+    let stream = model.stream_message(messages, options).await?;
+    let model_name = model.model_name().to_string();
+    let price_table = model.price_table().clone();
+    let cost_tracker = model.cost_tracker().clone();
+    let budget = model.budget().cloned();
+    let rate_limiter = model.rate_limiter().cloned();
+    Ok(Box::pin(stream.map(move |mut delta| {
+        if let Ok(TextDelta {
+            usage: Some(usage),
+            cost,
+            ..
+        }) = &mut delta
+        {
+            *cost = price_table.cost(&model_name, usage);
+            cost_tracker.record(*usage, *cost);
+            if let Some(budget) = &budget {
+                budget.record(*usage, *cost);
+            }
+            if let Some(rate_limiter) = &rate_limiter {
+                rate_limiter.reconcile(estimated_tokens, usage.input_tokens + usage.output_tokens);
+            }
+        }
+        delta
+    })))
+}
+
+pub(crate) async fn score_float<M: ChatModel>(
+    model: &M,
+    instruction: String,
+    text: String,
+    min_bound: f64,
+    max_bound: f64,
+    call_options: &CallOptions,
+) -> Result<Response<f64>, Error> {
+    let call_options = model.default_options().overridden_by(call_options);
+    let prompt = score_float_prompt(instruction, text, min_bound, max_bound, &call_options);
+    generate_valid(model, prompt, &call_options).await
+}
+
+/// Builds the request for `score_float`.
+pub(crate) fn score_float_prompt(
+    instruction: String,
+    text: String,
+    min_bound: f64,
+    max_bound: f64,
+    call_options: &CallOptions,
+) -> Prompt<impl Fn(Message) -> Result<f64, Error> + Send + Sync> {
+    let input_text = format!(
+        "Instruction:\n{}\n\nText:\n{}\n\nRange:\n[{}, {}]\n\nValid JSON:",
+        instruction, text, min_bound, max_bound
+    );
+    let messages = vec![
+        Message {
+            role: MessageRole::System,
+            content: String::from("Score the following text with the provided instruction and range as a float value as valid JSON:\n{\"score\": float}"),
+            obj: None,
+        },
+        Message {
+            role: MessageRole::User,
+            content: input_text,
+            obj: None,
+        },
+    ];
+    let options = GenerateMessageOptionsBuilder::new()
+        .temperature(0.0)
+        .call_options(call_options)
+        .force_json(true)
+        .json_schema(single_property_schema("score", json!({"type": "number"})))
+        .build();
+    Prompt {
+        messages,
+        options,
+        decode: move |message| decode_score_float(message, min_bound, max_bound),
+    }
+}
+
+pub(crate) async fn score_int<M: ChatModel>(
+    model: &M,
+    instruction: String,
+    text: String,
+    min_bound: i64,
+    max_bound: i64,
+    call_options: &CallOptions,
+) -> Result<Response<i64>, Error> {
+    let call_options = model.default_options().overridden_by(call_options);
+    let prompt = score_int_prompt(instruction, text, min_bound, max_bound, &call_options);
+    generate_valid(model, prompt, &call_options).await
+}
+
+/// Builds the request for `score_int`.
+pub(crate) fn score_int_prompt(
+    instruction: String,
+    text: String,
+    min_bound: i64,
+    max_bound: i64,
+    call_options: &CallOptions,
+) -> Prompt<impl Fn(Message) -> Result<i64, Error> + Send + Sync> {
+    let input_text = format!(
+        "Instruction:\n{}\n\nText:\n{}\n\nRange:\n[{}, {}]\n\nValid JSON:",
+        instruction, text, min_bound, max_bound
+    );
+    let messages = vec![
+        Message {
+            role: MessageRole::System,
+            content: String::from("Score the following text with the provided instruction and range as an integer value as valid JSON:\n{\"score\": int}"),
+            obj: None,
+        },
+        Message {
+            role: MessageRole::User,
+            content: input_text,
+            obj: None,
+        },
+    ];
+    let options = GenerateMessageOptionsBuilder::new()
+        .temperature(0.0)
+        .call_options(call_options)
+        .force_json(true)
+        .json_schema(single_property_schema("score", json!({"type": "integer"})))
+        .build();
+    Prompt {
+        messages,
+        options,
+        decode: move |message| decode_score_int(message, min_bound, max_bound),
+    }
+}
+
+pub(crate) async fn parse<M, T>(
+    model: &M,
+    text: String,
+    call_options: &CallOptions,
+) -> Result<Response<T>, Error>
+where
+    M: ChatModel,
+    T: for<'de> Deserialize<'de> + JsonSchema,
+{
+    let call_options = model.default_options().overridden_by(call_options);
+    let prompt = parse_prompt::<T>(text, &call_options);
+    generate_valid(model, prompt, &call_options).await
+}
+
+/// Builds the request for `parse`.
+pub(crate) fn parse_prompt<T>(
+    text: String,
+    call_options: &CallOptions,
+) -> Prompt<impl Fn(Message) -> Result<T, Error> + Send + Sync>
+where
+    T: for<'de> Deserialize<'de> + JsonSchema,
+{
+    schema_prompt(
+        text,
+        &struct_to_json_schema_string::<T>(),
+        struct_to_json_schema::<T>(),
+        call_options,
+    )
+}
+
+pub(crate) async fn parse_value<M: ChatModel>(
+    model: &M,
+    text: String,
+    schema: Value,
+    call_options: &CallOptions,
+) -> Result<Response<Value>, Error> {
+    let call_options = model.default_options().overridden_by(call_options);
+    let prompt = parse_value_prompt(text, schema, &call_options);
+    generate_valid(model, prompt, &call_options).await
+}
+
+/// Builds the request for `parse_value`.
+pub(crate) fn parse_value_prompt(
+    text: String,
+    schema: Value,
+    call_options: &CallOptions,
+) -> Prompt<impl Fn(Message) -> Result<Value, Error> + Send + Sync> {
+    let json_schema_string = to_string_pretty(&schema).unwrap();
+    let Prompt {
+        messages,
+        options,
+        decode,
+    } = schema_prompt::<Value>(text, &json_schema_string, schema.clone(), call_options);
+    Prompt {
+        messages,
+        options,
+        decode: move |message: Message| {
+            let value = decode(message)?;
+            schema::check(&value, &schema)
+                .map_err(|problem| Error::schema_mismatch(value.to_string(), &problem))?;
+            Ok(value)
+        },
+    }
+}
+
+/// Asks for `text` as JSON that follows `json_schema`, decoded into `T`.
+fn schema_prompt<T>(
+    text: String,
+    json_schema_string: &str,
+    json_schema: Value,
+    call_options: &CallOptions,
+) -> Prompt<impl Fn(Message) -> Result<T, Error> + Send + Sync>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let input_text = format!(
+        "Text:\n{}\n\nSchema:\n{}\n\nValid JSON:",
+        text, json_schema_string
+    );
+    let messages = vec![
+        Message {
+            role: MessageRole::System,
+            content: String::from("Parse the following text with the provided schema."),
+            obj: None,
+        },
+        Message {
+            role: MessageRole::User,
+            content: input_text,
+            obj: None,
+        },
+    ];
+    let options = GenerateMessageOptionsBuilder::new()
+        .temperature(0.0)
+        .call_options(call_options)
+        .force_json(true)
+        .json_schema(json_schema)
+        .build();
+    Prompt {
+        messages,
+        options,
+        decode: decode_object::<T>,
+    }
+}
+
+/// The messages and options a primitive sends, and how it decodes the answer.
+pub(crate) struct Prompt<F> {
+    pub(crate) messages: Vec<Message>,
+    pub(crate) options: GenerateMessageOptions,
+    pub(crate) decode: F,
+}
+
+/// Generates a message for `prompt` and decodes it. With repairs enabled, a
+/// response that fails to decode is appended to the conversation along with a
+/// description of the error and the model is asked again.
+async fn generate_valid<M, T, F>(
+    model: &M,
+    prompt: Prompt<F>,
+    call_options: &CallOptions,
+) -> Result<Response<T>, Error>
+where
+    M: ChatModel,
+    F: Fn(Message) -> Result<T, Error>,
+{
+    let Prompt {
+        mut messages,
+        options,
+        decode,
+    } = prompt;
+    let started = Instant::now();
+    let mut repairs = vec![];
+    let mut usage: Option<Usage> = None;
+    loop {
+        let can_repair = repairs.len() < call_options.max_repairs.unwrap_or(0) as usize;
+        let result = send_message(model, &messages, &options, call_options).await;
+        let (content, error) = match result {
+            Ok((generation, cached, cache_slot)) => {
+                let answered_by = generation
+                    .model
+                    .clone()
+                    .unwrap_or_else(|| model.model_name().to_string());
+                if let Some(generation_usage) = generation.usage.filter(|_| !cached) {
+                    record_usage(model, &answered_by, generation_usage);
+                    *usage.get_or_insert_with(Usage::default) += generation_usage;
+                }
+                let content = generation.message.content.clone();
+                // Only checked here and decoded again below, so that no value
+                // is held across the cache write: `T` need not be `Send`.
+                match decode(generation.message.clone()).map(drop) {
+                    Ok(()) => {
+                        fill_cache(cache_slot, &generation).await;
+                        let value = decode(generation.message)?;
+                        return Ok(Response {
+                            value,
+                            repairs,
+                            usage,
+                            cost: usage
+                                .and_then(|usage| model.price_table().cost(&answered_by, &usage)),
+                            model: answered_by,
+                            latency: started.elapsed(),
+                            request_id: request_id(&generation.headers),
+                            cached,
+                            served_by: None,
+                        });
+                    }
+                    Err(error) if can_repair && is_repairable(&error) => (content, error),
+                    Err(error) => return Err(error),
+                }
+            }
+            // Backends that enforce JSON mode reject unparseable completions
+            // themselves, keeping the completion as `raw`.
+            Err(Error::MalformedJson { raw, serde_error }) if can_repair => {
+                (raw.clone(), Error::MalformedJson { raw, serde_error })
+            }
+            Err(error) => return Err(error),
+        };
+        messages.push(Message {
+            role: MessageRole::Assistant,
+            content: content.clone(),
+            obj: None,
+        });
+        messages.push(Message {
+            role: MessageRole::User,
+            content: repair_instruction(&error),
+            obj: None,
+        });
+        repairs.push(RepairAttempt { content, error });
+    }
+}
+
+/// Asks for `n` completions of `prompt` in one request and decodes each of
+/// them. Completions that fail to decode are not repaired. Backends that
+/// ignore `n` return fewer.
+pub(crate) async fn sample_n<M, T, F>(
+    model: &M,
+    prompt: Prompt<F>,
+    call_options: &CallOptions,
+    n: usize,
+) -> Result<Samples<T>, Error>
+where
+    M: ChatModel,
+    F: Fn(Message) -> Result<T, Error>,
+{
+    let Prompt {
+        messages,
+        mut options,
+        decode,
+    } = prompt;
+    options.n = Some(n.min(u32::MAX as usize) as u32);
+    let (generation, cached, cache_slot) =
+        send_message(model, &messages, &options, call_options).await?;
+    let answered_by = generation
+        .model
+        .clone()
+        .unwrap_or_else(|| model.model_name().to_string());
+    let usage = generation.usage.filter(|_| !cached);
+    if let Some(usage) = usage {
+        record_usage(model, &answered_by, usage);
+    }
+    let values: Vec<Result<T, Error>> = std::iter::once(generation.message.clone())
+        .chain(generation.alternatives.iter().cloned())
+        .map(decode)
+        .collect();
+    if values.iter().all(Result::is_ok) {
+        fill_cache(cache_slot, &generation).await;
+    }
+    Ok(Samples {
+        values,
+        repairs: vec![],
+        usage,
+        cost: usage.and_then(|usage| model.price_table().cost(&answered_by, &usage)),
+        model: Some(answered_by),
+        served_by: None,
+        request_id: request_id(&generation.headers),
+    })
+}
+
+/// Adds what one request used to the model's cost tracker and budget.
+fn record_usage<M: ChatModel>(model: &M, answered_by: &str, usage: Usage) {
+    let cost = model.price_table().cost(answered_by, &usage);
+    model.cost_tracker().record(usage, cost);
+    if let Some(budget) = model.budget() {
+        budget.record(usage, cost);
+    }
+}
+
+/// The cache and key a fresh generation is stored under once it has decoded,
+/// so that an answer that fails validation is never served from the cache.
+type CacheSlot<'a> = Option<(&'a Cache, String)>;
+
+/// Sends one request for `messages`, or answers it from the model's cache.
+/// Returns the generation, whether it came from the cache, and where to cache
+/// it with `fill_cache` if it did not and turns out to be valid.
+async fn send_message<'a, M: ChatModel>(
+    model: &'a M,
+    messages: &[Message],
+    options: &GenerateMessageOptions,
+    call_options: &CallOptions,
+) -> Result<(Generation, bool, CacheSlot<'a>), Error> {
+    let cache = model
+        .cache()
+        .filter(|_| !call_options.bypass_cache.unwrap_or(false) && is_cacheable(options))
+        .map(|cache| {
+            (
+                cache,
+                request_key(model.base_url(), model.model_name(), messages, options),
+            )
+        });
+    if let Some((cache, request)) = &cache {
+        if let Some(generation) = cache.get(request).await {
+            return Ok((generation, true, None));
+        }
+    }
+    if let Some(budget) = model.budget() {
+        budget.acquire()?;
+    }
+    let estimated_tokens = estimate_tokens(messages, options.max_tokens);
+    if let Some(rate_limiter) = model.rate_limiter() {
+        rate_limiter.acquire(estimated_tokens).await;
+    }
+    let result = model
+        .generate_message(messages.to_vec(), options.clone())
+        .await;
+    if let Some(rate_limiter) = model.rate_limiter() {
+        match &result {
+            Ok(generation) => {
+                rate_limiter.observe(&generation.headers);
+                if let Some(usage) = generation.usage {
+                    rate_limiter
+                        .reconcile(estimated_tokens, usage.input_tokens + usage.output_tokens);
+                }
+            }
+            Err(Error::RateLimited {
+                retry_after: Some(retry_after),
+                ..
+            }) => rate_limiter.pause(*retry_after),
+            Err(_) => {}
+        }
+    }
+    Ok((result?, false, cache))
+}
+
+async fn fill_cache(cache_slot: CacheSlot<'_>, generation: &Generation) {
+    if let Some((cache, request)) = cache_slot {
+        cache.put(&request, generation).await;
+    }
+}
+
+fn decode_object<T>(message: Message) -> Result<T, Error>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    match message.obj {
+        Some(obj) => json_response_to_obj::<T>(obj),
+        None => Err(Error::schema_mismatch(
+            message.content,
+            "expected a JSON object",
+        )),
+    }
+}
+
+fn decode_classification(
+    message: Message,
+    lookup_table: &HashMap<String, usize>,
+) -> Result<usize, Error> {
+    let response = decode_object::<ClassificationResponse>(message)?;
+    match lookup_table.get(&response.classification) {
+        Some(choice_index) => Ok(*choice_index),
+        None => Err(Error::InvalidChoice {
+            raw: response.classification,
+        }),
+    }
+}
+
+fn decode_score_float(message: Message, min_bound: f64, max_bound: f64) -> Result<f64, Error> {
+    let score = decode_object::<ScoreResponse<f64>>(message)?.score;
+    if !(min_bound..=max_bound).contains(&score) {
+        return Err(Error::OutOfRange {
+            value: score,
+            min_bound,
+            max_bound,
+        });
+    }
+    Ok(score)
+}
+
+fn decode_score_int(message: Message, min_bound: i64, max_bound: i64) -> Result<i64, Error> {
+    let score = decode_object::<ScoreResponse<i64>>(message)?.score;
+    if !(min_bound..=max_bound).contains(&score) {
+        return Err(Error::OutOfRange {
+            value: score as f64,
+            min_bound: min_bound as f64,
+            max_bound: max_bound as f64,
+        });
+    }
+    Ok(score)
+}
+
+fn is_repairable(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::MalformedJson { .. }
+            | Error::SchemaMismatch { .. }
+            | Error::InvalidChoice { .. }
+            | Error::OutOfRange { .. }
+    )
+}
+
+/// The follow-up turn that tells the model what was wrong with its answer.
+fn repair_instruction(error: &Error) -> String {
+    let problem = match error {
+        Error::MalformedJson { serde_error, .. } => {
+            format!("Your response is not valid JSON: {}.", serde_error)
+        }
+        Error::SchemaMismatch { serde_error, .. } => {
+            format!(
+                "Your response does not match the expected format: {}.",
+                serde_error
+            )
+        }
+        Error::InvalidChoice { raw } => format!(
+            "\"{}\" is not one of the choices. Provide the key of one of the listed choices.",
+            raw
+        ),
+        Error::OutOfRange {
+            value,
+            min_bound,
+            max_bound,
+        } => format!(
+            "The score {} is outside the range [{}, {}].",
+            value, min_bound, max_bound
+        ),
+        error => error.to_string(),
+    };
+    format!(
+        "{} Respond again with only the corrected valid JSON.",
+        problem
+    )
+}
@@ -0,0 +1,224 @@
+use reqwest::header::HeaderMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::retry::parse_reset_duration;
+use crate::Message;
+
+/// Characters per token assumed when estimating the size of a prompt. This is
+/// about right for English text with OpenAI's tokenizers.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Tokens added per message for role markers and separators.
+const TOKENS_PER_MESSAGE: u64 = 4;
+
+/// Client-side throttling for requests-per-minute and tokens-per-minute limits.
+///
+/// Both limits are token buckets that refill continuously, so a full minute's
+/// allowance can be used in a burst and then trickles back. Before each request
+/// the prompt size is estimated and the request waits until both buckets have
+/// room; once the response reports actual usage the difference is given back or
+/// taken. `x-ratelimit-remaining-*` headers lower the buckets to what the
+/// server reports, and an exhausted limit or a 429 pauses all requests until
+/// the server says it resets.
+///
+/// Clones share the same buckets, so models that use the same API key should
+/// share one limiter.
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    requests_per_minute: Option<u32>,
+    tokens_per_minute: Option<u64>,
+    state: Arc<Mutex<RateLimiterState>>,
+}
+
+#[derive(Debug)]
+struct RateLimiterState {
+    requests: f64,
+    tokens: f64,
+    updated: Instant,
+    paused_until: Option<Instant>,
+}
+
+pub struct RateLimiterBuilder {
+    requests_per_minute: Option<u32>,
+    tokens_per_minute: Option<u64>,
+}
+
+impl RateLimiterBuilder {
+    pub fn new() -> Self {
+        RateLimiterBuilder {
+            requests_per_minute: None,
+            tokens_per_minute: None,
+        }
+    }
+
+    /// At least 1; a limit of 0 would never let a request through.
+    pub fn requests_per_minute(&mut self, requests_per_minute: u32) -> &mut Self {
+        self.requests_per_minute = Some(requests_per_minute.max(1));
+        self
+    }
+
+    /// At least 1; a limit of 0 would never let a request through.
+    pub fn tokens_per_minute(&mut self, tokens_per_minute: u64) -> &mut Self {
+        self.tokens_per_minute = Some(tokens_per_minute.max(1));
+        self
+    }
+
+    pub fn build(&self) -> RateLimiter {
+        RateLimiter {
+            requests_per_minute: self.requests_per_minute,
+            tokens_per_minute: self.tokens_per_minute,
+            state: Arc::new(Mutex::new(RateLimiterState {
+                requests: self.requests_per_minute.unwrap_or(0) as f64,
+                tokens: self.tokens_per_minute.unwrap_or(0) as f64,
+                updated: Instant::now(),
+                paused_until: None,
+            })),
+        }
+    }
+}
+
+impl Default for RateLimiterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RateLimiter {
+    /// Waits until a request of about `estimated_tokens` fits in both limits
+    /// and takes it out of the buckets.
+    pub(crate) async fn acquire(&self, estimated_tokens: u64) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().unwrap();
+                let now = Instant::now();
+                self.refill(&mut state, now);
+                match state
+                    .paused_until
+                    .filter(|paused_until| *paused_until > now)
+                {
+                    Some(paused_until) => paused_until - now,
+                    None => {
+                        // A request larger than the whole bucket could never
+                        // fit, so it only waits for a full one.
+                        let needed_tokens = match self.tokens_per_minute {
+                            Some(capacity) => estimated_tokens.min(capacity) as f64,
+                            None => 0.0,
+                        };
+                        let request_wait = Self::time_to_fill(
+                            state.requests,
+                            1.0,
+                            self.requests_per_minute.map(f64::from),
+                        );
+                        let token_wait = Self::time_to_fill(
+                            state.tokens,
+                            needed_tokens,
+                            self.tokens_per_minute.map(|tokens| tokens as f64),
+                        );
+                        let wait = request_wait.max(token_wait);
+                        if wait.is_zero() {
+                            if self.requests_per_minute.is_some() {
+                                state.requests -= 1.0;
+                            }
+                            if self.tokens_per_minute.is_some() {
+                                state.tokens -= estimated_tokens as f64;
+                            }
+                            return;
+                        }
+                        wait
+                    }
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Corrects the token bucket once the actual usage of a request is known.
+    pub(crate) fn reconcile(&self, estimated_tokens: u64, actual_tokens: u64) {
+        let Some(capacity) = self.tokens_per_minute else {
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        state.tokens =
+            (state.tokens + estimated_tokens as f64 - actual_tokens as f64).min(capacity as f64);
+    }
+
+    /// Lowers the buckets to the remaining allowance reported by the server and
+    /// pauses until the reset time of any limit that is used up.
+    pub(crate) fn observe(&self, headers: &HeaderMap) {
+        let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        for limit in ["requests", "tokens"] {
+            let Some(remaining) = header(&format!("x-ratelimit-remaining-{}", limit))
+                .and_then(|value| value.trim().parse::<f64>().ok())
+            else {
+                continue;
+            };
+            match limit {
+                "requests" => state.requests = state.requests.min(remaining),
+                _ => state.tokens = state.tokens.min(remaining),
+            }
+            if remaining <= 0.0 {
+                if let Some(reset) =
+                    header(&format!("x-ratelimit-reset-{}", limit)).and_then(parse_reset_duration)
+                {
+                    Self::pause_until(&mut state, now + reset);
+                }
+            }
+        }
+    }
+
+    /// Holds back every request for `duration`, e.g. after a 429.
+    pub(crate) fn pause(&self, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        Self::pause_until(&mut state, Instant::now() + duration);
+    }
+
+    fn pause_until(state: &mut RateLimiterState, until: Instant) {
+        if state
+            .paused_until
+            .is_none_or(|paused_until| paused_until < until)
+        {
+            state.paused_until = Some(until);
+        }
+    }
+
+    fn refill(&self, state: &mut RateLimiterState, now: Instant) {
+        let minutes = (now - state.updated).as_secs_f64() / 60.0;
+        if let Some(capacity) = self.requests_per_minute {
+            state.requests = (state.requests + minutes * capacity as f64).min(capacity as f64);
+        }
+        if let Some(capacity) = self.tokens_per_minute {
+            state.tokens = (state.tokens + minutes * capacity as f64).min(capacity as f64);
+        }
+        state.updated = now;
+    }
+
+    /// How long until a bucket at `level` holds `needed`.
+    fn time_to_fill(level: f64, needed: f64, per_minute: Option<f64>) -> Duration {
+        match per_minute {
+            Some(per_minute) if level < needed => {
+                Duration::from_secs_f64((needed - level) / (per_minute / 60.0))
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// A rough token count for `messages` plus the completion they may produce,
+/// which providers count against tokens-per-minute limits up front.
+pub(crate) fn estimate_tokens(messages: &[Message], max_tokens: Option<u32>) -> u64 {
+    messages
+        .iter()
+        .map(|message| estimate_text_tokens([message.content.as_str()]) + TOKENS_PER_MESSAGE)
+        .sum::<u64>()
+        + max_tokens.unwrap_or(0) as u64
+}
+
+/// A rough token count for `texts` taken together, from the number of
+/// characters rather than bytes so non-ASCII text is not overcounted.
+pub(crate) fn estimate_text_tokens<'a>(texts: impl IntoIterator<Item = &'a str>) -> u64 {
+    let chars: usize = texts.into_iter().map(|text| text.chars().count()).sum();
+    chars.div_ceil(CHARS_PER_TOKEN) as u64
+}
@@ -0,0 +1,92 @@
+use serde::{Deserialize, Serialize};
+use std::ops::{Add, AddAssign};
+use std::time::Duration;
+
+use crate::Error;
+
+/// The value returned by a primitive together with how it was obtained.
+#[derive(Debug)]
+pub struct Response<T> {
+    pub value: T,
+    /// Responses that failed validation and were sent back for repair, oldest
+    /// first. Empty when the first response was valid.
+    pub repairs: Vec<RepairAttempt>,
+    /// Tokens used by every request of the call, including repairs, or `None`
+    /// if the provider did not report any.
+    pub usage: Option<Usage>,
+    /// Dollar cost of `usage`, if the model has a price for it.
+    pub cost: Option<f64>,
+    /// The model that answered, as reported by the provider when it does.
+    pub model: String,
+    /// Time spent on the whole call, including retries and repairs.
+    pub latency: Duration,
+    /// The provider's id for the last request, for support tickets and logs.
+    pub request_id: Option<String>,
+    /// Whether the final answer was served from the model's response cache.
+    pub cached: bool,
+    /// The name a `FallbackModel` or `RouterModel` gave the model that served
+    /// the call, or `None` for a call made on a model directly.
+    pub served_by: Option<String>,
+}
+
+impl<T> Response<T> {
+    /// Converts the value while keeping everything else about the call.
+    pub fn map<U, F>(self, f: F) -> Response<U>
+    where
+        F: FnOnce(T) -> U,
+    {
+        Response {
+            value: f(self.value),
+            repairs: self.repairs,
+            usage: self.usage,
+            cost: self.cost,
+            model: self.model,
+            latency: self.latency,
+            request_id: self.request_id,
+            cached: self.cached,
+            served_by: self.served_by,
+        }
+    }
+}
+
+/// A response that was rejected during a repair loop.
+#[derive(Debug)]
+pub struct RepairAttempt {
+    /// The raw content the model answered with.
+    pub content: String,
+    /// Why it was rejected. This is what the model was told on the next turn.
+    pub error: Error,
+}
+
+/// Token counts reported by the provider.
+///
+/// `input_tokens` and `output_tokens` are totals; cached and reasoning tokens
+/// are the parts of them that providers break out separately.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Input tokens served from the provider's prompt cache.
+    pub cached_input_tokens: u64,
+    /// Output tokens spent on hidden reasoning.
+    pub reasoning_tokens: u64,
+}
+
+impl Add for Usage {
+    type Output = Usage;
+
+    fn add(self, other: Usage) -> Usage {
+        Usage {
+            input_tokens: self.input_tokens + other.input_tokens,
+            output_tokens: self.output_tokens + other.output_tokens,
+            cached_input_tokens: self.cached_input_tokens + other.cached_input_tokens,
+            reasoning_tokens: self.reasoning_tokens + other.reasoning_tokens,
+        }
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        *self = *self + other;
+    }
+}
@@ -0,0 +1,307 @@
+use reqwest::header::HeaderMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{Cassette, Error};
+
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+const DEFAULT_INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+const DEFAULT_MULTIPLIER: f64 = 2.0;
+const DEFAULT_RETRYABLE_STATUSES: [u16; 7] = [408, 409, 429, 500, 502, 503, 504];
+
+/// What happened on one attempt of a request, passed to the `on_attempt` hook.
+#[derive(Debug)]
+pub struct Attempt<'a> {
+    /// 1-based attempt number.
+    pub number: u32,
+    /// Time spent on this attempt, excluding any backoff before it.
+    pub elapsed: Duration,
+    /// The HTTP status on success, or the error the attempt failed with.
+    pub outcome: Result<u16, &'a Error>,
+    /// How long until the next attempt, or `None` if this was the last one.
+    pub retry_in: Option<Duration>,
+}
+
+pub type AttemptHook = Arc<dyn Fn(&Attempt) + Send + Sync>;
+
+/// How a model retries failed requests.
+///
+/// Rate limits, timeouts, connection failures and the usual transient 5xx
+/// statuses are retried with exponential backoff. A delay requested by the
+/// server through `Retry-After`, `retry-after-ms` or `x-ratelimit-reset-*`
+/// takes precedence over the computed backoff. If that delay is longer than
+/// `max_backoff` the request is not retried, and the error (e.g.
+/// `Error::RateLimited` with its `retry_after`) is returned right away.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: bool,
+    retryable_statuses: Vec<u16>,
+    on_attempt: Option<AttemptHook>,
+    /// Set by the model builders. Every attempt goes through it when present.
+    cassette: Option<Cassette>,
+}
+
+pub struct RetryPolicyBuilder {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: bool,
+    retryable_statuses: Vec<u16>,
+    on_attempt: Option<AttemptHook>,
+}
+
+impl RetryPolicyBuilder {
+    pub fn new() -> Self {
+        RetryPolicyBuilder {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            initial_backoff: DEFAULT_INITIAL_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+            multiplier: DEFAULT_MULTIPLIER,
+            jitter: true,
+            retryable_statuses: DEFAULT_RETRYABLE_STATUSES.to_vec(),
+            on_attempt: None,
+        }
+    }
+
+    /// Total number of attempts, including the first one.
+    pub fn max_attempts(&mut self, max_attempts: u32) -> &mut Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn initial_backoff(&mut self, initial_backoff: Duration) -> &mut Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// The longest wait between attempts. A request whose server asks for a
+    /// longer delay is not retried.
+    pub fn max_backoff(&mut self, max_backoff: Duration) -> &mut Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    pub fn multiplier(&mut self, multiplier: f64) -> &mut Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Randomizes each backoff between half and all of its nominal value so
+    /// that concurrent callers do not retry in lockstep.
+    pub fn jitter(&mut self, jitter: bool) -> &mut Self {
+        self.jitter = jitter;
+        self
+    }
+
+    pub fn retryable_statuses(&mut self, retryable_statuses: Vec<u16>) -> &mut Self {
+        self.retryable_statuses = retryable_statuses;
+        self
+    }
+
+    /// Called after every attempt, successful or not.
+    pub fn on_attempt<F>(&mut self, on_attempt: F) -> &mut Self
+    where
+        F: Fn(&Attempt) + Send + Sync + 'static,
+    {
+        self.on_attempt = Some(Arc::new(on_attempt));
+        self
+    }
+
+    pub fn build(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            initial_backoff: self.initial_backoff,
+            max_backoff: self.max_backoff,
+            multiplier: self.multiplier,
+            jitter: self.jitter,
+            retryable_statuses: self.retryable_statuses.clone(),
+            on_attempt: self.on_attempt.clone(),
+            cassette: None,
+        }
+    }
+}
+
+impl Default for RetryPolicyBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RetryPolicy {
+    /// Sends every request exactly once. This is what models use unless a
+    /// policy is configured.
+    pub fn none() -> Self {
+        RetryPolicyBuilder::new().max_attempts(1).build()
+    }
+
+    pub(crate) fn with_cassette(mut self, cassette: Option<Cassette>) -> Self {
+        self.cassette = cassette;
+        self
+    }
+
+    /// Whether `error` is worth another attempt under this policy.
+    pub fn is_retryable(&self, error: &Error) -> bool {
+        match error {
+            Error::Http { status, .. } | Error::Auth { status, .. } => {
+                self.retryable_statuses.contains(status)
+            }
+            Error::RateLimited { .. } => self.retryable_statuses.contains(&429),
+            Error::Timeout => true,
+            Error::Transport(e) => !e.is_builder(),
+            _ => false,
+        }
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let nominal = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let capped = nominal.min(self.max_backoff.as_secs_f64());
+        let delay = if self.jitter {
+            capped * (0.5 + 0.5 * random_fraction())
+        } else {
+            capped
+        };
+        // Near `Duration::MAX` the float rounds past what a `Duration` holds.
+        seconds_to_duration(delay.max(0.0)).unwrap_or(self.max_backoff)
+    }
+
+    /// Sends `request` until it succeeds, fails with a non-retryable error, or
+    /// runs out of attempts. Only 2xx responses are returned as `Ok`.
+    pub(crate) async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        let mut next = Some(request);
+        let mut number = 0;
+        loop {
+            number += 1;
+            let current = next.take().expect("a request is kept for every retry");
+            if number < self.max_attempts {
+                // Requests with streaming bodies cannot be cloned, so they only
+                // ever get one attempt.
+                next = current.try_clone();
+            }
+            let started = Instant::now();
+            let sent = match &self.cassette {
+                Some(cassette) => cassette.send(current).await,
+                None => current.send().await.map_err(Error::from),
+            };
+            let (result, server_delay) = match sent {
+                Ok(response) if response.status().is_success() => (Ok(response), None),
+                Ok(response) => {
+                    let server_delay = retry_after(response.headers());
+                    (Err(Error::from_response(response).await), server_delay)
+                }
+                Err(e) => (Err(e), None),
+            };
+            let retry_in = match &result {
+                Err(e) if next.is_some() && self.is_retryable(e) => match server_delay {
+                    Some(delay) if delay > self.max_backoff => None,
+                    Some(delay) => Some(delay),
+                    None => Some(self.backoff(number)),
+                },
+                _ => None,
+            };
+            if let Some(on_attempt) = &self.on_attempt {
+                on_attempt(&Attempt {
+                    number,
+                    elapsed: started.elapsed(),
+                    outcome: result.as_ref().map(|response| response.status().as_u16()),
+                    retry_in,
+                });
+            }
+            match retry_in {
+                Some(retry_in) => tokio::time::sleep(retry_in).await,
+                None => return result,
+            }
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicyBuilder::new().build()
+    }
+}
+
+/// The delay a server asked for before the next request, if it sent one.
+///
+/// `Retry-After` (in seconds) and `retry-after-ms` are used as-is. Otherwise the
+/// `x-ratelimit-reset-*` headers are consulted for whichever limit has been
+/// exhausted, using OpenAI's duration format such as `1s`, `6m0s` or `20ms`.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    if let Some(millis) =
+        header("retry-after-ms").and_then(|value| value.trim().parse::<f64>().ok())
+    {
+        return seconds_to_duration(millis / 1000.0);
+    }
+    if let Some(seconds) = header("retry-after").and_then(|value| value.trim().parse::<f64>().ok())
+    {
+        return seconds_to_duration(seconds);
+    }
+    ["requests", "tokens"]
+        .iter()
+        .filter(|limit| {
+            header(&format!("x-ratelimit-remaining-{}", limit))
+                .is_none_or(|remaining| remaining.trim() == "0")
+        })
+        .filter_map(|limit| header(&format!("x-ratelimit-reset-{}", limit)))
+        .filter_map(parse_reset_duration)
+        .max()
+}
+
+fn seconds_to_duration(seconds: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Parses durations like `1h2m3.5s`, `6m0s`, `20ms` or a bare number of seconds.
+pub(crate) fn parse_reset_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return seconds_to_duration(seconds);
+    }
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(rest.len());
+        let number = rest[..number_end].parse::<f64>().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += number * scale;
+        rest = &rest[unit_end..];
+    }
+    seconds_to_duration(total)
+}
+
+/// A uniformly distributed number in `[0, 1)`. `RandomState` is seeded
+/// randomly per instance, which is plenty for spreading out retries.
+fn random_fraction() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_nanos())
+            .unwrap_or_default(),
+    );
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
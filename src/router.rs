@@ -0,0 +1,529 @@
+use futures::StreamExt;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::rate_limit::estimate_text_tokens;
+use crate::{
+    AnyModel, CallOptions, ClassifyVote, CostTracker, DynModel, Error, Model, Response, ScoreVote,
+    TextStream, Usage, VoteOptions,
+};
+
+/// The `Model` method a call was made with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Primitive {
+    Classify,
+    BinaryClassify,
+    GenerateText,
+    GenerateTextStream,
+    ScoreFloat,
+    ScoreInt,
+    /// `parse` and `parse_value`.
+    Parse,
+}
+
+/// A call as seen by a `RouteRule`.
+#[derive(Debug)]
+pub struct RouteRequest<'a> {
+    pub primitive: Primitive,
+    /// The instruction, for every primitive except `parse`.
+    pub instruction: Option<&'a str>,
+    pub text: &'a str,
+    /// The choices of a `classify` call.
+    pub choices: Option<&'a [String]>,
+    /// A rough token count of the instruction, text and choices. The rest of
+    /// the prompt, such as a schema, is not counted.
+    pub estimated_tokens: u64,
+    pub options: &'a CallOptions,
+}
+
+pub type RoutePredicate = Arc<dyn Fn(&RouteRequest) -> bool + Send + Sync>;
+
+/// Which calls a route of a `RouterModel` takes.
+#[derive(Clone)]
+pub struct RouteRule {
+    predicate: RoutePredicate,
+}
+
+impl RouteRule {
+    /// Calls to `primitive`.
+    pub fn primitive(primitive: Primitive) -> Self {
+        RouteRule::custom(move |request| request.primitive == primitive)
+    }
+
+    /// Calls whose prompt is estimated at no more than `tokens`.
+    pub fn max_tokens(tokens: u64) -> Self {
+        RouteRule::custom(move |request| request.estimated_tokens <= tokens)
+    }
+
+    /// Calls whose prompt is estimated at more than `tokens`.
+    pub fn min_tokens(tokens: u64) -> Self {
+        RouteRule::custom(move |request| request.estimated_tokens > tokens)
+    }
+
+    /// `classify` calls with at most `choices` choices.
+    pub fn max_choices(choices: usize) -> Self {
+        RouteRule::custom(move |request| {
+            request
+                .choices
+                .is_some_and(|request_choices| request_choices.len() <= choices)
+        })
+    }
+
+    /// `classify` calls with more than `choices` choices.
+    pub fn min_choices(choices: usize) -> Self {
+        RouteRule::custom(move |request| {
+            request
+                .choices
+                .is_some_and(|request_choices| request_choices.len() > choices)
+        })
+    }
+
+    pub fn custom<F>(predicate: F) -> Self
+    where
+        F: Fn(&RouteRequest) -> bool + Send + Sync + 'static,
+    {
+        RouteRule {
+            predicate: Arc::new(predicate),
+        }
+    }
+
+    /// Calls that both rules take.
+    pub fn and(self, other: RouteRule) -> Self {
+        RouteRule::custom(move |request| self.matches(request) && other.matches(request))
+    }
+
+    /// Calls that either rule takes.
+    pub fn or(self, other: RouteRule) -> Self {
+        RouteRule::custom(move |request| self.matches(request) || other.matches(request))
+    }
+
+    pub fn matches(&self, request: &RouteRequest) -> bool {
+        (self.predicate)(request)
+    }
+}
+
+/// What one route of a `RouterModel` has served so far.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RouteMetrics {
+    /// The name the route was added with.
+    pub route: String,
+    pub calls: u64,
+    pub failures: u64,
+    /// Usage reported by the route's successful calls. Streamed calls add
+    /// theirs once the stream reports it.
+    pub usage: Usage,
+    /// Dollar cost of the successful calls, where the inner model has a price.
+    pub cost: f64,
+    /// Time spent in calls, summed. Streamed calls count until the stream
+    /// starts.
+    pub latency: Duration,
+}
+
+/// A `Model` that sends each call to one of several models, e.g. cheap models
+/// for short texts and a strong one for long documents.
+///
+/// Routes are checked in the order they were added and the first whose rule
+/// takes the call serves it; calls no route takes go to the default model.
+/// The router keeps `RouteMetrics` for every route and adds what its calls use
+/// to its own cost tracker as well as the serving model's.
+#[derive(Clone)]
+pub struct RouterModel {
+    /// The default route is last and has no rule.
+    routes: Vec<(String, Option<RouteRule>, AnyModel)>,
+    metrics: Arc<Mutex<Vec<RouteMetrics>>>,
+    cost_tracker: CostTracker,
+}
+
+pub struct RouterModelBuilder {
+    routes: Vec<(String, Option<RouteRule>, AnyModel)>,
+    default_route: (String, AnyModel),
+    cost_tracker: CostTracker,
+}
+
+impl RouterModelBuilder {
+    /// A router that sends every call to `model` until routes are added.
+    pub fn new<M: DynModel + 'static>(name: String, model: M) -> Self {
+        RouterModelBuilder {
+            routes: vec![],
+            default_route: (name, AnyModel::new(model)),
+            cost_tracker: CostTracker::new(),
+        }
+    }
+
+    /// Sends the calls `rule` takes to `model`, unless a route added earlier
+    /// takes them first.
+    pub fn route<M: DynModel + 'static>(
+        &mut self,
+        name: String,
+        rule: RouteRule,
+        model: M,
+    ) -> &mut Self {
+        self.routes.push((name, Some(rule), AnyModel::new(model)));
+        self
+    }
+
+    /// Accumulates usage and cost of every routed call in `cost_tracker`.
+    pub fn cost_tracker(&mut self, cost_tracker: CostTracker) -> &mut Self {
+        self.cost_tracker = cost_tracker;
+        self
+    }
+
+    pub fn build(&self) -> RouterModel {
+        let (default_name, default_model) = self.default_route.clone();
+        let mut routes = self.routes.clone();
+        routes.push((default_name, None, default_model));
+        let metrics = routes
+            .iter()
+            .map(|(name, _, _)| RouteMetrics {
+                route: name.clone(),
+                ..RouteMetrics::default()
+            })
+            .collect();
+        RouterModel {
+            routes,
+            metrics: Arc::new(Mutex::new(metrics)),
+            cost_tracker: self.cost_tracker.clone(),
+        }
+    }
+}
+
+impl RouterModel {
+    /// Metrics of every route in the order they are checked, the default
+    /// route last.
+    pub fn metrics(&self) -> Vec<RouteMetrics> {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    /// The name of the route that would serve `request`.
+    pub fn route_name(&self, request: &RouteRequest) -> &str {
+        &self.routes[self.select(request)].0
+    }
+
+    fn select(&self, request: &RouteRequest) -> usize {
+        self.routes
+            .iter()
+            .position(|(_, rule, _)| rule.as_ref().is_none_or(|rule| rule.matches(request)))
+            .expect("the default route takes every call")
+    }
+
+    /// Records a finished call in the route's metrics and marks a response
+    /// with the route's name.
+    fn record<T>(
+        &self,
+        index: usize,
+        started: Instant,
+        result: Result<Response<T>, Error>,
+    ) -> Result<Response<T>, Error> {
+        let mut metrics = self.metrics.lock().unwrap();
+        let route = &mut metrics[index];
+        route.calls += 1;
+        route.latency += started.elapsed();
+        match result {
+            Ok(mut response) => {
+                if let Some(usage) = response.usage {
+                    route.usage += usage;
+                    route.cost += response.cost.unwrap_or(0.0);
+                    self.cost_tracker.record(usage, response.cost);
+                }
+                response.served_by = Some(route.route.clone());
+                Ok(response)
+            }
+            Err(e) => {
+                route.failures += 1;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A rough token count of what a call asks about, from the lengths of its
+/// instruction, text and choices, so routing does not build the prompt. It is
+/// estimated like the rate limiter estimates prompts.
+fn request_tokens(instruction: Option<&str>, text: &str, choices: Option<&[String]>) -> u64 {
+    let choices = choices.unwrap_or_default().iter().map(String::as_str);
+    estimate_text_tokens(instruction.into_iter().chain([text]).chain(choices))
+}
+
+impl Model for RouterModel {
+    fn cost_tracker(&self) -> &CostTracker {
+        &self.cost_tracker
+    }
+
+    async fn classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::Classify,
+            instruction: Some(&instruction),
+            text: &text,
+            choices: Some(&choices),
+            estimated_tokens: request_tokens(Some(&instruction), &text, Some(&choices)),
+            options,
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .classify_with_options(instruction, text, choices, options)
+            .await;
+        self.record(index, started, result)
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::BinaryClassify,
+            instruction: Some(&instruction),
+            text: &text,
+            choices: None,
+            estimated_tokens: request_tokens(Some(&instruction), &text, None),
+            options,
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .binary_classify_with_options(instruction, text, options)
+            .await;
+        self.record(index, started, result)
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::GenerateText,
+            instruction: Some(&instruction),
+            text: &text,
+            choices: None,
+            estimated_tokens: request_tokens(Some(&instruction), &text, None),
+            options,
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .generate_text_with_options(instruction, text, options)
+            .await;
+        self.record(index, started, result)
+    }
+
+    async fn generate_text_stream_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::GenerateTextStream,
+            instruction: Some(&instruction),
+            text: &text,
+            choices: None,
+            estimated_tokens: request_tokens(Some(&instruction), &text, None),
+            options,
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .generate_text_stream_with_options(instruction, text, options)
+            .await;
+        {
+            let mut metrics = self.metrics.lock().unwrap();
+            metrics[index].calls += 1;
+            metrics[index].latency += started.elapsed();
+            if result.is_err() {
+                metrics[index].failures += 1;
+            }
+        }
+        let name = self.routes[index].0.clone();
+        let metrics = self.metrics.clone();
+        let cost_tracker = self.cost_tracker.clone();
+        Ok(Box::pin(result?.map(move |delta| {
+            let mut delta = delta?;
+            if let Some(usage) = delta.usage {
+                let mut metrics = metrics.lock().unwrap();
+                metrics[index].usage += usage;
+                metrics[index].cost += delta.cost.unwrap_or(0.0);
+                cost_tracker.record(usage, delta.cost);
+            }
+            delta.served_by = Some(name.clone());
+            Ok(delta)
+        })))
+    }
+
+    async fn score_float_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::ScoreFloat,
+            instruction: Some(&instruction),
+            text: &text,
+            choices: None,
+            estimated_tokens: request_tokens(Some(&instruction), &text, None),
+            options,
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .score_float_with_options(instruction, text, min_bound, max_bound, options)
+            .await;
+        self.record(index, started, result)
+    }
+
+    async fn score_int_with_options(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::ScoreInt,
+            instruction: Some(&instruction),
+            text: &text,
+            choices: None,
+            estimated_tokens: request_tokens(Some(&instruction), &text, None),
+            options,
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .score_int_with_options(instruction, text, min_bound, max_bound, options)
+            .await;
+        self.record(index, started, result)
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        text: String,
+        options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::Parse,
+            instruction: None,
+            text: &text,
+            choices: None,
+            estimated_tokens: request_tokens(None, &text, None),
+            options,
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .parse_with_options::<T>(text, options)
+            .await;
+        self.record(index, started, result)
+    }
+
+    async fn parse_value_with_options(
+        &self,
+        text: String,
+        schema: Value,
+        options: &CallOptions,
+    ) -> Result<Response<Value>, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::Parse,
+            instruction: None,
+            text: &text,
+            choices: None,
+            estimated_tokens: request_tokens(None, &text, None),
+            options,
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .parse_value_with_options(text, schema, options)
+            .await;
+        self.record(index, started, result)
+    }
+
+    async fn classify_vote(
+        &self,
+        instruction: String,
+        text: String,
+        choices: Vec<String>,
+        options: &VoteOptions,
+    ) -> Result<Response<ClassifyVote>, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::Classify,
+            instruction: Some(&instruction),
+            text: &text,
+            choices: Some(&choices),
+            estimated_tokens: request_tokens(Some(&instruction), &text, Some(&choices)),
+            options: &options.sample_options(),
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .classify_vote(instruction, text, choices, options)
+            .await;
+        self.record(index, started, result)
+    }
+
+    async fn score_float_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: f64,
+        max_bound: f64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<f64>>, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::ScoreFloat,
+            instruction: Some(&instruction),
+            text: &text,
+            choices: None,
+            estimated_tokens: request_tokens(Some(&instruction), &text, None),
+            options: &options.sample_options(),
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .score_float_vote(instruction, text, min_bound, max_bound, options)
+            .await;
+        self.record(index, started, result)
+    }
+
+    async fn score_int_vote(
+        &self,
+        instruction: String,
+        text: String,
+        min_bound: i64,
+        max_bound: i64,
+        options: &VoteOptions,
+    ) -> Result<Response<ScoreVote<i64>>, Error> {
+        let index = self.select(&RouteRequest {
+            primitive: Primitive::ScoreInt,
+            instruction: Some(&instruction),
+            text: &text,
+            choices: None,
+            estimated_tokens: request_tokens(Some(&instruction), &text, None),
+            options: &options.sample_options(),
+        });
+        let started = Instant::now();
+        let result = self.routes[index]
+            .2
+            .score_int_vote(instruction, text, min_bound, max_bound, options)
+            .await;
+        self.record(index, started, result)
+    }
+}
@@ -0,0 +1,161 @@
+use serde_json::{Map, Value};
+
+/// Checks `value` against the parts of JSON Schema that `schemars` emits and
+/// that models tend to get wrong: `type`, `enum`, `const`, `properties`,
+/// `required`, `additionalProperties: false`, `items`, `$ref` into the
+/// schema's own definitions, and `allOf`/`anyOf`/`oneOf`. Other keywords are
+/// ignored, so a value this accepts may still fail to deserialize.
+///
+/// Returns a description of the first problem found.
+pub(crate) fn check(value: &Value, schema: &Value) -> Result<(), String> {
+    Checker { root: schema }.check(value, schema, "")
+}
+
+struct Checker<'a> {
+    root: &'a Value,
+}
+
+impl<'a> Checker<'a> {
+    fn check(&self, value: &Value, schema: &'a Value, path: &str) -> Result<(), String> {
+        let schema = match schema {
+            Value::Bool(true) => return Ok(()),
+            Value::Bool(false) => return Err(problem(path, "no value is allowed here")),
+            Value::Object(schema) => schema,
+            _ => return Ok(()),
+        };
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            if let Some(target) = self.resolve(reference) {
+                self.check(value, target, path)?;
+            }
+        }
+        if let Some(types) = schema.get("type") {
+            let allowed: Vec<&str> = match types {
+                Value::String(name) => vec![name.as_str()],
+                Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+                _ => vec![],
+            };
+            if !allowed.is_empty() && !allowed.iter().any(|name| has_type(value, name)) {
+                return Err(problem(
+                    path,
+                    &format!(
+                        "expected {}, found {}",
+                        allowed.join(" or "),
+                        type_name(value)
+                    ),
+                ));
+            }
+        }
+        if let Some(choices) = schema.get("enum").and_then(Value::as_array) {
+            if !choices.contains(value) {
+                return Err(problem(
+                    path,
+                    &format!("{} is not one of {}", value, Value::from(choices.clone())),
+                ));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if value != expected {
+                return Err(problem(path, &format!("expected {}", expected)));
+            }
+        }
+        if let Some(subschemas) = schema.get("allOf").and_then(Value::as_array) {
+            for subschema in subschemas {
+                self.check(value, subschema, path)?;
+            }
+        }
+        // `oneOf` is treated like `anyOf`: a value matching several of the
+        // alternatives is not what models get wrong.
+        for keyword in ["anyOf", "oneOf"] {
+            if let Some(subschemas) = schema.get(keyword).and_then(Value::as_array) {
+                let results: Vec<_> = subschemas
+                    .iter()
+                    .map(|subschema| self.check(value, subschema, path))
+                    .collect();
+                if !results.iter().any(Result::is_ok) {
+                    if let Some(Err(first)) = results.into_iter().next() {
+                        return Err(first);
+                    }
+                }
+            }
+        }
+        if let Value::Object(object) = value {
+            self.check_object(object, schema, path)?;
+        }
+        if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+            for (index, item) in items.iter().enumerate() {
+                self.check(item, item_schema, &format!("{}/{}", path, index))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn check_object(
+        &self,
+        object: &Map<String, Value>,
+        schema: &'a Map<String, Value>,
+        path: &str,
+    ) -> Result<(), String> {
+        let properties = schema.get("properties").and_then(Value::as_object);
+        if let Some(required) = schema.get("required").and_then(Value::as_array) {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !object.contains_key(name) {
+                    return Err(problem(path, &format!("missing property `{}`", name)));
+                }
+            }
+        }
+        for (name, property) in object {
+            let property_path = format!("{}/{}", path, name);
+            match properties.and_then(|properties| properties.get(name)) {
+                Some(property_schema) => self.check(property, property_schema, &property_path)?,
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        return Err(problem(path, &format!("unexpected property `{}`", name)));
+                    }
+                    Some(additional) => self.check(property, additional, &property_path)?,
+                    None => {}
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up a reference like `#/definitions/Address` in the root schema.
+    fn resolve(&self, reference: &str) -> Option<&'a Value> {
+        let pointer = reference.strip_prefix('#')?;
+        self.root.pointer(pointer)
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn problem(path: &str, message: &str) -> String {
+    if path.is_empty() {
+        message.to_string()
+    } else {
+        format!("at `{}`: {}", path, message)
+    }
+}
@@ -0,0 +1,159 @@
+use futures::Stream;
+use std::pin::Pin;
+
+use crate::{Error, Usage};
+
+/// A streamed completion. Dropping it closes the underlying connection.
+pub type TextStream = Pin<Box<dyn Stream<Item = Result<TextDelta, Error>> + Send>>;
+
+/// A piece of a streamed completion.
+///
+/// Every stream ends with one delta without text that carries the
+/// `finish_reason` and `usage` the provider reported, if any, and the cost of
+/// that usage. A stream whose response ends before the provider signals the
+/// end instead ends with `Error::IncompleteStream`.
+#[derive(Debug, Clone, Default)]
+pub struct TextDelta {
+    pub text: String,
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+    /// Dollar cost of `usage`, if the model has a price for it.
+    pub cost: Option<f64>,
+    /// Like `Response::served_by`, on every delta.
+    pub served_by: Option<String>,
+}
+
+/// How a provider delimits the events of a streamed response.
+#[derive(Clone, Copy)]
+pub(crate) enum Framing {
+    /// Server-sent events; each event's `data` is one payload.
+    ServerSentEvents,
+    /// Newline-delimited JSON; each non-empty line is one payload.
+    JsonLines,
+}
+
+/// What a provider has reported about the stream so far besides text.
+#[derive(Default)]
+pub(crate) struct StreamState {
+    /// Set once the provider signalled the end of the stream. Anything after
+    /// that is ignored.
+    pub(crate) done: bool,
+    /// Set by providers without an end-of-stream event once the last payload
+    /// has arrived. Unlike `done`, the rest of the response is still read.
+    pub(crate) complete: bool,
+    pub(crate) finish_reason: Option<String>,
+    pub(crate) usage: Option<Usage>,
+}
+
+/// Turns a streamed response into text deltas. `decode` is called with each
+/// payload and returns the text it carries, recording anything else in the
+/// state.
+pub(crate) fn text_stream<F>(response: reqwest::Response, framing: Framing, decode: F) -> TextStream
+where
+    F: FnMut(&str, &mut StreamState) -> Result<Option<String>, Error> + Send + 'static,
+{
+    let reader = PayloadReader {
+        response,
+        framing,
+        buffer: vec![],
+        data: vec![],
+        eof: false,
+    };
+    let stream = futures::stream::unfold(
+        Some((reader, decode, StreamState::default())),
+        |state| async move {
+            let (mut reader, mut decode, mut state) = state?;
+            while !state.done {
+                let payload = match reader.next_payload().await {
+                    Ok(Some(payload)) => payload,
+                    Ok(None) => break,
+                    Err(e) => return Some((Err(e), None)),
+                };
+                match decode(&payload, &mut state) {
+                    Ok(Some(text)) if !text.is_empty() => {
+                        let delta = TextDelta {
+                            text,
+                            ..TextDelta::default()
+                        };
+                        return Some((Ok(delta), Some((reader, decode, state))));
+                    }
+                    Ok(_) => {}
+                    Err(e) => return Some((Err(e), None)),
+                }
+            }
+            if !state.done && !state.complete {
+                return Some((Err(Error::IncompleteStream), None));
+            }
+            let last = TextDelta {
+                text: String::new(),
+                finish_reason: state.finish_reason,
+                usage: state.usage,
+                ..TextDelta::default()
+            };
+            Some((Ok(last), None))
+        },
+    );
+    Box::pin(stream)
+}
+
+struct PayloadReader {
+    response: reqwest::Response,
+    framing: Framing,
+    buffer: Vec<u8>,
+    /// `data` lines of the server-sent event being read.
+    data: Vec<String>,
+    eof: bool,
+}
+
+impl PayloadReader {
+    /// The next payload, or `None` once the response body is exhausted.
+    async fn next_payload(&mut self) -> Result<Option<String>, Error> {
+        loop {
+            if let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim_end_matches(['\r', '\n']);
+                if let Some(payload) = self.push_line(line) {
+                    return Ok(Some(payload));
+                }
+                continue;
+            }
+            if self.eof {
+                if !self.buffer.is_empty() {
+                    self.buffer.push(b'\n');
+                    continue;
+                }
+                return Ok(self.take_event());
+            }
+            match self.response.chunk().await? {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk),
+                None => self.eof = true,
+            }
+        }
+    }
+
+    fn push_line(&mut self, line: &str) -> Option<String> {
+        match self.framing {
+            Framing::JsonLines if line.trim().is_empty() => None,
+            Framing::JsonLines => Some(line.to_string()),
+            Framing::ServerSentEvents if line.is_empty() => self.take_event(),
+            // `event`, `id` and `retry` fields and comments are not needed:
+            // every provider repeats the event type inside the JSON payload.
+            Framing::ServerSentEvents => {
+                if let Some(value) = line.strip_prefix("data:") {
+                    self.data
+                        .push(value.strip_prefix(' ').unwrap_or(value).to_string());
+                }
+                None
+            }
+        }
+    }
+
+    fn take_event(&mut self) -> Option<String> {
+        if self.data.is_empty() {
+            None
+        } else {
+            Some(self.data.drain(..).collect::<Vec<_>>().join("\n"))
+        }
+    }
+}
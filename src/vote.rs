@@ -0,0 +1,450 @@
+use std::future::Future;
+use std::time::Instant;
+
+use crate::primitives::{self, Prompt};
+use crate::{
+    batch, BatchOptionsBuilder, CallOptions, ChatModel, Error, Message, RepairAttempt, Response,
+    Usage,
+};
+
+const DEFAULT_SAMPLES: usize = 5;
+const DEFAULT_TEMPERATURE: f64 = 0.7;
+
+/// Settings for the `*_vote` methods on `Model`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VoteOptions {
+    samples: usize,
+    temperature: f64,
+    concurrency: usize,
+    call_options: CallOptions,
+}
+
+pub struct VoteOptionsBuilder {
+    samples: usize,
+    temperature: f64,
+    concurrency: Option<usize>,
+    call_options: CallOptions,
+}
+
+impl VoteOptionsBuilder {
+    pub fn new() -> Self {
+        VoteOptionsBuilder {
+            samples: DEFAULT_SAMPLES,
+            temperature: DEFAULT_TEMPERATURE,
+            concurrency: None,
+            call_options: CallOptions::default(),
+        }
+    }
+
+    /// Number of completions to vote over. Defaults to 5.
+    pub fn samples(&mut self, samples: usize) -> &mut Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    /// Sampling temperature of every completion, replacing the one in the
+    /// call options. Defaults to 0.7; at 0 the samples would mostly agree.
+    pub fn temperature(&mut self, temperature: f64) -> &mut Self {
+        self.temperature = temperature;
+        self
+    }
+
+    /// Maximum number of requests in flight when the samples are requested
+    /// one by one. Defaults to all of them at once.
+    pub fn concurrency(&mut self, concurrency: usize) -> &mut Self {
+        self.concurrency = Some(concurrency.max(1));
+        self
+    }
+
+    /// Options applied to every sample. A seed makes the samples repeat each
+    /// other, so it should not be set.
+    pub fn call_options(&mut self, call_options: CallOptions) -> &mut Self {
+        self.call_options = call_options;
+        self
+    }
+
+    pub fn build(&self) -> VoteOptions {
+        VoteOptions {
+            samples: self.samples,
+            temperature: self.temperature,
+            concurrency: self.concurrency.unwrap_or(self.samples),
+            call_options: self.call_options.clone(),
+        }
+    }
+}
+
+impl Default for VoteOptionsBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for VoteOptions {
+    fn default() -> Self {
+        VoteOptionsBuilder::new().build()
+    }
+}
+
+impl VoteOptions {
+    pub(crate) fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// The options of one sample. The cache is bypassed, since a cached answer
+    /// would count as every vote.
+    pub(crate) fn sample_options(&self) -> CallOptions {
+        self.call_options.overridden_by(&CallOptions {
+            temperature: Some(self.temperature),
+            bypass_cache: Some(true),
+            ..CallOptions::default()
+        })
+    }
+}
+
+/// The outcome of `classify_vote`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassifyVote {
+    /// Index of the choice most samples picked. A tie goes to the choice
+    /// listed first.
+    pub choice: usize,
+    /// Number of samples that picked each choice, indexed like the choices.
+    pub votes: Vec<usize>,
+    /// Share of the valid samples that picked `choice`, from 0 to 1.
+    pub confidence: f64,
+    /// Samples that failed or gave an invalid answer; they have no vote.
+    pub failed: usize,
+}
+
+impl ClassifyVote {
+    /// Share of the valid samples that picked each choice.
+    pub fn distribution(&self) -> Vec<f64> {
+        let total = self.votes.iter().sum::<usize>().max(1) as f64;
+        self.votes
+            .iter()
+            .map(|votes| *votes as f64 / total)
+            .collect()
+    }
+}
+
+/// The outcome of `score_float_vote` and `score_int_vote`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoreVote<T> {
+    /// The valid scores, in the order they were sampled.
+    pub scores: Vec<T>,
+    pub mean: f64,
+    pub median: f64,
+    /// Population standard deviation of `scores`.
+    pub stddev: f64,
+    /// Samples that failed or gave an invalid score.
+    pub failed: usize,
+}
+
+/// The decoded completions of a vote and what they cost together.
+pub(crate) struct Samples<T> {
+    pub(crate) values: Vec<Result<T, Error>>,
+    pub(crate) repairs: Vec<RepairAttempt>,
+    pub(crate) usage: Option<Usage>,
+    pub(crate) cost: Option<f64>,
+    pub(crate) model: Option<String>,
+    pub(crate) served_by: Option<String>,
+    pub(crate) request_id: Option<String>,
+}
+
+impl<T> Samples<T> {
+    fn from_responses(results: Vec<Result<Response<T>, Error>>) -> Self {
+        let mut samples = Samples {
+            values: vec![],
+            repairs: vec![],
+            usage: None,
+            cost: None,
+            model: None,
+            served_by: None,
+            request_id: None,
+        };
+        for result in results {
+            match result {
+                Ok(response) => {
+                    samples.repairs.extend(response.repairs);
+                    samples.add_usage(response.usage, response.cost);
+                    samples.model.get_or_insert(response.model);
+                    samples.served_by = samples.served_by.or(response.served_by);
+                    samples.request_id = response.request_id.or(samples.request_id);
+                    samples.values.push(Ok(response.value));
+                }
+                Err(e) => samples.values.push(Err(e)),
+            }
+        }
+        samples
+    }
+
+    pub(crate) fn add_usage(&mut self, usage: Option<Usage>, cost: Option<f64>) {
+        if let Some(usage) = usage {
+            *self.usage.get_or_insert_with(Usage::default) += usage;
+        }
+        if let Some(cost) = cost {
+            *self.cost.get_or_insert(0.0) += cost;
+        }
+    }
+
+    fn extend(&mut self, other: Samples<T>) {
+        self.values.extend(other.values);
+        self.repairs.extend(other.repairs);
+        self.add_usage(other.usage, other.cost);
+        self.model = self.model.take().or(other.model);
+        self.served_by = self.served_by.take().or(other.served_by);
+        self.request_id = other.request_id.or(self.request_id.take());
+    }
+
+    /// Splits off the valid values, failing with the first error if there are
+    /// none.
+    fn into_response<V>(
+        self,
+        started: Instant,
+        summarize: impl FnOnce(Vec<T>, usize) -> V,
+    ) -> Result<Response<V>, Error> {
+        let mut valid = vec![];
+        let mut first_error = None;
+        let mut failed = 0;
+        for value in self.values {
+            match value {
+                Ok(value) => valid.push(value),
+                Err(e) => {
+                    failed += 1;
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if valid.is_empty() {
+            return Err(first_error.expect("a vote takes at least one sample"));
+        }
+        Ok(Response {
+            value: summarize(valid, failed),
+            repairs: self.repairs,
+            usage: self.usage,
+            cost: self.cost,
+            model: self.model.unwrap_or_default(),
+            latency: started.elapsed(),
+            request_id: self.request_id,
+            cached: false,
+            served_by: self.served_by,
+        })
+    }
+}
+
+/// Makes `samples` calls with at most `options.concurrency` in flight.
+pub(crate) async fn sample<T, F, Fut>(samples: usize, options: &VoteOptions, call: F) -> Samples<T>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Response<T>, Error>>,
+{
+    let batch_options = BatchOptionsBuilder::new()
+        .concurrency(options.concurrency)
+        .build();
+    let results = batch::run(vec![(); samples], &batch_options, |_| call()).await;
+    Samples::from_responses(results)
+}
+
+/// Asks `model` for every sample of `prompt` in one request, then makes
+/// separate calls with `call` for any the backend did not return.
+async fn sample_chat<M, T, D, F, Fut>(
+    model: &M,
+    prompt: Prompt<D>,
+    call_options: &CallOptions,
+    options: &VoteOptions,
+    call: F,
+) -> Result<Samples<T>, Error>
+where
+    M: ChatModel,
+    D: Fn(Message) -> Result<T, Error>,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<Response<T>, Error>>,
+{
+    let mut samples = primitives::sample_n(model, prompt, call_options, options.samples).await?;
+    let missing = options.samples.saturating_sub(samples.values.len());
+    if missing > 0 {
+        samples.extend(sample(missing, options, call).await);
+    }
+    Ok(samples)
+}
+
+/// Counts the votes of `samples`. A sample that picked a choice past the end
+/// of the list counts as failed.
+pub(crate) fn tally_choices(
+    mut samples: Samples<usize>,
+    choice_count: usize,
+    started: Instant,
+) -> Result<Response<ClassifyVote>, Error> {
+    for value in &mut samples.values {
+        if let Ok(choice) = value {
+            if *choice >= choice_count {
+                *value = Err(Error::InvalidChoice {
+                    raw: choice.to_string(),
+                });
+            }
+        }
+    }
+    samples.into_response(started, |choices, failed| {
+        let mut votes = vec![0; choice_count];
+        for choice in &choices {
+            votes[*choice] += 1;
+        }
+        // `max_by_key` keeps the last maximum, so search from the back to let
+        // ties go to the first choice.
+        let choice = votes
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|(_, votes)| **votes)
+            .map_or(0, |(choice, _)| choice);
+        ClassifyVote {
+            choice,
+            confidence: votes[choice] as f64 / choices.len() as f64,
+            votes,
+            failed,
+        }
+    })
+}
+
+pub(crate) fn summarize_scores<T>(
+    samples: Samples<T>,
+    started: Instant,
+) -> Result<Response<ScoreVote<T>>, Error>
+where
+    T: Copy + Into<Score>,
+{
+    samples.into_response(started, |scores, failed| {
+        let mut sorted: Vec<f64> = scores.iter().map(|score| (*score).into().0).collect();
+        sorted.sort_by(f64::total_cmp);
+        let count = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / count;
+        let middle = sorted.len() / 2;
+        let median = if sorted.len() % 2 == 0 {
+            (sorted[middle - 1] + sorted[middle]) / 2.0
+        } else {
+            sorted[middle]
+        };
+        let variance = sorted
+            .iter()
+            .map(|score| (score - mean).powi(2))
+            .sum::<f64>()
+            / count;
+        ScoreVote {
+            scores,
+            mean,
+            median,
+            stddev: variance.sqrt(),
+            failed,
+        }
+    })
+}
+
+/// A score as `f64`, for the statistics of a `ScoreVote`.
+pub(crate) struct Score(f64);
+
+impl From<f64> for Score {
+    fn from(score: f64) -> Self {
+        Score(score)
+    }
+}
+
+impl From<i64> for Score {
+    fn from(score: i64) -> Self {
+        Score(score as f64)
+    }
+}
+
+/// `Model::classify_vote` for backends that can return several completions
+/// per request.
+pub(crate) async fn classify<M: ChatModel>(
+    model: &M,
+    instruction: String,
+    text: String,
+    choices: Vec<String>,
+    options: &VoteOptions,
+) -> Result<Response<ClassifyVote>, Error> {
+    let started = Instant::now();
+    let sample_options = options.sample_options();
+    let call_options = model.default_options().overridden_by(&sample_options);
+    let prompt = primitives::classify_prompt(
+        instruction.clone(),
+        text.clone(),
+        choices.clone(),
+        &call_options,
+    );
+    let samples = sample_chat(model, prompt, &call_options, options, || {
+        model.classify_with_options(
+            instruction.clone(),
+            text.clone(),
+            choices.clone(),
+            &sample_options,
+        )
+    })
+    .await?;
+    tally_choices(samples, choices.len(), started)
+}
+
+/// `Model::score_float_vote` for backends that can return several completions
+/// per request.
+pub(crate) async fn score_float<M: ChatModel>(
+    model: &M,
+    instruction: String,
+    text: String,
+    min_bound: f64,
+    max_bound: f64,
+    options: &VoteOptions,
+) -> Result<Response<ScoreVote<f64>>, Error> {
+    let started = Instant::now();
+    let sample_options = options.sample_options();
+    let call_options = model.default_options().overridden_by(&sample_options);
+    let prompt = primitives::score_float_prompt(
+        instruction.clone(),
+        text.clone(),
+        min_bound,
+        max_bound,
+        &call_options,
+    );
+    let samples = sample_chat(model, prompt, &call_options, options, || {
+        model.score_float_with_options(
+            instruction.clone(),
+            text.clone(),
+            min_bound,
+            max_bound,
+            &sample_options,
+        )
+    })
+    .await?;
+    summarize_scores(samples, started)
+}
+
+/// `Model::score_int_vote` for backends that can return several completions
+/// per request.
+pub(crate) async fn score_int<M: ChatModel>(
+    model: &M,
+    instruction: String,
+    text: String,
+    min_bound: i64,
+    max_bound: i64,
+    options: &VoteOptions,
+) -> Result<Response<ScoreVote<i64>>, Error> {
+    let started = Instant::now();
+    let sample_options = options.sample_options();
+    let call_options = model.default_options().overridden_by(&sample_options);
+    let prompt = primitives::score_int_prompt(
+        instruction.clone(),
+        text.clone(),
+        min_bound,
+        max_bound,
+        &call_options,
+    );
+    let samples = sample_chat(model, prompt, &call_options, options, || {
+        model.score_int_with_options(
+            instruction.clone(),
+            text.clone(),
+            min_bound,
+            max_bound,
+            &sample_options,
+        )
+    })
+    .await?;
+    summarize_scores(samples, started)
+}
@@ -0,0 +1,181 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use futures::StreamExt;
+use llm_primitives::{AnthropicModel, AnthropicModelBuilder, CallOptions, Error, Model};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::json;
+
+fn model(server: &StubServer) -> AnthropicModel {
+    AnthropicModelBuilder::new("claude-test".to_string())
+        .api_key("test-key".to_string())
+        .base_url(format!("{}/v1", server.url()))
+        .build()
+}
+
+fn text_reply(text: &str) -> StubResponse {
+    StubResponse::json(json!({
+        "content": [{"type": "text", "text": text}],
+        "model": "claude-test-20240101",
+        "usage": {"input_tokens": 12, "output_tokens": 4},
+    }))
+}
+
+#[tokio::test]
+async fn sends_auth_headers_and_messages_body() {
+    let server = StubServer::start().await;
+    server.push(text_reply("Hello there"));
+    let response = model(&server)
+        .generate_text_with_options(
+            "Be brief.".to_string(),
+            "Hi".to_string(),
+            &CallOptions::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.value, "Hello there");
+    assert_eq!(response.model, "claude-test-20240101");
+    let usage = response.usage.unwrap();
+    assert_eq!((usage.input_tokens, usage.output_tokens), (12, 4));
+
+    let request = server.last_request();
+    assert_eq!(request.method, "POST");
+    assert_eq!(request.path, "/v1/messages");
+    assert_eq!(request.header("x-api-key"), Some("test-key"));
+    assert_eq!(request.header("anthropic-version"), Some("2023-06-01"));
+    let body = request.json();
+    assert_eq!(body["model"], "claude-test");
+    assert_eq!(body["max_tokens"], 4096);
+    assert_eq!(body["system"], "Be brief.");
+    assert_eq!(body["messages"], json!([{"role": "user", "content": "Hi"}]));
+    assert!(body.get("stream").is_none());
+}
+
+#[tokio::test]
+async fn classify_prefills_json_and_decodes_the_label() {
+    let server = StubServer::start().await;
+    server.push(text_reply("\"classification\": \"B\"}"));
+    let response = model(&server)
+        .classify(
+            "Pick the fruit.".to_string(),
+            "banana".to_string(),
+            vec!["car".to_string(), "fruit".to_string()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(response, 1);
+
+    let body = server.last_request().json();
+    let messages = body["messages"].as_array().unwrap();
+    assert_eq!(
+        messages.last().unwrap(),
+        &json!({"role": "assistant", "content": "{"})
+    );
+    assert_eq!(body["temperature"], 0.0);
+}
+
+#[tokio::test]
+async fn decodes_scores_and_parsed_structs() {
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    struct Person {
+        name: String,
+        age: u32,
+    }
+
+    let server = StubServer::start().await;
+    server.push(text_reply("\"score\": 7}"));
+    server.push(text_reply("\"name\": \"Ada\", \"age\": 36}"));
+    let model = model(&server);
+    let score = model
+        .score_int("Rate it.".to_string(), "ok".to_string(), 0, 10)
+        .await
+        .unwrap();
+    assert_eq!(score, 7);
+    let person: Person = model.parse("Ada is 36.".to_string()).await.unwrap();
+    assert_eq!(
+        person,
+        Person {
+            name: "Ada".to_string(),
+            age: 36,
+        }
+    );
+}
+
+#[tokio::test]
+async fn maps_error_statuses() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::status(
+        401,
+        json!({"type": "error", "error": {"type": "authentication_error"}}),
+    ));
+    server.push(StubResponse::status(
+        429,
+        json!({"type": "error", "error": {"type": "rate_limit_error"}}),
+    ));
+    let model = model(&server);
+    let error = model
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::Auth { .. }), "{:?}", error);
+    let error = model
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::RateLimited { .. }), "{:?}", error);
+}
+
+#[tokio::test]
+async fn refusals_are_content_filtered() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::json(json!({
+        "content": [],
+        "stop_reason": "refusal",
+        "model": "claude-test-20240101",
+        "usage": {"input_tokens": 12, "output_tokens": 0},
+    })));
+    let event = |data: &str| format!("event: x\ndata: {}\n\n", data);
+    server.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        [
+            event(r#"{"type":"message_start","message":{"usage":{"input_tokens":3}}}"#),
+            event(
+                r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"I"}}"#,
+            ),
+            event(r#"{"type":"message_delta","delta":{"stop_reason":"refusal"}}"#),
+            event(r#"{"type":"message_stop"}"#),
+        ]
+        .concat(),
+    ));
+    let model = model(&server);
+
+    let error = model
+        .classify(
+            "Pick one.".to_string(),
+            "t".to_string(),
+            vec!["a".to_string(), "b".to_string()],
+        )
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, Error::ContentFiltered { reason } if reason == "refusal"),
+        "{:?}",
+        error
+    );
+
+    let deltas: Vec<_> = model
+        .generate_text_stream("i".to_string(), "t".to_string())
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert_eq!(deltas[0].as_ref().unwrap().text, "I");
+    let error = deltas.last().unwrap().as_ref().unwrap_err();
+    assert!(
+        matches!(error, Error::ContentFiltered { reason } if reason == "refusal"),
+        "{:?}",
+        error
+    );
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::{chat_reply, chat_stream, StubServer};
+use futures::StreamExt;
+use llm_primitives::{AzureOpenAIModel, AzureOpenAIModelBuilder, CredentialProvider, Model};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn builder(server: &StubServer) -> AzureOpenAIModelBuilder {
+    let mut builder = AzureOpenAIModelBuilder::new("resource".to_string(), "gpt-4o".to_string());
+    builder.endpoint(server.url().to_string());
+    builder
+}
+
+async fn ask(model: &AzureOpenAIModel) {
+    let text = model
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "ok");
+}
+
+#[tokio::test]
+async fn sends_the_api_key_to_the_deployment() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("ok"));
+    ask(&builder(&server).api_key("azure-key".to_string()).build()).await;
+
+    let request = server.last_request();
+    assert_eq!(
+        request.path,
+        "/openai/deployments/gpt-4o/chat/completions?api-version=2024-06-01"
+    );
+    assert_eq!(request.header("api-key"), Some("azure-key"));
+    assert_eq!(request.header("authorization"), None);
+}
+
+#[tokio::test]
+async fn sends_entra_tokens_from_a_credential_provider() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("ok"));
+    server.push(chat_reply("ok"));
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let counter = fetches.clone();
+    let model = builder(&server)
+        .api_version("2024-10-21".to_string())
+        .token_provider(CredentialProvider::rotating(
+            Duration::from_secs(60),
+            move || {
+                let fetch = counter.fetch_add(1, Ordering::SeqCst);
+                async move { Ok(format!("token-{}", fetch)) }
+            },
+        ))
+        .build();
+    ask(&model).await;
+    ask(&model).await;
+
+    for request in server.requests() {
+        assert_eq!(
+            request.path,
+            "/openai/deployments/gpt-4o/chat/completions?api-version=2024-10-21"
+        );
+        assert_eq!(request.header("authorization"), Some("Bearer token-0"));
+        assert_eq!(request.header("api-key"), None);
+    }
+    assert_eq!(fetches.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn streams_ask_for_usage_from_api_versions_that_accept_it() {
+    let server = StubServer::start().await;
+    for api_version in ["2024-06-01", "2024-10-21"] {
+        server.push(chat_stream("Hi"));
+        let deltas: Vec<_> = builder(&server)
+            .api_key("azure-key".to_string())
+            .api_version(api_version.to_string())
+            .build()
+            .generate_text_stream("i".to_string(), "t".to_string())
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(deltas.iter().all(Result::is_ok), "{:?}", deltas);
+        let stream_options = server.last_request().json()["stream_options"].clone();
+        if api_version == "2024-06-01" {
+            assert!(stream_options.is_null());
+        } else {
+            assert_eq!(stream_options["include_usage"], true);
+        }
+    }
+
+    server.push(chat_stream("Hi"));
+    builder(&server)
+        .api_key("azure-key".to_string())
+        .stream_usage(true)
+        .build()
+        .generate_text_stream("i".to_string(), "t".to_string())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    assert_eq!(
+        server.last_request().json()["stream_options"]["include_usage"],
+        true
+    );
+}
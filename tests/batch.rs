@@ -0,0 +1,204 @@
+use llm_primitives::{
+    BatchOptions, BatchOptionsBuilder, BatchProgress, CallOptions, CancelToken, Error, Model,
+    Response, TextStream,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A model whose `score_int` sleeps for the number of milliseconds in the
+/// text and answers with it, failing on a text that is not a number. It keeps
+/// track of how many calls are in flight.
+#[derive(Default)]
+struct Sleepy {
+    in_flight: AtomicUsize,
+    max_in_flight: AtomicUsize,
+    finished: AtomicUsize,
+}
+
+impl Sleepy {
+    fn texts(delays: &[&str]) -> Vec<String> {
+        delays.iter().map(|delay| delay.to_string()).collect()
+    }
+}
+
+impl Model for Sleepy {
+    async fn classify_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        _choices: Vec<String>,
+        _options: &CallOptions,
+    ) -> Result<Response<usize>, Error> {
+        unimplemented!()
+    }
+
+    async fn binary_classify_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        _options: &CallOptions,
+    ) -> Result<Response<bool>, Error> {
+        unimplemented!()
+    }
+
+    async fn generate_text_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        _options: &CallOptions,
+    ) -> Result<Response<String>, Error> {
+        unimplemented!()
+    }
+
+    async fn generate_text_stream_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        _options: &CallOptions,
+    ) -> Result<TextStream, Error> {
+        unimplemented!()
+    }
+
+    async fn score_float_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        _min_bound: f64,
+        _max_bound: f64,
+        _options: &CallOptions,
+    ) -> Result<Response<f64>, Error> {
+        unimplemented!()
+    }
+
+    async fn score_int_with_options(
+        &self,
+        _instruction: String,
+        text: String,
+        _min_bound: i64,
+        _max_bound: i64,
+        _options: &CallOptions,
+    ) -> Result<Response<i64>, Error> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+        let delay = text.parse::<u64>();
+        tokio::time::sleep(Duration::from_millis(*delay.as_ref().unwrap_or(&10))).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.finished.fetch_add(1, Ordering::SeqCst);
+        let value = delay.map_err(|_| Error::InvalidChoice { raw: text.clone() })?;
+        Ok(Response {
+            value: value as i64,
+            repairs: vec![],
+            usage: None,
+            cost: None,
+            model: "sleepy".to_string(),
+            latency: Duration::from_millis(value),
+            request_id: None,
+            cached: false,
+            served_by: None,
+        })
+    }
+
+    async fn parse_with_options<T>(
+        &self,
+        _text: String,
+        _options: &CallOptions,
+    ) -> Result<Response<T>, Error>
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        unimplemented!()
+    }
+}
+
+async fn score_all(
+    model: &Sleepy,
+    texts: Vec<String>,
+    options: &BatchOptions,
+) -> Vec<Result<i64, Error>> {
+    model
+        .score_int_many("i".to_string(), texts, 0, 1000, options)
+        .await
+        .into_iter()
+        .map(|result| result.map(|response| response.value))
+        .collect()
+}
+
+#[tokio::test]
+async fn results_are_in_input_order_and_failures_stay_with_their_item() {
+    let model = Sleepy::default();
+    let options = BatchOptionsBuilder::new().build();
+    let results = score_all(
+        &model,
+        Sleepy::texts(&["60", "x", "5", "30", "y"]),
+        &options,
+    )
+    .await;
+
+    assert_eq!(results.len(), 5);
+    assert_eq!(results[0].as_ref().unwrap(), &60);
+    assert!(matches!(&results[1], Err(Error::InvalidChoice { raw }) if raw == "x"));
+    assert_eq!(results[2].as_ref().unwrap(), &5);
+    assert_eq!(results[3].as_ref().unwrap(), &30);
+    assert!(matches!(&results[4], Err(Error::InvalidChoice { raw }) if raw == "y"));
+}
+
+#[tokio::test]
+async fn no_more_than_the_concurrency_are_in_flight() {
+    let model = Sleepy::default();
+    let options = BatchOptionsBuilder::new().concurrency(3).build();
+    let texts = Sleepy::texts(&["20"; 10]);
+    let results = score_all(&model, texts, &options).await;
+
+    assert!(results.iter().all(Result::is_ok));
+    assert_eq!(model.max_in_flight.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn progress_is_reported_after_every_item() {
+    let model = Sleepy::default();
+    let reports: Arc<Mutex<Vec<BatchProgress>>> = Arc::default();
+    let sink = reports.clone();
+    let options = BatchOptionsBuilder::new()
+        .concurrency(1)
+        .on_progress(move |progress| sink.lock().unwrap().push(*progress))
+        .build();
+    score_all(&model, Sleepy::texts(&["1", "x", "1"]), &options).await;
+
+    let reports = reports.lock().unwrap();
+    let summary: Vec<_> = reports
+        .iter()
+        .map(|progress| {
+            (
+                progress.index,
+                progress.completed,
+                progress.failed,
+                progress.total,
+            )
+        })
+        .collect();
+    assert_eq!(summary, vec![(0, 1, 0, 3), (1, 2, 1, 3), (2, 3, 1, 3)]);
+}
+
+#[tokio::test]
+async fn cancelling_fails_the_items_that_have_not_finished() {
+    let model = Sleepy::default();
+    let cancel = CancelToken::new();
+    let on_first = cancel.clone();
+    let options = BatchOptionsBuilder::new()
+        .concurrency(2)
+        .cancel_token(cancel.clone())
+        .on_progress(move |_| on_first.cancel())
+        .build();
+    let results = score_all(&model, Sleepy::texts(&["5", "500", "5", "5"]), &options).await;
+
+    assert!(cancel.is_cancelled());
+    assert_eq!(results[0].as_ref().unwrap(), &5);
+    for result in &results[1..] {
+        assert!(matches!(result, Err(Error::Cancelled)), "{:?}", result);
+    }
+    // The slow call was dropped and the last ones never started.
+    assert_eq!(model.finished.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,131 @@
+mod common;
+
+use common::{chat_reply_with_usage, openai_builder, StubServer};
+use futures::future::join_all;
+use llm_primitives::{
+    Budget, BudgetBuilder, BudgetLimit, Error, Model, OpenAIModel, Price, PriceTable, Response,
+};
+use std::time::Duration;
+
+fn model(server: &StubServer, budget: &Budget) -> OpenAIModel {
+    let mut prices = PriceTable::new();
+    prices.price("gpt-test".to_string(), Price::new(1.0, 1.0));
+    openai_builder(server)
+        .price_table(prices)
+        .budget(budget.clone())
+        .build()
+}
+
+async fn ask(model: &OpenAIModel) -> Result<Response<String>, Error> {
+    model
+        .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+        .await
+}
+
+fn refused_for(result: Result<Response<String>, Error>) -> BudgetLimit {
+    match result {
+        Err(Error::BudgetExceeded { limit, .. }) => limit,
+        other => panic!("expected BudgetExceeded, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn refuses_requests_once_the_request_limit_is_reached() {
+    let server = StubServer::start().await;
+    server.push(chat_reply_with_usage(1, 1));
+    server.push(chat_reply_with_usage(1, 1));
+    let budget = BudgetBuilder::new().max_requests(2).build();
+    let model = model(&server, &budget);
+
+    ask(&model).await.unwrap();
+    assert_eq!(budget.remaining().requests, Some(1));
+    ask(&model).await.unwrap();
+    assert_eq!(refused_for(ask(&model).await), BudgetLimit::Requests);
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(budget.remaining().requests, Some(0));
+    assert_eq!(budget.remaining().tokens, None);
+}
+
+#[tokio::test]
+async fn refuses_requests_once_reported_tokens_reach_the_limit() {
+    let server = StubServer::start().await;
+    server.push(chat_reply_with_usage(4, 2));
+    server.push(chat_reply_with_usage(4, 2));
+    let budget = BudgetBuilder::new().max_tokens(10).build();
+    let model = model(&server, &budget);
+
+    ask(&model).await.unwrap();
+    assert_eq!(budget.remaining().tokens, Some(4));
+    // The request that crosses the limit still completes.
+    ask(&model).await.unwrap();
+    assert_eq!(budget.remaining().tokens, Some(0));
+    assert_eq!(refused_for(ask(&model).await), BudgetLimit::Tokens);
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn refuses_requests_once_the_cost_limit_is_reached() {
+    let server = StubServer::start().await;
+    server.push(chat_reply_with_usage(600_000, 600_000));
+    let budget = BudgetBuilder::new().max_cost(1.0).build();
+    let model = model(&server, &budget);
+
+    assert_eq!(ask(&model).await.unwrap().cost, Some(1.2));
+    assert_eq!(budget.remaining().cost, Some(0.0));
+    assert_eq!(refused_for(ask(&model).await), BudgetLimit::Cost);
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn one_budget_is_shared_by_the_models_given_clones_of_it() {
+    let server = StubServer::start().await;
+    server.push(chat_reply_with_usage(1, 1));
+    server.push(chat_reply_with_usage(1, 1));
+    let budget = BudgetBuilder::new().max_requests(2).build();
+    let first = model(&server, &budget);
+    let second = model(&server, &budget);
+
+    ask(&first).await.unwrap();
+    ask(&second).await.unwrap();
+    assert_eq!(refused_for(ask(&first).await), BudgetLimit::Requests);
+    assert_eq!(refused_for(ask(&second).await), BudgetLimit::Requests);
+    assert_eq!(budget.remaining().requests, Some(0));
+}
+
+#[tokio::test]
+async fn the_budget_is_refilled_when_its_window_ends() {
+    let server = StubServer::start().await;
+    server.push(chat_reply_with_usage(1, 1));
+    server.push(chat_reply_with_usage(1, 1));
+    let budget = BudgetBuilder::new()
+        .max_requests(1)
+        .window(Duration::from_millis(200))
+        .build();
+    let model = model(&server, &budget);
+
+    ask(&model).await.unwrap();
+    let Err(Error::BudgetExceeded { resets_in, .. }) = ask(&model).await else {
+        panic!("expected BudgetExceeded");
+    };
+    let resets_in = resets_in.unwrap();
+    assert!(resets_in <= Duration::from_millis(200), "{:?}", resets_in);
+    tokio::time::sleep(resets_in).await;
+    ask(&model).await.unwrap();
+}
+
+#[tokio::test]
+async fn concurrent_requests_can_overshoot_a_token_limit() {
+    let server = StubServer::start().await;
+    for _ in 0..3 {
+        server.push(chat_reply_with_usage(5, 3));
+    }
+    let budget = BudgetBuilder::new().max_tokens(10).build();
+    let model = model(&server, &budget);
+
+    // All three are admitted before any of them reports its usage.
+    let results = join_all((0..3).map(|_| ask(&model))).await;
+    assert!(results.iter().all(Result::is_ok), "{:?}", results);
+    assert_eq!(budget.remaining().tokens, Some(0));
+    assert_eq!(refused_for(ask(&model).await), BudgetLimit::Tokens);
+    assert_eq!(server.requests().len(), 3);
+}
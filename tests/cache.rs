@@ -0,0 +1,73 @@
+mod common;
+
+use common::{chat_reply, openai_builder, StubServer};
+use llm_primitives::{
+    Cache, CacheBuilder, CacheStats, Error, MemoryCacheStore, Model, OpenAIModel,
+};
+use std::time::Duration;
+
+fn model(server: &StubServer, cache: &Cache) -> OpenAIModel {
+    openai_builder(server).cache(cache.clone()).build()
+}
+
+#[tokio::test]
+async fn entries_expire_after_a_sub_second_ttl() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("first"));
+    server.push(chat_reply("second"));
+    let cache = CacheBuilder::new(MemoryCacheStore::new(10))
+        .ttl(Duration::from_millis(300))
+        .build();
+    let model = model(&server, &cache);
+    let ask = || model.generate_text("i".to_string(), "t".to_string());
+
+    assert_eq!(ask().await.unwrap(), "first");
+    assert_eq!(ask().await.unwrap(), "first");
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(ask().await.unwrap(), "second");
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2 });
+}
+
+#[tokio::test]
+async fn servers_with_the_same_model_name_do_not_share_entries() {
+    let first = StubServer::start().await;
+    let second = StubServer::start().await;
+    first.push(chat_reply("from first"));
+    second.push(chat_reply("from second"));
+    let cache = Cache::new(MemoryCacheStore::new(10));
+
+    let text = model(&first, &cache)
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "from first");
+    let text = model(&second, &cache)
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "from second");
+}
+
+#[tokio::test]
+async fn answers_that_fail_to_decode_are_not_cached() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("{\"classification\": \"Z\"}"));
+    server.push(chat_reply("{\"classification\": \"B\"}"));
+    let cache = Cache::new(MemoryCacheStore::new(10));
+    let model = model(&server, &cache);
+    let classify = || {
+        model.classify(
+            "Pick the fruit.".to_string(),
+            "banana".to_string(),
+            vec!["car".to_string(), "fruit".to_string()],
+        )
+    };
+
+    let error = classify().await.unwrap_err();
+    assert!(matches!(error, Error::InvalidChoice { .. }), "{:?}", error);
+    assert_eq!(classify().await.unwrap(), 1);
+    assert_eq!(classify().await.unwrap(), 1);
+    assert_eq!(server.requests().len(), 2);
+    assert_eq!(cache.stats(), CacheStats { hits: 1, misses: 2 });
+}
@@ -0,0 +1,114 @@
+mod common;
+
+use common::{chat_reply, StubServer};
+use futures::future::join_all;
+use llm_primitives::{
+    AzureOpenAIModelBuilder, Cassette, CredentialProvider, Error, Model, OpenAIModel,
+    OpenAIModelBuilder,
+};
+use std::path::PathBuf;
+
+fn model(base_url: &str, cassette: Cassette) -> OpenAIModel {
+    OpenAIModelBuilder::new("gpt-test".to_string())
+        .api_key("test-key".to_string())
+        .base_url(base_url.to_string())
+        .cassette(cassette)
+        .build()
+}
+
+/// A cassette file in a directory of its own for this test run.
+fn cassette_path(name: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join(format!("llm-primitives-cassette-{}", std::process::id()))
+        .join(name)
+}
+
+/// An environment variable no test sets.
+fn unset_key() -> CredentialProvider {
+    CredentialProvider::env("LLM_PRIMITIVES_TEST_UNSET_KEY".to_string())
+}
+
+#[tokio::test]
+async fn concurrent_recordings_are_all_saved_and_replayed() {
+    let path = cassette_path("concurrent.json");
+    let server = StubServer::start().await;
+    for _ in 0..5 {
+        server.push(chat_reply("ok"));
+    }
+    let texts: Vec<String> = (0..5).map(|i| format!("text {}", i)).collect();
+
+    let recording = model(server.url(), Cassette::record(&path));
+    let answers = join_all(
+        texts
+            .iter()
+            .map(|text| recording.generate_text("i".to_string(), text.clone())),
+    )
+    .await;
+    assert!(answers.iter().all(|answer| answer.is_ok()));
+
+    let replaying = model(server.url(), Cassette::replay(&path).unwrap());
+    for text in texts.iter().rev() {
+        let answer = replaying
+            .generate_text("i".to_string(), text.clone())
+            .await
+            .unwrap();
+        assert_eq!(answer, "ok");
+    }
+    assert_eq!(server.requests().len(), 5);
+    let _ = std::fs::remove_file(path);
+}
+
+#[tokio::test]
+async fn replaying_needs_no_api_key() {
+    let openai_path = cassette_path("no-key-openai.json");
+    let azure_path = cassette_path("no-key-azure.json");
+    let server = StubServer::start().await;
+    server.push(chat_reply("ok"));
+    server.push(chat_reply("ok"));
+    let azure = |cassette: Cassette| {
+        AzureOpenAIModelBuilder::new("resource".to_string(), "gpt-4o".to_string())
+            .endpoint(server.url().to_string())
+            .credentials(unset_key())
+            .cassette(cassette)
+            .try_build()
+    };
+    model(server.url(), Cassette::record(&openai_path))
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    let mut recording = AzureOpenAIModelBuilder::new("resource".to_string(), "gpt-4o".to_string());
+    recording
+        .endpoint(server.url().to_string())
+        .api_key("azure-key".to_string())
+        .cassette(Cassette::record(&azure_path));
+    recording
+        .build()
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+
+    let openai = OpenAIModelBuilder::new("gpt-test".to_string())
+        .credentials(unset_key())
+        .base_url(server.url().to_string())
+        .cassette(Cassette::replay(&openai_path).unwrap())
+        .try_build()
+        .unwrap();
+    let text = openai
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "ok");
+    let text = azure(Cassette::replay(&azure_path).unwrap())
+        .unwrap()
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "ok");
+    assert_eq!(server.requests().len(), 2);
+
+    // Recording still needs the key.
+    let error = azure(Cassette::record(cassette_path("unused.json"))).unwrap_err();
+    assert!(matches!(error, Error::Credentials(_)), "{:?}", error);
+    let _ = std::fs::remove_file(openai_path);
+    let _ = std::fs::remove_file(azure_path);
+}
@@ -0,0 +1,271 @@
+//! A minimal HTTP/1.1 server that answers with queued responses and records
+//! what it was sent, so backends can be tested without network access.
+
+#![allow(dead_code)]
+
+use llm_primitives::{OpenAIModel, OpenAIModelBuilder};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A builder for an `OpenAIModel` named `gpt-test` that talks to `server`.
+pub fn openai_builder(server: &StubServer) -> OpenAIModelBuilder {
+    let mut builder = OpenAIModelBuilder::new("gpt-test".to_string());
+    builder
+        .api_key("test-key".to_string())
+        .base_url(server.url().to_string());
+    builder
+}
+
+pub fn openai_model(server: &StubServer) -> OpenAIModel {
+    openai_builder(server).build()
+}
+
+/// A chat completion whose only choice answers with `content`.
+pub fn chat_reply(content: &str) -> StubResponse {
+    StubResponse::json(json!({
+        "choices": [{"message": {"role": "assistant", "content": content}}],
+    }))
+}
+
+/// A chat completion of "ok" that used `prompt_tokens` and
+/// `completion_tokens`.
+pub fn chat_reply_with_usage(prompt_tokens: u64, completion_tokens: u64) -> StubResponse {
+    StubResponse::json(json!({
+        "choices": [{"message": {"role": "assistant", "content": "ok"}}],
+        "usage": {"prompt_tokens": prompt_tokens, "completion_tokens": completion_tokens},
+    }))
+}
+
+/// A chat completion stream of `content` that ends with a usage chunk of 5
+/// input and 2 output tokens.
+pub fn chat_stream(content: &str) -> StubResponse {
+    let chunks = [
+        json!({"choices": [{"delta": {"content": content}, "finish_reason": "stop"}]}),
+        json!({"choices": [], "usage": {"prompt_tokens": 5, "completion_tokens": 2}}),
+    ];
+    let mut body = String::new();
+    for chunk in chunks {
+        body.push_str(&format!("data: {}\n\n", chunk));
+    }
+    body.push_str("data: [DONE]\n\n");
+    StubResponse::raw(200, "text/event-stream", body)
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    /// Path and query string.
+    pub path: String,
+    /// Header names are lowercase.
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).expect("request body is not JSON")
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StubResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    chunks: Vec<Vec<u8>>,
+    chunk_delay: Duration,
+}
+
+impl StubResponse {
+    pub fn json(body: Value) -> Self {
+        StubResponse::raw(200, "application/json", body.to_string())
+    }
+
+    pub fn status(status: u16, body: Value) -> Self {
+        StubResponse::raw(status, "application/json", body.to_string())
+    }
+
+    pub fn raw(status: u16, content_type: &str, body: impl Into<Vec<u8>>) -> Self {
+        StubResponse {
+            status,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            chunks: vec![body.into()],
+            chunk_delay: Duration::ZERO,
+        }
+    }
+
+    /// One JSON value per line, written one line at a time.
+    pub fn ndjson(lines: Vec<Value>, delay: Duration) -> Self {
+        StubResponse {
+            status: 200,
+            headers: vec![(
+                "Content-Type".to_string(),
+                "application/x-ndjson".to_string(),
+            )],
+            chunks: lines
+                .iter()
+                .map(|line| format!("{}\n", line).into_bytes())
+                .collect(),
+            chunk_delay: delay,
+        }
+    }
+
+    /// `chunks` written one at a time, so that a payload can be split across
+    /// reads.
+    pub fn chunked(content_type: &str, chunks: &[&str], delay: Duration) -> Self {
+        StubResponse {
+            status: 200,
+            headers: vec![("Content-Type".to_string(), content_type.to_string())],
+            chunks: chunks
+                .iter()
+                .map(|chunk| chunk.as_bytes().to_vec())
+                .collect(),
+            chunk_delay: delay,
+        }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+#[derive(Clone)]
+pub struct StubServer {
+    url: String,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    responses: Arc<Mutex<VecDeque<StubResponse>>>,
+}
+
+impl StubServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = StubServer {
+            url: format!("http://{}", listener.local_addr().unwrap()),
+            requests: Arc::new(Mutex::new(vec![])),
+            responses: Arc::new(Mutex::new(VecDeque::new())),
+        };
+        let handler = server.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                tokio::spawn(async move { handler.serve(stream).await });
+            }
+        });
+        server
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Answers the next request with `response`. Requests without a queued
+    /// response get a 500.
+    pub fn push(&self, response: StubResponse) -> &Self {
+        self.responses.lock().unwrap().push_back(response);
+        self
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn last_request(&self) -> RecordedRequest {
+        self.requests()
+            .pop()
+            .expect("the server has not received a request")
+    }
+
+    async fn serve(&self, stream: TcpStream) {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let Some(request) = read_request(&mut reader).await else {
+            return;
+        };
+        self.requests.lock().unwrap().push(request);
+        let response = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_else(|| StubResponse::status(500, json!({"error": "no stub response"})));
+        let length: usize = response.chunks.iter().map(Vec::len).sum();
+        let mut head = format!("HTTP/1.1 {} Stub\r\n", response.status);
+        for (name, value) in &response.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str(&format!(
+            "Content-Length: {}\r\nConnection: close\r\n\r\n",
+            length
+        ));
+        if writer.write_all(head.as_bytes()).await.is_err() {
+            return;
+        }
+        for chunk in &response.chunks {
+            if writer.write_all(chunk).await.is_err() {
+                return;
+            }
+            let _ = writer.flush().await;
+            if !response.chunk_delay.is_zero() {
+                tokio::time::sleep(response.chunk_delay).await;
+            }
+        }
+        let _ = writer.shutdown().await;
+    }
+}
+
+async fn read_request<R: AsyncBufReadExt + Unpin>(reader: &mut R) -> Option<RecordedRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let path = parts.next()?.to_string();
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await.ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+    let mut body = vec![];
+    if let Some(length) = headers.get("content-length") {
+        body.resize(length.parse().ok()?, 0);
+        reader.read_exact(&mut body).await.ok()?;
+    } else if headers
+        .get("transfer-encoding")
+        .is_some_and(|encoding| encoding.contains("chunked"))
+    {
+        loop {
+            let mut size = String::new();
+            reader.read_line(&mut size).await.ok()?;
+            let size = usize::from_str_radix(size.trim(), 16).ok()?;
+            let mut chunk = vec![0; size + 2];
+            reader.read_exact(&mut chunk).await.ok()?;
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+    Some(RecordedRequest {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
@@ -0,0 +1,206 @@
+mod common;
+
+use common::{chat_reply_with_usage, openai_builder, StubServer};
+use llm_primitives::{
+    CallOptions, CostTotals, CostTracker, Error, Model, Price, PriceTable, Response, TextStream,
+    Usage,
+};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::cell::Cell;
+use std::future::{ready, Future};
+
+fn usage(input_tokens: u64, output_tokens: u64) -> Usage {
+    Usage {
+        input_tokens,
+        output_tokens,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn cached_input_is_billed_at_its_own_price() {
+    let price = Price::new(2.0, 8.0);
+    let usage = Usage {
+        input_tokens: 1_000_000,
+        output_tokens: 500_000,
+        cached_input_tokens: 400_000,
+        reasoning_tokens: 0,
+    };
+    assert_eq!(price.cost(&usage), 2.0 + 4.0);
+    assert_eq!(price.with_cached_input(0.5).cost(&usage), 1.2 + 0.2 + 4.0);
+}
+
+#[test]
+fn prefixes_price_versions_of_a_model_but_not_other_models() {
+    let mut table = PriceTable::new();
+    table
+        .price("gpt-4o".to_string(), Price::new(2.5, 10.0))
+        .price("gpt-4o-mini".to_string(), Price::new(0.15, 0.6))
+        .price("gpt-4".to_string(), Price::new(30.0, 60.0))
+        .price("claude-3-5-sonnet".to_string(), Price::new(3.0, 15.0));
+
+    let input = |model: &str| table.get(model).map(|price| price.input);
+    assert_eq!(input("gpt-4o"), Some(2.5));
+    assert_eq!(input("gpt-4o-2024-08-06"), Some(2.5));
+    assert_eq!(input("gpt-4o-mini"), Some(0.15));
+    assert_eq!(input("gpt-4o-mini-2024-07-18"), Some(0.15));
+    assert_eq!(input("gpt-4-0613"), Some(30.0));
+    assert_eq!(input("claude-3-5-sonnet@20240620"), Some(3.0));
+    // Neither a version of `gpt-4o` nor of `gpt-4`.
+    assert_eq!(input("gpt-4o-audio-preview"), None);
+    assert_eq!(input("gpt-4-turbo"), None);
+    assert_eq!(input("gpt-4.1"), None);
+    assert_eq!(table.cost("gpt-4.1", &usage(1, 1)), None);
+}
+
+#[tokio::test]
+async fn trackers_total_usage_and_cost_across_the_models_sharing_them() {
+    let server = StubServer::start().await;
+    server.push(chat_reply_with_usage(1_000_000, 100_000));
+    server.push(chat_reply_with_usage(500_000, 0));
+    let mut prices = PriceTable::new();
+    prices.price("gpt-test".to_string(), Price::new(2.0, 4.0));
+    let tracker = CostTracker::new();
+    let priced = openai_builder(&server)
+        .price_table(prices)
+        .cost_tracker(tracker.clone())
+        .build();
+    let unpriced = openai_builder(&server)
+        .cost_tracker(tracker.clone())
+        .build();
+
+    let response = priced
+        .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+        .await
+        .unwrap();
+    assert_eq!(response.cost, Some(2.4));
+    let response = unpriced
+        .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+        .await
+        .unwrap();
+    assert_eq!(response.cost, None);
+
+    let totals = tracker.totals();
+    assert_eq!(totals.requests, 2);
+    assert_eq!(totals.usage, usage(1_500_000, 100_000));
+    assert_eq!(totals.cost, 2.4);
+    assert_eq!(priced.cost_tracker().totals(), totals);
+    unpriced.cost_tracker().reset();
+    assert_eq!(tracker.totals(), CostTotals::default());
+}
+
+/// A model outside the crate that is neither `Sync` nor tracks cost.
+struct Canned {
+    calls: Cell<u32>,
+}
+
+impl Canned {
+    fn answer<T>(&self, value: T) -> impl Future<Output = Result<Response<T>, Error>> + Send
+    where
+        T: Send,
+    {
+        self.calls.set(self.calls.get() + 1);
+        async move {
+            Ok(Response {
+                value,
+                repairs: vec![],
+                usage: None,
+                cost: None,
+                model: "canned".to_string(),
+                latency: Default::default(),
+                request_id: None,
+                cached: false,
+                served_by: None,
+            })
+        }
+    }
+}
+
+impl Model for Canned {
+    fn classify_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        _choices: Vec<String>,
+        _options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<usize>, Error>> + Send {
+        self.answer(0)
+    }
+
+    fn binary_classify_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        _options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<bool>, Error>> + Send {
+        self.answer(true)
+    }
+
+    fn generate_text_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        _options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<String>, Error>> + Send {
+        self.answer("canned".to_string())
+    }
+
+    fn generate_text_stream_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        _options: &CallOptions,
+    ) -> impl Future<Output = Result<TextStream, Error>> + Send {
+        ready(Err(Error::Cancelled))
+    }
+
+    fn score_float_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        min_bound: f64,
+        _max_bound: f64,
+        _options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<f64>, Error>> + Send {
+        self.answer(min_bound)
+    }
+
+    fn score_int_with_options(
+        &self,
+        _instruction: String,
+        _text: String,
+        min_bound: i64,
+        _max_bound: i64,
+        _options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<i64>, Error>> + Send {
+        self.answer(min_bound)
+    }
+
+    // An `async fn` would hold `&self`, which is not `Send`.
+    #[allow(clippy::manual_async_fn)]
+    fn parse_with_options<T>(
+        &self,
+        _text: String,
+        _options: &CallOptions,
+    ) -> impl Future<Output = Result<Response<T>, Error>> + Send
+    where
+        T: for<'de> Deserialize<'de> + JsonSchema,
+    {
+        async { Err(Error::Cancelled) }
+    }
+}
+
+#[tokio::test]
+async fn models_without_a_tracker_report_nothing() {
+    let model = Canned {
+        calls: Cell::new(0),
+    };
+    let response = model
+        .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+        .await
+        .unwrap();
+    assert_eq!(response.value, "canned");
+    assert_eq!(model.calls.get(), 1);
+    assert_eq!(model.cost_tracker().totals(), CostTotals::default());
+}
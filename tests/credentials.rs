@@ -0,0 +1,76 @@
+use llm_primitives::{CredentialProvider, Error};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A rotating provider whose fetches hand out `key-0`, `key-1`, ... and fail
+/// while the returned flag is set.
+fn rotating(interval: Duration) -> (CredentialProvider, Arc<AtomicBool>) {
+    let failing = Arc::new(AtomicBool::new(false));
+    let fetches = Arc::new(AtomicUsize::new(0));
+    let fail = failing.clone();
+    let provider = CredentialProvider::rotating(interval, move || {
+        let result = if fail.load(Ordering::SeqCst) {
+            Err("secret manager unavailable".into())
+        } else {
+            Ok(format!("key-{}", fetches.fetch_add(1, Ordering::SeqCst)))
+        };
+        async move { result }
+    });
+    (provider, failing)
+}
+
+#[tokio::test]
+async fn rotating_keys_are_refreshed_once_they_are_older_than_the_interval() {
+    let (provider, _) = rotating(Duration::from_millis(50));
+    assert_eq!(provider.fetch().await.unwrap(), "key-0");
+    assert_eq!(provider.fetch().await.unwrap(), "key-0");
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(provider.fetch().await.unwrap(), "key-1");
+}
+
+#[tokio::test]
+async fn a_failed_first_fetch_fails_the_request() {
+    let (provider, failing) = rotating(Duration::from_secs(60));
+    failing.store(true, Ordering::SeqCst);
+    assert!(matches!(provider.fetch().await, Err(Error::Credentials(_))));
+    failing.store(false, Ordering::SeqCst);
+    assert_eq!(provider.fetch().await.unwrap(), "key-0");
+}
+
+#[tokio::test]
+async fn a_stale_key_is_used_for_at_most_one_more_interval_after_failed_refreshes() {
+    let (provider, failing) = rotating(Duration::from_millis(100));
+    assert_eq!(provider.fetch().await.unwrap(), "key-0");
+    failing.store(true, Ordering::SeqCst);
+
+    tokio::time::sleep(Duration::from_millis(120)).await;
+    assert_eq!(provider.fetch().await.unwrap(), "key-0");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    match provider.fetch().await {
+        Err(Error::Credentials(e)) => assert_eq!(e.to_string(), "secret manager unavailable"),
+        other => panic!("expected a credentials error, got {:?}", other),
+    }
+
+    failing.store(false, Ordering::SeqCst);
+    assert_eq!(provider.fetch().await.unwrap(), "key-1");
+}
+
+#[tokio::test]
+async fn debug_output_never_contains_the_key() {
+    let fixed = CredentialProvider::fixed("sk-fixed-secret".to_string());
+    assert_eq!(format!("{:?}", fixed), "Fixed(<redacted>)");
+
+    let callback = CredentialProvider::callback(|| async { Ok("sk-callback-secret".to_string()) });
+    assert_eq!(callback.fetch().await.unwrap(), "sk-callback-secret");
+    assert_eq!(format!("{:?}", callback), "Callback");
+
+    let (rotating, _) = rotating(Duration::from_secs(60));
+    assert_eq!(rotating.fetch().await.unwrap(), "key-0");
+    let debug = format!("{:?}", rotating);
+    assert!(!debug.contains("key-0"), "{}", debug);
+    assert!(debug.starts_with("Rotating"), "{}", debug);
+
+    let env = CredentialProvider::env("LLM_PRIMITIVES_TEST_KEY".to_string());
+    assert_eq!(format!("{:?}", env), "Env(\"LLM_PRIMITIVES_TEST_KEY\")");
+}
@@ -0,0 +1,56 @@
+mod common;
+
+use common::{chat_reply, openai_model, StubServer};
+use llm_primitives::{AnyModel, CallOptionsBuilder, Error, Model};
+use schemars::JsonSchema;
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+struct Person {
+    name: String,
+    age: u32,
+}
+
+fn any_model(server: &StubServer) -> AnyModel {
+    AnyModel::new(openai_model(server))
+}
+
+#[tokio::test]
+async fn parse_repairs_answers_that_do_not_fit_the_schema() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("{\"name\": \"Ada\"}"));
+    server.push(chat_reply("{\"name\": \"Ada\", \"age\": 36}"));
+    let options = CallOptionsBuilder::new().max_repairs(1).build();
+    let response = any_model(&server)
+        .parse_with_options::<Person>("Ada is 36.".to_string(), &options)
+        .await
+        .unwrap();
+    assert_eq!(
+        response.value,
+        Person {
+            name: "Ada".to_string(),
+            age: 36,
+        }
+    );
+    assert_eq!(response.repairs.len(), 1);
+
+    let body = server.last_request().json();
+    let repair = body["messages"].as_array().unwrap().last().unwrap()["content"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(repair.contains("missing property `age`"), "{}", repair);
+}
+
+#[tokio::test]
+async fn parse_value_rejects_answers_that_do_not_fit_the_schema() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("{\"name\": \"Ada\", \"age\": \"thirty-six\"}"));
+    let schema = serde_json::to_value(schemars::schema_for!(Person)).unwrap();
+    let error = any_model(&server)
+        .parse_value("Ada is 36.".to_string(), schema)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::SchemaMismatch { .. }), "{:?}", error);
+    assert!(error.to_string().contains("/age"), "{}", error);
+}
@@ -0,0 +1,144 @@
+use llm_primitives::{
+    cohen_kappa, fleiss_kappa, BatchOptions, EnsembleModelBuilder, Error, MockModel,
+};
+
+#[tokio::test]
+async fn items_a_model_failed_on_are_sent_for_review() {
+    let steady = MockModel::new();
+    steady.on_classify(|_| Ok(0));
+    let flaky = MockModel::new();
+    flaky.on_classify(|call| match call.text() {
+        "fails" => Err(Error::Timeout),
+        _ => Ok(0),
+    });
+    let ensemble = EnsembleModelBuilder::new()
+        .model("steady".to_string(), steady)
+        .model("flaky".to_string(), flaky)
+        .build();
+
+    let report = ensemble
+        .classify_many(
+            "Pick one.".to_string(),
+            vec!["works".to_string(), "fails".to_string()],
+            vec!["a".to_string(), "b".to_string()],
+            &BatchOptions::default(),
+        )
+        .await;
+    assert!(!report.items[0].incomplete);
+    let item = &report.items[1];
+    assert!(item.incomplete);
+    assert!(!item.disagreement);
+    assert_eq!(item.majority, Some(0));
+    let review: Vec<usize> = report.disagreements().map(|(index, _)| index).collect();
+    assert_eq!(review, vec![1]);
+}
+
+fn assert_close(actual: Option<f64>, expected: f64) {
+    let actual = actual.expect("kappa is defined");
+    assert!(
+        (actual - expected).abs() < 1e-9,
+        "{} != {}",
+        actual,
+        expected
+    );
+}
+
+/// Two raters labelling `counts[i][j]` items `i` and `j` respectively.
+fn cohen_ratings(counts: [[usize; 2]; 2]) -> (Vec<&'static str>, Vec<&'static str>) {
+    let labels = ["yes", "no"];
+    let mut first = vec![];
+    let mut second = vec![];
+    for (i, row) in counts.iter().enumerate() {
+        for (j, count) in row.iter().enumerate() {
+            first.extend(std::iter::repeat_n(labels[i], *count));
+            second.extend(std::iter::repeat_n(labels[j], *count));
+        }
+    }
+    (first, second)
+}
+
+#[test]
+fn cohen_kappa_matches_textbook_values() {
+    // Observed agreement 0.7 against 0.5 expected by chance.
+    let (first, second) = cohen_ratings([[20, 5], [10, 15]]);
+    assert_close(cohen_kappa(&first, &second), 0.4);
+    // Observed agreement 0.6, but skewed label frequencies make chance
+    // explain 0.54 of it.
+    let (first, second) = cohen_ratings([[45, 15], [25, 15]]);
+    assert_close(cohen_kappa(&first, &second), 3.0 / 23.0);
+
+    assert_close(cohen_kappa(&first, &first), 1.0);
+    assert_close(cohen_kappa(&["a", "b"], &["b", "a"]), -1.0);
+}
+
+#[test]
+fn cohen_kappa_is_undefined_without_ratings_or_label_variety() {
+    assert_eq!(cohen_kappa::<&str>(&[], &[]), None);
+    assert_eq!(cohen_kappa(&["a", "a", "a"], &["a", "a", "a"]), None);
+    // One rater varying is enough.
+    assert_close(cohen_kappa(&["a", "a"], &["a", "b"]), 0.0);
+}
+
+#[test]
+#[should_panic(expected = "both raters must rate the same items")]
+fn cohen_kappa_needs_the_same_items() {
+    cohen_kappa(&["a"], &["a", "b"]);
+}
+
+#[test]
+fn fleiss_kappa_matches_the_textbook_example() {
+    // The worked example commonly used for Fleiss' kappa: 10 items rated by
+    // 14 raters into 5 categories, as counts per category.
+    let counts = [
+        [0, 0, 0, 0, 14],
+        [0, 2, 6, 4, 2],
+        [0, 0, 3, 5, 6],
+        [0, 3, 9, 2, 0],
+        [2, 2, 8, 1, 1],
+        [7, 7, 0, 0, 0],
+        [3, 2, 6, 3, 0],
+        [2, 5, 3, 2, 2],
+        [6, 5, 2, 1, 0],
+        [0, 2, 2, 3, 7],
+    ];
+    let ratings: Vec<Vec<usize>> = counts
+        .iter()
+        .map(|item| {
+            item.iter()
+                .enumerate()
+                .flat_map(|(category, count)| std::iter::repeat_n(category, *count))
+                .collect()
+        })
+        .collect();
+    let kappa = fleiss_kappa(&ratings).unwrap();
+    assert!((kappa - 0.2099).abs() < 1e-4, "{}", kappa);
+}
+
+#[test]
+fn fleiss_kappa_pools_the_label_frequencies_of_all_raters() {
+    let ratings = vec![
+        vec!["a", "a"],
+        vec!["b", "b"],
+        vec!["a", "b"],
+        vec!["b", "b"],
+    ];
+    // Fleiss pools both raters' frequencies: p = 3/8 and 5/8.
+    assert_close(
+        fleiss_kappa(&ratings),
+        (0.75 - 34.0 / 64.0) / (1.0 - 34.0 / 64.0),
+    );
+    assert_close(fleiss_kappa(&[vec![1, 1, 1], vec![2, 2, 2]]), 1.0);
+}
+
+#[test]
+fn fleiss_kappa_is_undefined_without_items_raters_or_label_variety() {
+    assert_eq!(fleiss_kappa::<u8>(&[]), None);
+    assert_eq!(fleiss_kappa(&[vec![1], vec![2]]), None);
+    assert_eq!(fleiss_kappa(&[vec![1, 1], vec![1, 1]]), None);
+}
+
+#[test]
+#[should_panic(expected = "every item must have the same number of ratings")]
+fn fleiss_kappa_needs_the_same_number_of_ratings_per_item() {
+    fleiss_kappa(&[vec![1, 2], vec![1]]);
+}
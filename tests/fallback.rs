@@ -0,0 +1,206 @@
+mod common;
+
+use common::{chat_reply, openai_builder, StubResponse, StubServer};
+use futures::StreamExt;
+use llm_primitives::{
+    Error, FallbackModel, FallbackModelBuilder, MockModel, Model, OpenAIModel, Price, PriceTable,
+};
+use serde_json::json;
+use std::time::Duration;
+
+fn model(server: &StubServer) -> OpenAIModel {
+    openai_builder(server)
+        .price_table(
+            PriceTable::new()
+                .price("gpt-test".to_string(), Price::new(2.0, 4.0))
+                .clone(),
+        )
+        .build()
+}
+
+fn unavailable() -> StubResponse {
+    StubResponse::status(503, json!({"error": {"message": "overloaded"}}))
+}
+
+#[tokio::test]
+async fn responses_name_the_model_that_served_them() {
+    let first = StubServer::start().await;
+    let second = StubServer::start().await;
+    first.push(unavailable());
+    second.push(chat_reply("ok"));
+    let fallback = FallbackModelBuilder::new()
+        .model("first".to_string(), model(&first))
+        .model("second".to_string(), model(&second))
+        .build();
+
+    let response = fallback
+        .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+        .await
+        .unwrap();
+    assert_eq!(response.value, "ok");
+    assert_eq!(response.served_by.as_deref(), Some("second"));
+}
+
+#[tokio::test]
+async fn streamed_calls_record_their_cost() {
+    let first = StubServer::start().await;
+    let second = StubServer::start().await;
+    first.push(unavailable());
+    second.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"ok\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":1000000,\"completion_tokens\":0}}\n\n",
+            "data: [DONE]\n\n",
+        ),
+    ));
+    let fallback = FallbackModelBuilder::new()
+        .model("first".to_string(), model(&first))
+        .model("second".to_string(), model(&second))
+        .build();
+
+    let deltas: Vec<_> = fallback
+        .generate_text_stream("i".to_string(), "t".to_string())
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    let deltas: Vec<_> = deltas.into_iter().map(Result::unwrap).collect();
+    assert!(deltas
+        .iter()
+        .all(|delta| delta.served_by.as_deref() == Some("second")));
+    assert_eq!(deltas.last().unwrap().cost, Some(2.0));
+
+    let totals = fallback.cost_tracker().totals();
+    assert_eq!(totals.requests, 1);
+    assert_eq!(totals.cost, 2.0);
+}
+
+#[tokio::test]
+async fn rejected_credentials_do_not_fall_back() {
+    let first = StubServer::start().await;
+    let second = StubServer::start().await;
+    first.push(StubResponse::status(
+        401,
+        json!({"error": {"message": "bad key"}}),
+    ));
+    let fallback = FallbackModelBuilder::new()
+        .model("first".to_string(), model(&first))
+        .model("second".to_string(), model(&second))
+        .build();
+
+    let result = fallback
+        .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+        .await;
+    assert!(matches!(result, Err(Error::Auth { status: 401, .. })));
+    assert!(second.requests().is_empty());
+}
+
+const COOLDOWN: Duration = Duration::from_millis(100);
+
+/// A fallback from `primary` to a `backup` that always answers "backup",
+/// skipping `primary` for `COOLDOWN` after two failures in a row.
+fn with_breaker(primary: &MockModel) -> FallbackModel {
+    let backup = MockModel::new();
+    backup.on_generate_text(|_| Ok("backup".to_string()));
+    FallbackModelBuilder::new()
+        .model("primary".to_string(), primary.clone())
+        .model("backup".to_string(), backup)
+        .circuit_breaker(2, COOLDOWN)
+        .build()
+}
+
+async fn served_by(fallback: &FallbackModel) -> String {
+    fallback
+        .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+        .await
+        .unwrap()
+        .served_by
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_circuit_opens_after_consecutive_failures_and_closes_on_success() {
+    let primary = MockModel::new();
+    primary
+        .queue_generate_text(Err(Error::Timeout))
+        .queue_generate_text(Err(Error::Timeout))
+        .on_generate_text(|_| Ok("primary".to_string()));
+    let fallback = with_breaker(&primary);
+
+    assert_eq!(served_by(&fallback).await, "backup");
+    assert!(!fallback.is_circuit_open("primary"));
+    assert_eq!(served_by(&fallback).await, "backup");
+    assert!(fallback.is_circuit_open("primary"));
+
+    // Skipped while open.
+    assert_eq!(served_by(&fallback).await, "backup");
+    assert_eq!(primary.call_count(), 2);
+
+    tokio::time::sleep(COOLDOWN).await;
+    assert!(!fallback.is_circuit_open("primary"));
+    assert_eq!(served_by(&fallback).await, "primary");
+    assert_eq!(primary.call_count(), 3);
+}
+
+#[tokio::test]
+async fn one_failure_after_the_cooldown_reopens_the_circuit() {
+    let primary = MockModel::new();
+    primary
+        .queue_generate_text(Err(Error::Timeout))
+        .queue_generate_text(Err(Error::Timeout))
+        .queue_generate_text(Err(Error::Timeout))
+        .on_generate_text(|_| Ok("primary".to_string()));
+    let fallback = with_breaker(&primary);
+    served_by(&fallback).await;
+    served_by(&fallback).await;
+    assert!(fallback.is_circuit_open("primary"));
+
+    tokio::time::sleep(COOLDOWN).await;
+    assert_eq!(served_by(&fallback).await, "backup");
+    assert_eq!(primary.call_count(), 3);
+    assert!(fallback.is_circuit_open("primary"));
+    assert_eq!(served_by(&fallback).await, "backup");
+    assert_eq!(primary.call_count(), 3);
+}
+
+#[tokio::test]
+async fn errors_that_do_not_fall_back_leave_the_circuit_closed() {
+    let primary = MockModel::new();
+    primary.on_generate_text(|_| {
+        Err(Error::InvalidChoice {
+            raw: "?".to_string(),
+        })
+    });
+    let fallback = with_breaker(&primary);
+    for _ in 0..3 {
+        let result = fallback
+            .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+            .await;
+        assert!(matches!(result, Err(Error::InvalidChoice { .. })));
+    }
+    assert!(!fallback.is_circuit_open("primary"));
+}
+
+#[tokio::test]
+async fn every_model_is_tried_when_every_circuit_is_open() {
+    let only = MockModel::new();
+    only.queue_generate_text(Err(Error::Timeout))
+        .queue_generate_text(Err(Error::Timeout))
+        .on_generate_text(|_| Ok("recovered".to_string()));
+    let fallback = FallbackModelBuilder::new()
+        .model("only".to_string(), only.clone())
+        .circuit_breaker(2, Duration::from_secs(60))
+        .build();
+    for _ in 0..2 {
+        let result = fallback
+            .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+            .await;
+        assert!(matches!(result, Err(Error::Timeout)));
+    }
+    assert!(fallback.is_circuit_open("only"));
+
+    assert_eq!(served_by(&fallback).await, "only");
+    assert!(!fallback.is_circuit_open("only"));
+}
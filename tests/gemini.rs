@@ -0,0 +1,299 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use futures::StreamExt;
+use llm_primitives::{Error, GeminiModel, GeminiModelBuilder, Model};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+fn model(server: &StubServer) -> GeminiModel {
+    GeminiModelBuilder::new("gemini-test".to_string())
+        .api_key("test-key".to_string())
+        .base_url(format!("{}/v1beta", server.url()))
+        .build()
+}
+
+fn text_reply(text: &str) -> StubResponse {
+    StubResponse::json(json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [{"text": text}]},
+            "finishReason": "STOP",
+        }],
+        "usageMetadata": {"promptTokenCount": 9, "candidatesTokenCount": 3},
+        "modelVersion": "gemini-test-001",
+    }))
+}
+
+fn response_schema(server: &StubServer) -> Value {
+    server.last_request().json()["generationConfig"]["responseSchema"].clone()
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[allow(dead_code)]
+enum Color {
+    Red,
+    Green,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[allow(dead_code)]
+struct Address {
+    city: String,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+#[allow(dead_code)]
+struct Person {
+    /// Full name.
+    name: String,
+    age: u32,
+    visits: i64,
+    nickname: Option<String>,
+    color: Color,
+    address: Address,
+    previous: Option<Address>,
+    tags: Vec<String>,
+}
+
+#[tokio::test]
+async fn sends_the_key_system_instruction_and_contents() {
+    let server = StubServer::start().await;
+    server.push(text_reply("Hello"));
+    let response = model(&server)
+        .generate_text_with_options(
+            "Be brief.".to_string(),
+            "Hi".to_string(),
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.value, "Hello");
+    assert_eq!(response.model, "gemini-test-001");
+    let usage = response.usage.unwrap();
+    assert_eq!((usage.input_tokens, usage.output_tokens), (9, 3));
+
+    let request = server.last_request();
+    assert_eq!(request.path, "/v1beta/models/gemini-test:generateContent");
+    assert_eq!(request.header("x-goog-api-key"), Some("test-key"));
+    let body = request.json();
+    assert_eq!(
+        body["systemInstruction"],
+        json!({"parts": [{"text": "Be brief."}]})
+    );
+    assert_eq!(
+        body["contents"],
+        json!([{"role": "user", "parts": [{"text": "Hi"}]}])
+    );
+    assert!(body["generationConfig"].get("responseSchema").is_none());
+}
+
+#[tokio::test]
+async fn parse_inlines_definitions_into_the_response_schema() {
+    let server = StubServer::start().await;
+    server.push(text_reply(
+        &json!({
+            "name": "Ada",
+            "age": 36,
+            "visits": 2,
+            "nickname": null,
+            "color": "Green",
+            "address": {"city": "London"},
+            "previous": null,
+            "tags": [],
+        })
+        .to_string(),
+    ));
+    let person = model(&server)
+        .parse::<Person>("Ada, 36, from London.".to_string())
+        .await
+        .unwrap();
+    assert_eq!(person.name, "Ada");
+
+    let body = server.last_request().json();
+    assert_eq!(
+        body["generationConfig"]["responseMimeType"],
+        "application/json"
+    );
+    let schema = response_schema(&server);
+    assert_eq!(schema["type"], "OBJECT");
+    assert!(schema.get("definitions").is_none());
+    assert!(schema.get("$schema").is_none());
+    let properties = &schema["properties"];
+    assert_eq!(
+        properties["name"],
+        json!({"type": "STRING", "description": "Full name."})
+    );
+    // `uint32` is not a format Gemini knows, `int64` is.
+    assert_eq!(properties["age"]["type"], "INTEGER");
+    assert!(properties["age"].get("format").is_none());
+    assert_eq!(properties["visits"]["format"], "int64");
+    assert_eq!(
+        properties["nickname"],
+        json!({"type": "STRING", "nullable": true})
+    );
+    assert_eq!(
+        properties["color"],
+        json!({"type": "STRING", "enum": ["Red", "Green"]})
+    );
+    assert_eq!(
+        properties["address"],
+        json!({
+            "type": "OBJECT",
+            "properties": {"city": {"type": "STRING"}},
+            "required": ["city"],
+        })
+    );
+    assert_eq!(properties["previous"]["type"], "OBJECT");
+    assert_eq!(properties["previous"]["nullable"], true);
+    assert_eq!(
+        properties["tags"],
+        json!({"type": "ARRAY", "items": {"type": "STRING"}})
+    );
+}
+
+#[tokio::test]
+async fn refs_into_defs_are_inlined_too() {
+    let server = StubServer::start().await;
+    server.push(text_reply(
+        "{\"home\": {\"city\": \"London\"}, \"work\": null}",
+    ));
+    let schema = json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "type": "object",
+        "properties": {
+            "home": {"$ref": "#/$defs/Address", "description": "Where they live."},
+            "work": {"anyOf": [{"$ref": "#/$defs/Address"}, {"type": "null"}]},
+        },
+        "required": ["home", "work"],
+        "$defs": {
+            "Address": {
+                "type": "object",
+                "properties": {"city": {"type": "string"}},
+                "required": ["city"],
+            },
+        },
+    });
+    model(&server)
+        .parse_value("Lives and works in London.".to_string(), schema)
+        .await
+        .unwrap();
+
+    let schema = response_schema(&server);
+    assert!(schema.get("$defs").is_none());
+    assert!(!schema.to_string().contains("$ref"), "{}", schema);
+    let address = json!({
+        "type": "OBJECT",
+        "properties": {"city": {"type": "STRING"}},
+        "required": ["city"],
+    });
+    let mut home = address.clone();
+    home["description"] = json!("Where they live.");
+    assert_eq!(schema["properties"]["home"], home);
+    let mut work = address;
+    work["nullable"] = json!(true);
+    assert_eq!(schema["properties"]["work"], work);
+}
+
+#[tokio::test]
+async fn enums_are_kept_only_on_string_schemas() {
+    let server = StubServer::start().await;
+    server.push(text_reply(
+        "{\"level\": 2, \"size\": \"M\", \"unit\": \"cm\"}",
+    ));
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "level": {"type": "integer", "enum": [1, 2, 3]},
+            "size": {"type": ["string", "null"], "enum": ["S", "M", null]},
+            "unit": {"enum": ["cm", "in"]},
+        },
+        "required": ["level", "size", "unit"],
+    });
+    model(&server)
+        .parse_value("Level 2, size M, in cm.".to_string(), schema)
+        .await
+        .unwrap();
+
+    let properties = response_schema(&server)["properties"].clone();
+    assert_eq!(properties["level"], json!({"type": "INTEGER"}));
+    assert_eq!(
+        properties["size"],
+        json!({"type": "STRING", "nullable": true, "enum": ["S", "M"]})
+    );
+    assert_eq!(
+        properties["unit"],
+        json!({"type": "STRING", "enum": ["cm", "in"]})
+    );
+}
+
+#[tokio::test]
+async fn blocked_prompts_are_content_filtered() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::json(json!({
+        "promptFeedback": {"blockReason": "SAFETY"},
+    })));
+    let error = model(&server)
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, Error::ContentFiltered { reason } if reason == "SAFETY"),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn withheld_candidates_are_content_filtered() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::json(json!({
+        "candidates": [{"finishReason": "RECITATION"}],
+    })));
+    server.push(StubResponse::json(json!({
+        "candidates": [{
+            "content": {"role": "model", "parts": [{"text": "partial"}]},
+            "finishReason": "SAFETY",
+        }],
+    })));
+    let model = model(&server);
+    let error = model
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, Error::ContentFiltered { reason } if reason == "RECITATION"),
+        "{:?}",
+        error
+    );
+    // Text that was generated before the block is returned.
+    let text = model
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "partial");
+}
+
+#[tokio::test]
+async fn streams_blocked_before_any_text_are_content_filtered() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        "data: {\"candidates\":[{\"finishReason\":\"SAFETY\"}]}\n\n",
+    ));
+    let mut stream = model(&server)
+        .generate_text_stream("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    let error = stream.next().await.unwrap().unwrap_err();
+    assert!(
+        matches!(&error, Error::ContentFiltered { reason } if reason == "SAFETY"),
+        "{:?}",
+        error
+    );
+    assert_eq!(
+        server.last_request().path,
+        "/v1beta/models/gemini-test:streamGenerateContent?alt=sse"
+    );
+}
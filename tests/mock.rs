@@ -0,0 +1,162 @@
+use llm_primitives::{CallOptionsBuilder, Error, MockCall, MockModel, Model, VoteOptionsBuilder};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+
+fn choices() -> Vec<String> {
+    vec!["car".to_string(), "fruit".to_string()]
+}
+
+#[tokio::test]
+async fn queued_results_come_first_then_the_handler() {
+    let model = MockModel::new();
+    model
+        .queue_classify(Ok(1))
+        .queue_classify(Err(Error::Cancelled))
+        .on_classify(|call| Ok(call.text().len() % 2));
+
+    let options = Default::default();
+    let classify = |text: &str| {
+        model.classify_with_options("i".to_string(), text.to_string(), choices(), &options)
+    };
+    assert_eq!(classify("a").await.unwrap().value, 1);
+    assert!(matches!(classify("a").await, Err(Error::Cancelled)));
+    assert_eq!(classify("ab").await.unwrap().value, 0);
+    assert_eq!(classify("abc").await.unwrap().value, 1);
+}
+
+#[tokio::test]
+#[should_panic]
+async fn a_call_with_nothing_scripted_panics() {
+    let model = MockModel::new();
+    model.queue_score_int(Ok(3));
+    let _ = model
+        .score_float_with_options(
+            "i".to_string(),
+            "t".to_string(),
+            0.0,
+            1.0,
+            &Default::default(),
+        )
+        .await;
+}
+
+#[derive(Debug, Deserialize, PartialEq, JsonSchema)]
+struct Person {
+    name: String,
+    age: u32,
+}
+
+#[tokio::test]
+async fn parse_answers_convert_through_json() {
+    let model = MockModel::new();
+    model
+        .queue_parse(Ok(json!({"name": "Ada", "age": 36})))
+        .on_parse(|call| Ok(json!({"name": call.text(), "age": 0})));
+
+    let response = model
+        .parse_with_options::<Person>("t".to_string(), &Default::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        response.value,
+        Person {
+            name: "Ada".to_string(),
+            age: 36,
+        }
+    );
+    let response = model
+        .parse_value_with_options("Bob".to_string(), json!({}), &Default::default())
+        .await
+        .unwrap();
+    assert_eq!(response.value, json!({"name": "Bob", "age": 0}));
+}
+
+#[tokio::test]
+async fn calls_are_recorded_with_their_arguments_and_options() {
+    let model = MockModel::new();
+    model
+        .on_generate_text(|_| Ok("text".to_string()))
+        .on_binary_classify(|_| Ok(true))
+        .latency(Duration::from_millis(20));
+    let options = CallOptionsBuilder::new()
+        .temperature(0.2)
+        .max_tokens(10)
+        .build();
+
+    let response = model
+        .generate_text_with_options("i".to_string(), "t".to_string(), &options)
+        .await
+        .unwrap();
+    assert!(response.latency >= Duration::from_millis(20));
+    model
+        .binary_classify_with_options("j".to_string(), "u".to_string(), &Default::default())
+        .await
+        .unwrap();
+
+    assert_eq!(model.call_count(), 2);
+    assert_eq!(
+        model.calls(),
+        vec![
+            MockCall::GenerateText {
+                instruction: "i".to_string(),
+                text: "t".to_string(),
+                options: options.clone(),
+            },
+            MockCall::BinaryClassify {
+                instruction: "j".to_string(),
+                text: "u".to_string(),
+                options: Default::default(),
+            },
+        ]
+    );
+    assert_eq!(model.calls()[0].options(), &options);
+    assert_eq!(model.calls()[0].vote_options(), None);
+
+    model.clear_calls();
+    assert_eq!(model.call_count(), 0);
+    // The script survives.
+    model
+        .generate_text_with_options("i".to_string(), "t".to_string(), &options)
+        .await
+        .unwrap();
+    assert_eq!(model.call_count(), 1);
+}
+
+#[tokio::test]
+async fn every_sample_of_a_vote_is_recorded_with_the_vote_options() {
+    let model = MockModel::new();
+    model.on_score_int(|_| Ok(4));
+    let vote = VoteOptionsBuilder::new()
+        .samples(3)
+        .temperature(0.9)
+        .call_options(CallOptionsBuilder::new().max_tokens(10).build())
+        .build();
+
+    let response = model
+        .score_int_vote("i".to_string(), "t".to_string(), 1, 5, &vote)
+        .await
+        .unwrap();
+    assert_eq!(response.value.scores, vec![4, 4, 4]);
+
+    let calls = model.calls();
+    assert_eq!(calls.len(), 3);
+    let sample_options = CallOptionsBuilder::new()
+        .max_tokens(10)
+        .temperature(0.9)
+        .bypass_cache(true)
+        .build();
+    for call in &calls {
+        assert!(matches!(
+            call,
+            MockCall::ScoreInt {
+                min_bound: 1,
+                max_bound: 5,
+                ..
+            }
+        ));
+        assert_eq!(call.vote_options(), Some(&vote));
+        assert_eq!(call.options(), &sample_options);
+    }
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::{StubResponse, StubServer};
+use llm_primitives::{
+    CallOptions, Error, HttpConfigBuilder, Model, OllamaModel, OllamaModelBuilder,
+};
+use serde_json::json;
+use std::time::Duration;
+
+fn model(server: &StubServer) -> OllamaModel {
+    OllamaModelBuilder::new("llama3".to_string())
+        .base_url(server.url().to_string())
+        .build()
+}
+
+fn chat_reply(content: &str) -> StubResponse {
+    StubResponse::json(json!({
+        "model": "llama3:latest",
+        "message": {"role": "assistant", "content": content},
+        "done": true,
+        "prompt_eval_count": 20,
+        "eval_count": 6,
+    }))
+}
+
+#[tokio::test]
+async fn chat_sends_messages_and_options() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("Hi!"));
+    let response = model(&server)
+        .generate_text_with_options(
+            "Greet the user.".to_string(),
+            "Hello".to_string(),
+            &CallOptions::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.value, "Hi!");
+    assert_eq!(response.model, "llama3:latest");
+    let usage = response.usage.unwrap();
+    assert_eq!((usage.input_tokens, usage.output_tokens), (20, 6));
+
+    let request = server.last_request();
+    assert_eq!(request.path, "/api/chat");
+    let body = request.json();
+    assert_eq!(body["model"], "llama3");
+    assert_eq!(body["stream"], false);
+    assert_eq!(
+        body["messages"],
+        json!([
+            {"role": "system", "content": "Greet the user."},
+            {"role": "user", "content": "Hello"},
+        ])
+    );
+    assert!(body.get("format").is_none());
+    assert!(body["options"]["temperature"].is_number());
+}
+
+#[tokio::test]
+async fn classify_asks_for_the_schema_and_decodes_the_label() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("{\"classification\": \"A\"}"));
+    let choice = model(&server)
+        .classify(
+            "Is it positive?".to_string(),
+            "great".to_string(),
+            vec!["positive".to_string(), "negative".to_string()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(choice, 0);
+    let body = server.last_request().json();
+    assert_eq!(
+        body["format"]["properties"]["classification"]["enum"],
+        json!(["A", "B"])
+    );
+}
+
+#[tokio::test]
+async fn is_available_matches_the_latest_tag() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::json(
+        json!({"models": [{"name": "llama3:latest"}]}),
+    ));
+    assert!(model(&server).is_available().await.unwrap());
+    assert_eq!(server.last_request().path, "/api/tags");
+}
+
+#[tokio::test]
+async fn pull_follows_progress_past_the_client_timeout() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::ndjson(
+        vec![
+            json!({"status": "pulling manifest"}),
+            json!({"status": "downloading", "digest": "sha256:1", "total": 100, "completed": 50}),
+            json!({"status": "downloading", "digest": "sha256:1", "total": 100, "completed": 100}),
+            json!({"status": "verifying sha256 digest"}),
+            json!({"status": "success"}),
+        ],
+        Duration::from_millis(100),
+    ));
+    let model = OllamaModelBuilder::new("llama3".to_string())
+        .base_url(server.url().to_string())
+        .http_config(
+            HttpConfigBuilder::new()
+                .timeout(Some(Duration::from_millis(250)))
+                .build(),
+        )
+        .build();
+    model.pull().await.unwrap();
+
+    let request = server.last_request();
+    assert_eq!(request.path, "/api/pull");
+    assert_eq!(request.json(), json!({"model": "llama3", "stream": true}));
+}
+
+#[tokio::test]
+async fn pull_fails_on_an_error_line_or_without_success() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::ndjson(
+        vec![
+            json!({"status": "pulling manifest"}),
+            json!({"error": "pull model manifest: file does not exist"}),
+        ],
+        Duration::ZERO,
+    ));
+    server.push(StubResponse::ndjson(
+        vec![json!({"status": "pulling manifest"})],
+        Duration::ZERO,
+    ));
+    let model = model(&server);
+    let error = model.pull().await.unwrap_err();
+    assert!(
+        matches!(error, Error::Http { status: 500, .. }),
+        "{:?}",
+        error
+    );
+    let error = model.pull().await.unwrap_err();
+    assert!(matches!(error, Error::SchemaMismatch { .. }), "{:?}", error);
+}
@@ -0,0 +1,180 @@
+mod common;
+
+use common::{chat_reply, chat_stream, openai_builder, openai_model, StubResponse, StubServer};
+use futures::StreamExt;
+use llm_primitives::{CallOptionsBuilder, Error, Model};
+use serde_json::json;
+
+#[tokio::test]
+async fn sends_bearer_auth_and_decodes_the_first_choice() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::json(json!({
+        "choices": [{
+            "message": {"role": "assistant", "content": "{\"classification\": \"B\"}"},
+            "finish_reason": "stop",
+        }],
+        "model": "gpt-test-0001",
+        "usage": {"prompt_tokens": 30, "completion_tokens": 8},
+    })));
+    let choice = openai_model(&server)
+        .classify(
+            "Pick the fruit.".to_string(),
+            "banana".to_string(),
+            vec!["car".to_string(), "fruit".to_string()],
+        )
+        .await
+        .unwrap();
+    assert_eq!(choice, 1);
+
+    let request = server.last_request();
+    assert_eq!(request.path, "/chat/completions");
+    assert_eq!(request.header("authorization"), Some("Bearer test-key"));
+    let body = request.json();
+    assert_eq!(body["model"], "gpt-test");
+    assert_eq!(body["response_format"], json!({"type": "json_object"}));
+}
+
+#[tokio::test]
+async fn filtered_choice_is_content_filtered() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::json(json!({
+        "choices": [{
+            "message": {"role": "assistant", "content": null},
+            "finish_reason": "content_filter",
+        }],
+    })));
+    let error = openai_model(&server)
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, Error::ContentFiltered { reason } if reason == "content_filter"),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn null_content_decodes_as_empty_text() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::json(json!({
+        "choices": [{
+            "message": {"role": "assistant", "content": null},
+            "finish_reason": "stop",
+        }],
+    })));
+    let text = openai_model(&server)
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "");
+}
+
+#[tokio::test]
+async fn filtered_stream_is_content_filtered() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        concat!(
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Once\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{},\"finish_reason\":\"content_filter\"}]}\n\n",
+            "data: [DONE]\n\n",
+        ),
+    ));
+    let mut stream = openai_model(&server)
+        .generate_text_stream("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(stream.next().await.unwrap().unwrap().text, "Once");
+    let error = stream.next().await.unwrap().unwrap_err();
+    assert!(
+        matches!(error, Error::ContentFiltered { .. }),
+        "{:?}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn streams_ask_for_usage_unless_turned_off() {
+    let server = StubServer::start().await;
+    server.push(chat_stream("Hi"));
+    server.push(chat_stream("Hi"));
+    let deltas: Vec<_> = openai_model(&server)
+        .generate_text_stream("i".to_string(), "t".to_string())
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    let usage = deltas.last().unwrap().as_ref().unwrap().usage.unwrap();
+    assert_eq!((usage.input_tokens, usage.output_tokens), (5, 2));
+    let body = server.last_request().json();
+    assert_eq!(body["stream"], true);
+    assert_eq!(body["stream_options"], json!({"include_usage": true}));
+
+    openai_builder(&server)
+        .stream_usage(false)
+        .build()
+        .generate_text_stream("i".to_string(), "t".to_string())
+        .await
+        .unwrap()
+        .collect::<Vec<_>>()
+        .await;
+    let body = server.last_request().json();
+    assert_eq!(body["stream"], true);
+    assert!(body.get("stream_options").is_none());
+}
+
+#[tokio::test]
+async fn call_options_override_model_defaults_field_by_field() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("a"));
+    server.push(chat_reply("b"));
+    server.push(chat_reply("c"));
+    let model = openai_builder(&server)
+        .default_options(
+            CallOptionsBuilder::new()
+                .temperature(0.7)
+                .max_tokens(100)
+                .stop(vec!["END".to_string()])
+                .seed(1)
+                .build(),
+        )
+        .build();
+    let overrides = CallOptionsBuilder::new()
+        .temperature(0.2)
+        .seed(2)
+        .user("u-1".to_string())
+        .build();
+
+    model
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    let body = server.last_request().json();
+    assert_eq!(body["temperature"], 0.7);
+    assert_eq!(body["max_tokens"], 100);
+    assert_eq!(body["stop"], json!(["END"]));
+    assert_eq!(body["seed"], 1);
+    assert!(body.get("user").is_none());
+
+    model
+        .generate_text_with_options("i".to_string(), "t".to_string(), &overrides)
+        .await
+        .unwrap();
+    let body = server.last_request().json();
+    assert_eq!(body["temperature"], 0.2);
+    assert_eq!(body["max_tokens"], 100);
+    assert_eq!(body["stop"], json!(["END"]));
+    assert_eq!(body["seed"], 2);
+    assert_eq!(body["user"], "u-1");
+
+    // Without defaults or overrides the primitives ask for temperature 0.
+    openai_model(&server)
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    let body = server.last_request().json();
+    assert_eq!(body["temperature"], 0.0);
+    assert!(body.get("max_tokens").is_none());
+}
@@ -0,0 +1,227 @@
+mod common;
+
+use common::{openai_builder, StubResponse, StubServer};
+use llm_primitives::{
+    BudgetBuilder, BudgetLimit, CallOptions, Error, Model, OpenAIBatch, OpenAIBatchRequests,
+    OpenAIBatchStatus, OpenAIModel, OpenAIModelBuilder, Price, PriceTable,
+};
+use serde_json::json;
+
+fn builder(server: &StubServer) -> OpenAIModelBuilder {
+    let mut builder = openai_builder(server);
+    builder
+        .batch_endpoint("/chat/completions".to_string())
+        .price_table(
+            PriceTable::new()
+                .price("gpt-test".to_string(), Price::new(2.0, 4.0))
+                .clone(),
+        );
+    builder
+}
+
+fn model(server: &StubServer) -> OpenAIModel {
+    builder(server).build()
+}
+
+fn batch_object(status: &str, output_file_id: Option<&str>) -> StubResponse {
+    StubResponse::json(json!({
+        "id": "batch-1",
+        "status": status,
+        "output_file_id": output_file_id,
+        "request_counts": {"total": 2, "completed": 2, "failed": 0},
+        "created_at": 100,
+        "completed_at": output_file_id.map(|_| 160),
+    }))
+}
+
+fn output_line(custom_id: &str, label: &str) -> String {
+    json!({
+        "custom_id": custom_id,
+        "response": {
+            "status_code": 200,
+            "request_id": format!("req-{}", custom_id),
+            "body": {
+                "choices": [{"message": {
+                    "role": "assistant",
+                    "content": format!("{{\"classification\": \"{}\"}}", label),
+                }}],
+                "model": "gpt-test",
+                "usage": {"prompt_tokens": 1_000_000, "completion_tokens": 0},
+            },
+        },
+    })
+    .to_string()
+}
+
+fn classify(requests: &mut OpenAIBatchRequests<'_, usize>, custom_id: &str) -> Result<(), Error> {
+    requests.classify(
+        custom_id.to_string(),
+        "Pick the fruit.".to_string(),
+        "banana".to_string(),
+        vec!["car".to_string(), "fruit".to_string()],
+        &CallOptions::default(),
+    )?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn duplicate_custom_id_is_an_error() {
+    let server = StubServer::start().await;
+    let model = model(&server);
+    let mut requests = model.batch_requests::<usize>();
+    classify(&mut requests, "a").unwrap();
+    let error = classify(&mut requests, "a").unwrap_err();
+    assert!(matches!(error, Error::BatchFailed { .. }), "{:?}", error);
+    assert_eq!(requests.len(), 1);
+}
+
+#[tokio::test]
+async fn submits_polls_and_decodes_results() {
+    let server = StubServer::start().await;
+    let model = model(&server);
+    let mut requests = model.batch_requests::<usize>();
+    classify(&mut requests, "a").unwrap();
+    classify(&mut requests, "b").unwrap();
+
+    server.push(StubResponse::json(json!({"id": "file-in"})));
+    server.push(batch_object("validating", None));
+    let mut batch = requests.submit().await.unwrap();
+    assert_eq!(batch.id(), "batch-1");
+    assert_eq!(batch.status(), OpenAIBatchStatus::Validating);
+
+    let requests = server.requests();
+    assert_eq!(requests[0].path, "/files");
+    let upload = requests[0].text();
+    assert!(upload.contains("\"custom_id\":\"a\""));
+    assert!(upload.contains("\"url\":\"/chat/completions\""));
+    assert_eq!(requests[1].path, "/batches");
+    assert_eq!(
+        requests[1].json(),
+        json!({
+            "input_file_id": "file-in",
+            "endpoint": "/chat/completions",
+            "completion_window": "24h",
+        })
+    );
+
+    server.push(batch_object("completed", Some("file-out")));
+    model.refresh_batch(&mut batch).await.unwrap();
+    assert_eq!(batch.status(), OpenAIBatchStatus::Completed);
+    assert_eq!(server.last_request().path, "/batches/batch-1");
+
+    let output = format!("{}\n{}\n", output_line("a", "B"), output_line("b", "A"));
+    server.push(StubResponse::raw(200, "application/jsonl", output.clone()));
+    server.push(StubResponse::raw(200, "application/jsonl", output));
+    let results = model.batch_results(&mut batch).await.unwrap();
+    assert_eq!(server.last_request().path, "/files/file-out/content");
+    let a = results["a"].as_ref().unwrap();
+    assert_eq!(a.value, 1);
+    assert_eq!(a.cost, Some(1.0));
+    assert_eq!(a.request_id.as_deref(), Some("req-a"));
+    assert_eq!(results["b"].as_ref().unwrap().value, 0);
+
+    // Fetching the results again does not count their cost twice.
+    model.batch_results(&mut batch).await.unwrap();
+    let totals = model.cost_tracker().totals();
+    assert_eq!(totals.requests, 2);
+    assert_eq!(totals.cost, 2.0);
+}
+
+/// Submits classifications `a` and `b` and refreshes the batch once it has
+/// completed with results in `file-out` and failures in `file-err`.
+async fn completed_batch(server: &StubServer, model: &OpenAIModel) -> OpenAIBatch<usize> {
+    let mut requests = model.batch_requests::<usize>();
+    classify(&mut requests, "a").unwrap();
+    classify(&mut requests, "b").unwrap();
+    server.push(StubResponse::json(json!({"id": "file-in"})));
+    server.push(batch_object("validating", None));
+    let mut batch = requests.submit().await.unwrap();
+    server.push(StubResponse::json(json!({
+        "id": "batch-1",
+        "status": "completed",
+        "output_file_id": "file-out",
+        "error_file_id": "file-err",
+        "created_at": 100,
+        "completed_at": 160,
+    })));
+    model.refresh_batch(&mut batch).await.unwrap();
+    batch
+}
+
+fn error_line(custom_id: &str) -> String {
+    json!({
+        "custom_id": custom_id,
+        "response": null,
+        "error": {"code": "batch_expired", "message": "not run in time"},
+    })
+    .to_string()
+}
+
+#[tokio::test]
+async fn a_failed_fetch_records_no_cost_until_one_succeeds() {
+    let server = StubServer::start().await;
+    let model = model(&server);
+    let mut batch = completed_batch(&server, &model).await;
+
+    server.push(StubResponse::raw(
+        200,
+        "application/jsonl",
+        output_line("a", "B"),
+    ));
+    server.push(StubResponse::status(
+        404,
+        json!({"error": {"message": "gone"}}),
+    ));
+    let error = model.batch_results(&mut batch).await.unwrap_err();
+    assert!(
+        matches!(error, Error::Http { status: 404, .. }),
+        "{:?}",
+        error
+    );
+    assert_eq!(model.cost_tracker().totals().requests, 0);
+
+    server.push(StubResponse::raw(
+        200,
+        "application/jsonl",
+        output_line("a", "B"),
+    ));
+    server.push(StubResponse::raw(200, "application/jsonl", error_line("b")));
+    let results = model.batch_results(&mut batch).await.unwrap();
+    assert_eq!(results["a"].as_ref().unwrap().value, 1);
+    let error = results["b"].as_ref().unwrap_err();
+    assert!(
+        matches!(error, Error::BatchFailed { message } if message == "batch_expired: not run in time"),
+        "{:?}",
+        error
+    );
+    let totals = model.cost_tracker().totals();
+    assert_eq!(totals.requests, 1);
+    assert_eq!(totals.cost, 1.0);
+}
+
+#[tokio::test]
+async fn batches_are_charged_to_the_budget() {
+    let server = StubServer::start().await;
+    let budget = BudgetBuilder::new().max_requests(3).max_tokens(10).build();
+    let model = builder(&server).budget(budget.clone()).build();
+    let mut batch = completed_batch(&server, &model).await;
+    assert_eq!(budget.remaining().requests, Some(1));
+
+    // Two more requests do not fit, so nothing is uploaded.
+    let uploads = server.requests().len();
+    let mut requests = model.batch_requests::<usize>();
+    classify(&mut requests, "c").unwrap();
+    classify(&mut requests, "d").unwrap();
+    let Err(Error::BudgetExceeded { limit, .. }) = requests.submit().await else {
+        panic!("expected BudgetExceeded");
+    };
+    assert_eq!(limit, BudgetLimit::Requests);
+    assert_eq!(server.requests().len(), uploads);
+    assert_eq!(budget.remaining().requests, Some(1));
+
+    let output = format!("{}\n{}\n", output_line("a", "B"), output_line("b", "A"));
+    server.push(StubResponse::raw(200, "application/jsonl", output));
+    server.push(StubResponse::raw(200, "application/jsonl", ""));
+    model.batch_results(&mut batch).await.unwrap();
+    assert_eq!(budget.remaining().tokens, Some(0));
+}
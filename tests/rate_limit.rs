@@ -0,0 +1,135 @@
+mod common;
+
+use common::{chat_reply, chat_reply_with_usage, openai_builder, StubResponse, StubServer};
+use llm_primitives::{
+    CallOptionsBuilder, Error, Model, OpenAIModel, RateLimiter, RateLimiterBuilder,
+};
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+fn model(server: &StubServer, rate_limiter: RateLimiter) -> OpenAIModel {
+    openai_builder(server).rate_limiter(rate_limiter).build()
+}
+
+/// Time taken by a call that asks for up to `max_tokens` tokens. With the
+/// two one-character messages its prompt is estimated at 10 tokens.
+async fn timed_call(model: &OpenAIModel, max_tokens: u32) -> Duration {
+    let options = CallOptionsBuilder::new().max_tokens(max_tokens).build();
+    let started = Instant::now();
+    model
+        .generate_text_with_options("i".to_string(), "t".to_string(), &options)
+        .await
+        .unwrap();
+    started.elapsed()
+}
+
+#[tokio::test]
+async fn zero_limits_still_let_the_first_request_through() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("ok"));
+    let model = openai_builder(&server)
+        .rate_limiter(
+            RateLimiterBuilder::new()
+                .requests_per_minute(0)
+                .tokens_per_minute(0)
+                .build(),
+        )
+        .build();
+    let text = tokio::time::timeout(
+        Duration::from_secs(5),
+        model.generate_text("i".to_string(), "t".to_string()),
+    )
+    .await
+    .expect("the request waited on an empty bucket")
+    .unwrap();
+    assert_eq!(text, "ok");
+}
+
+#[tokio::test]
+async fn requests_wait_for_the_token_bucket_to_refill() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("ok"));
+    server.push(chat_reply("ok"));
+    // 100 tokens a second; each call is estimated at 3050 tokens.
+    let model = model(
+        &server,
+        RateLimiterBuilder::new().tokens_per_minute(6000).build(),
+    );
+
+    assert!(timed_call(&model, 3040).await < Duration::from_millis(500));
+    // 2950 tokens are left, so the second call waits about a second.
+    let waited = timed_call(&model, 3040).await;
+    assert!(waited >= Duration::from_millis(800), "{:?}", waited);
+}
+
+#[tokio::test]
+async fn reported_usage_gives_back_the_unused_estimate() {
+    let server = StubServer::start().await;
+    server.push(chat_reply_with_usage(5, 5));
+    server.push(chat_reply_with_usage(5, 5));
+    let model = model(
+        &server,
+        RateLimiterBuilder::new().tokens_per_minute(6000).build(),
+    );
+
+    timed_call(&model, 3040).await;
+    let waited = timed_call(&model, 3040).await;
+    assert!(waited < Duration::from_millis(500), "{:?}", waited);
+}
+
+#[tokio::test]
+async fn remaining_requests_reported_by_the_server_lower_the_bucket() {
+    let server = StubServer::start().await;
+    server.push(chat_reply("ok").header("x-ratelimit-remaining-requests", "0"));
+    server.push(chat_reply("ok"));
+    // One request every 200ms, with a full minute's worth allowed at once.
+    let model = model(
+        &server,
+        RateLimiterBuilder::new().requests_per_minute(300).build(),
+    );
+
+    assert!(timed_call(&model, 1).await < Duration::from_millis(150));
+    let waited = timed_call(&model, 1).await;
+    assert!(waited >= Duration::from_millis(150), "{:?}", waited);
+}
+
+#[tokio::test]
+async fn an_exhausted_limit_pauses_requests_until_it_resets() {
+    let server = StubServer::start().await;
+    server.push(
+        chat_reply("ok")
+            .header("x-ratelimit-remaining-tokens", "0")
+            .header("x-ratelimit-reset-tokens", "400ms"),
+    );
+    server.push(chat_reply("ok"));
+    let model = model(
+        &server,
+        RateLimiterBuilder::new().requests_per_minute(1000).build(),
+    );
+
+    timed_call(&model, 1).await;
+    let waited = timed_call(&model, 1).await;
+    assert!(waited >= Duration::from_millis(350), "{:?}", waited);
+}
+
+#[tokio::test]
+async fn a_rate_limited_response_pauses_requests_for_its_retry_after() {
+    let server = StubServer::start().await;
+    server.push(
+        StubResponse::status(429, json!({"error": {"message": "slow down"}}))
+            .header("retry-after", "1"),
+    );
+    server.push(chat_reply("ok"));
+    let model = model(
+        &server,
+        RateLimiterBuilder::new().requests_per_minute(1000).build(),
+    );
+
+    let error = model
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap_err();
+    assert!(matches!(error, Error::RateLimited { .. }), "{:?}", error);
+    let waited = timed_call(&model, 1).await;
+    assert!(waited >= Duration::from_millis(900), "{:?}", waited);
+}
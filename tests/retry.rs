@@ -0,0 +1,106 @@
+mod common;
+
+use common::{chat_reply, openai_builder, StubResponse, StubServer};
+use llm_primitives::{Error, Model, OpenAIModel, RetryPolicyBuilder};
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+fn model(server: &StubServer) -> OpenAIModel {
+    openai_builder(server)
+        .retry_policy(
+            RetryPolicyBuilder::new()
+                .max_attempts(3)
+                .max_backoff(Duration::from_secs(1))
+                .build(),
+        )
+        .build()
+}
+
+fn rate_limited() -> StubResponse {
+    StubResponse::status(429, json!({"error": {"message": "slow down"}}))
+}
+
+#[tokio::test]
+async fn retries_after_the_delay_the_server_asks_for() {
+    let server = StubServer::start().await;
+    server.push(rate_limited().header("retry-after-ms", "20"));
+    server.push(chat_reply("ok"));
+    let text = model(&server)
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "ok");
+    assert_eq!(server.requests().len(), 2);
+}
+
+#[tokio::test]
+async fn gives_up_when_the_server_delay_exceeds_max_backoff() {
+    let server = StubServer::start().await;
+    server.push(
+        rate_limited()
+            .header("x-ratelimit-remaining-tokens", "0")
+            .header("x-ratelimit-reset-tokens", "6m0s"),
+    );
+    let started = Instant::now();
+    let error = model(&server)
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap_err();
+    assert!(started.elapsed() < Duration::from_secs(1));
+    assert!(
+        matches!(
+            error,
+            Error::RateLimited {
+                retry_after: Some(delay),
+                ..
+            } if delay == Duration::from_secs(360)
+        ),
+        "{:?}",
+        error
+    );
+    assert_eq!(server.requests().len(), 1);
+}
+
+#[tokio::test]
+async fn ignores_delays_that_are_not_durations() {
+    let server = StubServer::start().await;
+    server.push(rate_limited().header("retry-after", "-1"));
+    server.push(rate_limited().header("retry-after", "1e300"));
+    server.push(chat_reply("ok"));
+    let text = model(&server)
+        .generate_text("i".to_string(), "t".to_string())
+        .await
+        .unwrap();
+    assert_eq!(text, "ok");
+}
+
+#[tokio::test]
+async fn an_unlimited_max_backoff_does_not_overflow() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::status(
+        503,
+        json!({"error": {"message": "overloaded"}}),
+    ));
+    let delays = Arc::new(Mutex::new(vec![]));
+    let sink = delays.clone();
+    let model = openai_builder(&server)
+        .retry_policy(
+            RetryPolicyBuilder::new()
+                .max_attempts(2)
+                .initial_backoff(Duration::MAX)
+                .max_backoff(Duration::MAX)
+                .jitter(false)
+                .on_attempt(move |attempt| sink.lock().unwrap().push(attempt.retry_in))
+                .build(),
+        )
+        .build();
+
+    // The call waits out the backoff instead of panicking while computing it.
+    let call = model.generate_text("i".to_string(), "t".to_string());
+    assert!(tokio::time::timeout(Duration::from_millis(100), call)
+        .await
+        .is_err());
+    assert_eq!(*delays.lock().unwrap(), vec![Some(Duration::MAX)]);
+    assert_eq!(server.requests().len(), 1);
+}
@@ -0,0 +1,259 @@
+mod common;
+
+use common::{chat_reply, chat_reply_with_usage, chat_stream, openai_builder, openai_model};
+use common::{StubResponse, StubServer};
+use futures::StreamExt;
+use llm_primitives::{
+    CallOptions, Error, MockModel, Model, Price, PriceTable, Primitive, Response, RouteRule,
+    RouterModel, RouterModelBuilder, Usage,
+};
+use serde_json::json;
+use std::time::Duration;
+
+/// A mock that answers every primitive the tests below call.
+fn answering() -> MockModel {
+    let model = MockModel::new();
+    model
+        .on_classify(|_| Ok(0))
+        .on_binary_classify(|_| Ok(true))
+        .on_generate_text(|_| Ok("ok".to_string()))
+        .on_parse(|_| Ok(json!({})));
+    model
+}
+
+fn served_by<T>(response: Response<T>) -> String {
+    response.served_by.unwrap()
+}
+
+async fn generate(router: &RouterModel, text: &str) -> String {
+    let response = router
+        .generate_text_with_options("i".to_string(), text.to_string(), &Default::default())
+        .await
+        .unwrap();
+    served_by(response)
+}
+
+async fn classify(router: &RouterModel, choices: usize) -> String {
+    let choices = (0..choices).map(|choice| choice.to_string()).collect();
+    let response = router
+        .classify_with_options(
+            "i".to_string(),
+            "t".to_string(),
+            choices,
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+    served_by(response)
+}
+
+#[tokio::test]
+async fn short_texts_take_the_small_route() {
+    let small = StubServer::start().await;
+    let large = StubServer::start().await;
+    small.push(chat_reply("from small"));
+    large.push(chat_reply("from large"));
+    let router = RouterModelBuilder::new("large".to_string(), openai_model(&large))
+        .route(
+            "small".to_string(),
+            RouteRule::max_tokens(10),
+            openai_model(&small),
+        )
+        .build();
+    let options = CallOptions::default();
+    let ask = |text: String| router.generate_text_with_options("i".to_string(), text, &options);
+
+    // 40 characters of instruction and text are about 10 tokens.
+    let response = ask("t".repeat(39)).await.unwrap();
+    assert_eq!(response.value, "from small");
+    assert_eq!(response.served_by.as_deref(), Some("small"));
+
+    let response = ask("t".repeat(41)).await.unwrap();
+    assert_eq!(response.value, "from large");
+    assert_eq!(response.served_by.as_deref(), Some("large"));
+}
+
+#[tokio::test]
+async fn long_texts_take_the_long_route_counting_characters_not_bytes() {
+    let router = RouterModelBuilder::new("short".to_string(), answering())
+        .route("long".to_string(), RouteRule::min_tokens(10), answering())
+        .build();
+
+    assert_eq!(generate(&router, &"t".repeat(39)).await, "short");
+    assert_eq!(generate(&router, &"t".repeat(40)).await, "long");
+    // 31 characters, but 91 bytes.
+    assert_eq!(generate(&router, &"語".repeat(30)).await, "short");
+    assert_eq!(generate(&router, &"🙂".repeat(30)).await, "short");
+}
+
+#[tokio::test]
+async fn calls_route_by_primitive() {
+    let cheap = answering();
+    let expensive = answering();
+    let router = RouterModelBuilder::new("expensive".to_string(), expensive.clone())
+        .route(
+            "cheap".to_string(),
+            RouteRule::primitive(Primitive::BinaryClassify),
+            cheap.clone(),
+        )
+        .build();
+
+    let response = router
+        .binary_classify_with_options("i".to_string(), "t".to_string(), &Default::default())
+        .await
+        .unwrap();
+    assert_eq!(served_by(response), "cheap");
+    let response = router
+        .parse_value_with_options("t".to_string(), json!({}), &Default::default())
+        .await
+        .unwrap();
+    assert_eq!(served_by(response), "expensive");
+    assert_eq!(generate(&router, "t").await, "expensive");
+    assert_eq!((cheap.call_count(), expensive.call_count()), (1, 2));
+}
+
+#[tokio::test]
+async fn classify_calls_route_by_number_of_choices() {
+    let router = RouterModelBuilder::new("default".to_string(), answering())
+        .route("few".to_string(), RouteRule::max_choices(2), answering())
+        .route("many".to_string(), RouteRule::min_choices(3), answering())
+        .build();
+
+    assert_eq!(classify(&router, 2).await, "few");
+    assert_eq!(classify(&router, 3).await, "default");
+    assert_eq!(classify(&router, 4).await, "many");
+    // Calls without choices match neither rule.
+    assert_eq!(generate(&router, "t").await, "default");
+}
+
+#[tokio::test]
+async fn custom_rules_see_the_whole_request_and_combine() {
+    let router = RouterModelBuilder::new("default".to_string(), answering())
+        .route(
+            "urgent text".to_string(),
+            RouteRule::custom(|request| request.text.starts_with("urgent"))
+                .and(RouteRule::primitive(Primitive::GenerateText)),
+            answering(),
+        )
+        .route(
+            "exact".to_string(),
+            RouteRule::custom(|request| request.instruction == Some("Be exact."))
+                .or(RouteRule::primitive(Primitive::Classify)),
+            answering(),
+        )
+        .build();
+
+    assert_eq!(generate(&router, "urgent: reply").await, "urgent text");
+    assert_eq!(generate(&router, "whenever").await, "default");
+    let response = router
+        .binary_classify_with_options(
+            "Be exact.".to_string(),
+            "urgent".to_string(),
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(served_by(response), "exact");
+    assert_eq!(classify(&router, 2).await, "exact");
+}
+
+#[tokio::test]
+async fn the_first_matching_route_serves_and_the_default_takes_the_rest() {
+    let first = answering();
+    let second = answering();
+    let default = answering();
+    let router = RouterModelBuilder::new("default".to_string(), default.clone())
+        .route(
+            "first".to_string(),
+            RouteRule::max_tokens(10),
+            first.clone(),
+        )
+        .route(
+            "second".to_string(),
+            RouteRule::max_tokens(100),
+            second.clone(),
+        )
+        .build();
+
+    assert_eq!(generate(&router, "t").await, "first");
+    assert_eq!(generate(&router, &"t".repeat(100)).await, "second");
+    assert_eq!(generate(&router, &"t".repeat(1000)).await, "default");
+    assert_eq!(
+        (
+            first.call_count(),
+            second.call_count(),
+            default.call_count()
+        ),
+        (1, 1, 1)
+    );
+}
+
+#[tokio::test]
+async fn routes_accumulate_calls_failures_usage_cost_and_latency() {
+    let priced = StubServer::start().await;
+    priced.push(chat_reply_with_usage(1_000_000, 100_000));
+    priced.push(StubResponse::status(
+        503,
+        json!({"error": {"message": "overloaded"}}),
+    ));
+    priced.push(chat_stream("streamed"));
+    let mut prices = PriceTable::new();
+    prices.price("gpt-test".to_string(), Price::new(2.0, 4.0));
+    let slow = MockModel::new();
+    slow.on_generate_text(|_| Err(Error::Timeout))
+        .latency(Duration::from_millis(20));
+    let router = RouterModelBuilder::new(
+        "priced".to_string(),
+        openai_builder(&priced).price_table(prices).build(),
+    )
+    .route(
+        "slow".to_string(),
+        RouteRule::custom(|request| request.text == "slow"),
+        slow,
+    )
+    .build();
+
+    assert_eq!(generate(&router, "t").await, "priced");
+    assert!(router
+        .generate_text_with_options("i".to_string(), "t".to_string(), &Default::default())
+        .await
+        .is_err());
+    let deltas: Vec<_> = router
+        .generate_text_stream("i".to_string(), "t".to_string())
+        .await
+        .unwrap()
+        .collect()
+        .await;
+    assert!(deltas
+        .iter()
+        .all(|delta| delta.as_ref().unwrap().served_by.as_deref() == Some("priced")));
+    for _ in 0..2 {
+        assert!(router
+            .generate_text_with_options("i".to_string(), "slow".to_string(), &Default::default())
+            .await
+            .is_err());
+    }
+
+    let metrics = router.metrics();
+    let slow = &metrics[0];
+    assert_eq!(slow.route, "slow");
+    assert_eq!((slow.calls, slow.failures), (2, 2));
+    assert_eq!(slow.usage, Usage::default());
+    assert!(slow.latency >= Duration::from_millis(40));
+
+    let priced = &metrics[1];
+    assert_eq!(priced.route, "priced");
+    assert_eq!((priced.calls, priced.failures), (3, 1));
+    assert_eq!(
+        (priced.usage.input_tokens, priced.usage.output_tokens),
+        (1_000_005, 100_002)
+    );
+    // 2.4 for the reply, and 5 and 2 tokens for the stream.
+    let stream_cost = (5.0 * 2.0 + 2.0 * 4.0) / 1_000_000.0;
+    assert!((priced.cost - (2.4 + stream_cost)).abs() < 1e-9);
+    assert!(priced.latency > Duration::ZERO);
+
+    let totals = router.cost_tracker().totals();
+    assert_eq!(totals.usage, priced.usage);
+    assert!((totals.cost - priced.cost).abs() < 1e-9);
+}
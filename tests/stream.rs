@@ -0,0 +1,195 @@
+mod common;
+
+use common::{openai_model, StubResponse, StubServer};
+use futures::StreamExt;
+use llm_primitives::{
+    AnthropicModelBuilder, Error, GeminiModelBuilder, Model, OllamaModelBuilder, TextDelta,
+    TextStream,
+};
+use std::time::Duration;
+
+const SPLIT_DELAY: Duration = Duration::from_millis(20);
+
+async fn collect(stream: TextStream) -> Vec<Result<TextDelta, Error>> {
+    stream.collect().await
+}
+
+/// The text of every delta, or the error the stream ended with.
+fn text(deltas: &[Result<TextDelta, Error>]) -> Result<String, &Error> {
+    let mut text = String::new();
+    for delta in deltas {
+        text.push_str(&delta.as_ref()?.text);
+    }
+    Ok(text)
+}
+
+async fn ask(model: &(impl Model + Sync)) -> Vec<Result<TextDelta, Error>> {
+    collect(
+        model
+            .generate_text_stream("i".to_string(), "t".to_string())
+            .await
+            .unwrap(),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn events_split_across_reads_are_reassembled() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::chunked(
+        "text/event-stream",
+        &[
+            "da",
+            "ta: {\"choices\":[{\"delta\":{\"content\":\"Hel\"},",
+            "\"finish_reason\":null}]}\r\n\r",
+            "\ndata: {\"choices\":[{\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n",
+            "\ndata: [DO",
+            "NE]\n\n",
+        ],
+        SPLIT_DELAY,
+    ));
+    let deltas = ask(&openai_model(&server)).await;
+    assert_eq!(text(&deltas).unwrap(), "Hello");
+    let last = deltas.last().unwrap().as_ref().unwrap();
+    assert_eq!(last.finish_reason.as_deref(), Some("stop"));
+}
+
+#[tokio::test]
+async fn data_lines_of_one_event_are_joined() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        concat!(
+            ": keep-alive comment\n",
+            "event: chunk\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hi\"},\n",
+            "data:\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        ),
+    ));
+    let deltas = ask(&openai_model(&server)).await;
+    assert_eq!(text(&deltas).unwrap(), "Hi");
+    assert_eq!(deltas.len(), 2);
+}
+
+#[tokio::test]
+async fn openai_stream_without_done_is_incomplete() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        "data: {\"choices\":[{\"delta\":{\"content\":\"Once upon\"},\"finish_reason\":null}]}\n\n",
+    ));
+    let deltas = ask(&openai_model(&server)).await;
+    assert_eq!(deltas[0].as_ref().unwrap().text, "Once upon");
+    assert!(
+        matches!(text(&deltas), Err(Error::IncompleteStream)),
+        "{:?}",
+        deltas
+    );
+}
+
+#[tokio::test]
+async fn anthropic_stream_without_message_stop_is_incomplete() {
+    let server = StubServer::start().await;
+    let event = |data: &str| format!("event: x\ndata: {}\n\n", data);
+    let complete = [
+        event(
+            r#"{"type":"message_start","message":{"usage":{"input_tokens":3,"output_tokens":1}}}"#,
+        ),
+        event(
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#,
+        ),
+        event(
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":2}}"#,
+        ),
+        event(r#"{"type":"message_stop"}"#),
+    ];
+    server.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        complete.concat(),
+    ));
+    server.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        complete[..3].concat(),
+    ));
+    let model = AnthropicModelBuilder::new("claude-test".to_string())
+        .api_key("test-key".to_string())
+        .base_url(server.url().to_string())
+        .build();
+
+    let deltas = ask(&model).await;
+    assert_eq!(text(&deltas).unwrap(), "Hi");
+    let last = deltas.last().unwrap().as_ref().unwrap();
+    assert_eq!(last.finish_reason.as_deref(), Some("end_turn"));
+    let deltas = ask(&model).await;
+    assert!(matches!(text(&deltas), Err(Error::IncompleteStream)));
+}
+
+#[tokio::test]
+async fn ollama_lines_split_across_reads_and_missing_done() {
+    let server = StubServer::start().await;
+    server.push(StubResponse::chunked(
+        "application/x-ndjson",
+        &[
+            "{\"message\":{\"role\":\"assistant\",\"content\":\"Hi\"},\"done\":false}\n{\"mess",
+            "age\":{\"role\":\"assistant\",\"content\":\"!\"},\"done\":false}\n\n",
+            "{\"done\":true,\"done_reason\":\"stop\",\"prompt_eval_count\":4,\"eval_count\":2}",
+        ],
+        SPLIT_DELAY,
+    ));
+    server.push(StubResponse::chunked(
+        "application/x-ndjson",
+        &["{\"message\":{\"role\":\"assistant\",\"content\":\"Hi\"},\"done\":false}\n"],
+        Duration::ZERO,
+    ));
+    let model = OllamaModelBuilder::new("llama3".to_string())
+        .base_url(server.url().to_string())
+        .build();
+
+    let deltas = ask(&model).await;
+    assert_eq!(text(&deltas).unwrap(), "Hi!");
+    let usage = deltas.last().unwrap().as_ref().unwrap().usage.unwrap();
+    assert_eq!((usage.input_tokens, usage.output_tokens), (4, 2));
+    let deltas = ask(&model).await;
+    assert!(matches!(text(&deltas), Err(Error::IncompleteStream)));
+}
+
+#[tokio::test]
+async fn gemini_stream_is_complete_once_it_has_a_finish_reason() {
+    let server = StubServer::start().await;
+    let chunk = |text: &str, finish_reason: Option<&str>| {
+        let mut candidate = serde_json::json!({
+            "content": {"role": "model", "parts": [{"text": text}]},
+        });
+        if let Some(finish_reason) = finish_reason {
+            candidate["finishReason"] = finish_reason.into();
+        }
+        format!(
+            "data: {}\r\n\r\n",
+            serde_json::json!({"candidates": [candidate]})
+        )
+    };
+    server.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        chunk("Hi", None) + &chunk("!", Some("STOP")),
+    ));
+    server.push(StubResponse::raw(
+        200,
+        "text/event-stream",
+        chunk("Hi", None),
+    ));
+    let model = GeminiModelBuilder::new("gemini-test".to_string())
+        .api_key("test-key".to_string())
+        .base_url(server.url().to_string())
+        .build();
+
+    let deltas = ask(&model).await;
+    assert_eq!(text(&deltas).unwrap(), "Hi!");
+    let deltas = ask(&model).await;
+    assert!(matches!(text(&deltas), Err(Error::IncompleteStream)));
+}
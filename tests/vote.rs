@@ -0,0 +1,90 @@
+mod common;
+
+use common::{openai_model, StubResponse, StubServer};
+use llm_primitives::{
+    FallbackModelBuilder, MockModel, Model, RouterModelBuilder, VoteOptions, VoteOptionsBuilder,
+};
+use serde_json::json;
+
+fn choices() -> Vec<String> {
+    vec!["car".to_string(), "fruit".to_string()]
+}
+
+fn three_samples() -> VoteOptions {
+    VoteOptionsBuilder::new().samples(3).build()
+}
+
+fn three_completions() -> StubResponse {
+    let choice = |label: &str| {
+        json!({"message": {
+            "role": "assistant",
+            "content": format!("{{\"classification\": \"{}\"}}", label),
+        }})
+    };
+    StubResponse::json(json!({
+        "choices": [choice("B"), choice("B"), choice("A")],
+    }))
+}
+
+#[tokio::test]
+async fn choices_out_of_range_count_as_failed() {
+    let model = MockModel::new();
+    model
+        .queue_classify(Ok(1))
+        .queue_classify(Ok(5))
+        .queue_classify(Ok(1));
+    let response = model
+        .classify_vote(
+            "Pick the fruit.".to_string(),
+            "banana".to_string(),
+            choices(),
+            &three_samples(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.value.choice, 1);
+    assert_eq!(response.value.votes, vec![0, 2]);
+    assert_eq!(response.value.failed, 1);
+}
+
+#[tokio::test]
+async fn fallback_asks_for_every_sample_in_one_request() {
+    let server = StubServer::start().await;
+    server.push(three_completions());
+    let fallback = FallbackModelBuilder::new()
+        .model("openai".to_string(), openai_model(&server))
+        .build();
+    let response = fallback
+        .classify_vote(
+            "Pick the fruit.".to_string(),
+            "banana".to_string(),
+            choices(),
+            &three_samples(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.value.votes, vec![1, 2]);
+    assert_eq!(response.served_by.as_deref(), Some("openai"));
+    assert_eq!(server.requests().len(), 1);
+    assert_eq!(server.last_request().json()["n"], 3);
+}
+
+#[tokio::test]
+async fn router_asks_for_every_sample_in_one_request() {
+    let server = StubServer::start().await;
+    server.push(three_completions());
+    let router = RouterModelBuilder::new("openai".to_string(), openai_model(&server)).build();
+    let response = router
+        .classify_vote(
+            "Pick the fruit.".to_string(),
+            "banana".to_string(),
+            choices(),
+            &three_samples(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.value.choice, 1);
+    assert_eq!(server.requests().len(), 1);
+    assert_eq!(server.last_request().json()["n"], 3);
+    assert_eq!(router.metrics()[0].calls, 1);
+}